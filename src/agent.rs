@@ -0,0 +1,292 @@
+/*
+        *** CLIPASS AGENT PROTOCOL ***
+*****************************************
+The agent keeps one unlocked vault in memory and listens on a Unix
+domain socket only reachable by its user (0600 socket, peer uid checked
+on Linux). Its directory is created 0700, an existing one must belong to
+the user and not be writable by others, it is never chmod-ed.
+
+One request per connection, each message is a single JSON line:
+
+  request  : {"vault": "/abs/vault/path", "args": ["get", "github"], "stdin": null}
+  response : {"ok": true, "output": "...", "error": null, "code": 0}
+             {"ok": false, "output": "", "error": "unfindable entry x", "code": 3}
+
+  - vault   : canonical path of the vault, must match the agent vault
+  - args    : a clipass command split in arguments, as on the command line
+  - stdin   : value for `set <id> [field] --stdin`
+  - code    : process exit code, see ClipassError::exit_code, 5 meaning
+              the agent holds another vault or does not run this command,
+              the client then unlocks the vault itself
+
+Besides vault commands, the agent understands ["status"] and ["lock"],
+for which the vault is not checked.
+The vault is loaded again when its file was saved by someone else, with
+the data key held by the agent: updates never overwrite another session.
+Locking, or staying idle longer than the lock timeout, drops the vault
+and its key and stops the agent.
+****************************************
+*/
+
+use std::env;
+use std::fs;
+use std::fs::DirBuilder;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+use crate::clipass::Clipass;
+use crate::command::Command;
+use crate::error::ClipassError;
+use crate::secret::SecretString;
+
+pub const SOCKET_ENV: &str = "CLIPASS_AGENT_SOCK";
+pub const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+const IO_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_REQUEST_SIZE: u64 = 1 << 20;
+const AGENT_ERROR_CODE: u8 = 5;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AgentRequest {
+    pub vault: String,
+    pub args: Vec<String>,
+    pub stdin: Option<SecretString>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AgentResponse {
+    pub ok: bool,
+    pub output: String,
+    pub error: Option<String>,
+    pub code: u8,
+}
+
+impl AgentResponse {
+    fn success(output: String) -> Self {
+        Self { ok: true, output, error: None, code: 0 }
+    }
+
+    fn failure(e: &ClipassError) -> Self {
+        Self { ok: false, output: String::new(), error: Some(e.to_string()), code: e.exit_code() }
+    }
+}
+
+// $CLIPASS_AGENT_SOCK, else $XDG_RUNTIME_DIR/clipass/agent.sock, else /tmp/clipass-<uid>/agent.sock
+pub fn socket_path() -> PathBuf {
+    if let Some(path) = env::var_os(SOCKET_ENV) {
+        return PathBuf::from(path);
+    }
+    let dir = match env::var_os("XDG_RUNTIME_DIR") {
+        Some(runtime_dir) => PathBuf::from(runtime_dir).join("clipass"),
+        None => env::temp_dir().join(format!("clipass-{}", current_uid())),
+    };
+    dir.join("agent.sock")
+}
+
+pub struct Agent {
+    clipass: Clipass,
+    vault_path: String,
+    socket_path: PathBuf,
+    lock_timeout: Duration,
+}
+
+impl Agent {
+    pub fn new(clipass: Clipass, vault_path: &str, socket_path: &Path, lock_timeout: Duration)
+        -> Result<Self, ClipassError>
+    {
+        Ok(Self {
+            clipass,
+            vault_path: canonical_path(vault_path)?,
+            socket_path: socket_path.to_path_buf(),
+            lock_timeout,
+        })
+    }
+
+    // Serve requests until locked or idle for longer than the lock timeout
+    pub fn serve(mut self) -> Result<(), ClipassError> {
+        let listener = bind_private(&self.socket_path)?;
+        listener.set_nonblocking(true)?;
+        let mut last_used = Instant::now();
+        let res = loop {
+            if last_used.elapsed() >= self.lock_timeout {
+                break Ok(());
+            }
+            let stream = match listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    thread::sleep(POLL_INTERVAL);
+                    continue;
+                },
+                Err(e) => break Err(e.into()),
+            };
+            last_used = Instant::now();
+            match self.handle(stream) {
+                Ok(true) => continue,
+                Ok(false) => break Ok(()),
+                Err(e) => eprintln!("agent: {e}"),
+            }
+        };
+        let _ = fs::remove_file(&self.socket_path);
+        self.clipass.wait_clipboard();
+        res
+    }
+
+    // Returns false once the agent is asked to lock
+    fn handle(&mut self, stream: UnixStream) -> Result<bool, ClipassError> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(IO_TIMEOUT))?;
+        if peer_uid(&stream)? != current_uid() {
+            return Err(ClipassError::Io("connection from another user refused".to_string()));
+        }
+        let request: AgentRequest = read_message(&stream)?;
+        let keep_running = request.args.first().map(String::as_str) != Some("lock");
+        let response = match self.dispatch(request) {
+            Ok(output) => AgentResponse::success(output),
+            Err(e) => AgentResponse::failure(&e),
+        };
+        write_message(&stream, &response)?;
+        Ok(keep_running)
+    }
+
+    fn dispatch(&mut self, request: AgentRequest) -> Result<String, ClipassError> {
+        match request.args.first().map(String::as_str) {
+            Some("status") => return Ok(format!("unlocked {}", self.vault_path)),
+            Some("lock") => return Ok("locked".to_string()),
+            _ => {},
+        }
+        if request.vault != self.vault_path {
+            return Err(ClipassError::AgentError(format!("agent holds {}", self.vault_path)));
+        }
+        let command = Command::from_args(request.args.iter().map(String::as_str))?;
+        // Saved by an interactive session or a direct run meanwhile
+        self.clipass.reload_if_changed()?;
+        let output = match command {
+            Command::Set(id, field, true) => {
+                let value = request.stdin
+                    .ok_or(ClipassError::Input("missing value for 'set --stdin'".to_string()))?;
+                self.clipass.set_value(&id, &field, value.expose())?
+            },
+            Command::Get(..) | Command::List(_) | Command::Find(_) | Command::Copy(..) | Command::Delete(_)
+            | Command::Generate(_) | Command::Totp(_) | Command::Hotp(_) => self.clipass.run(command)?,
+            // The client runs it itself
+            _ => return Err(ClipassError::AgentError("not available through the agent".to_string())),
+        };
+        if self.clipass.is_updated() {
+            self.clipass.save()?;
+        }
+        Ok(output)
+    }
+}
+
+pub struct AgentClient {
+    socket_path: PathBuf,
+}
+
+impl AgentClient {
+    pub fn new(socket_path: &Path) -> Self {
+        Self { socket_path: socket_path.to_path_buf() }
+    }
+
+    pub fn request(&self, request: &AgentRequest) -> Result<AgentResponse, ClipassError> {
+        let stream = UnixStream::connect(&self.socket_path)?;
+        stream.set_read_timeout(Some(IO_TIMEOUT))?;
+        write_message(&stream, request)?;
+        read_message(&stream)
+    }
+
+    // Run a command for `vault_path`, None when no agent holds this vault
+    pub fn run(&self, vault_path: &str, args: &[String], stdin: Option<SecretString>)
+        -> Option<AgentResponse>
+    {
+        let request = AgentRequest { vault: canonical_path(vault_path).ok()?, args: args.to_vec(), stdin };
+        let response = self.request(&request).ok()?;
+        match response.code {
+            AGENT_ERROR_CODE => None,
+            _ => Some(response),
+        }
+    }
+}
+
+pub fn canonical_path(path: &str) -> Result<String, ClipassError> {
+    Ok(fs::canonicalize(path)?.to_string_lossy().to_string())
+}
+
+fn bind_private(socket_path: &Path) -> Result<UnixListener, ClipassError> {
+    if let Some(dir) = socket_path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        private_dir(dir)?;
+    }
+    if UnixStream::connect(socket_path).is_ok() {
+        return Err(ClipassError::Io(format!("an agent already listens on {}", socket_path.display())));
+    }
+    // Stale socket from a previous agent
+    let _ = fs::remove_file(socket_path);
+    let listener = UnixListener::bind(socket_path)?;
+    fs::set_permissions(socket_path, fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+// Create the socket directory 0700, or check an existing one is private to this user
+fn private_dir(dir: &Path) -> Result<(), ClipassError> {
+    match DirBuilder::new().mode(0o700).create(dir) {
+        Ok(()) => return Ok(()),
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => {},
+        Err(e) => return Err(ClipassError::Io(format!("{}: {e}", dir.display()))),
+    }
+    let metadata = fs::symlink_metadata(dir)?;
+    if !metadata.is_dir() || metadata.uid() != current_uid() || metadata.mode() & 0o022 != 0 {
+        return Err(ClipassError::Io(format!(
+            "{} must be a directory owned by this user and not writable by others", dir.display())));
+    }
+    Ok(())
+}
+
+fn read_message<T: for<'de> Deserialize<'de>>(stream: &UnixStream) -> Result<T, ClipassError> {
+    // Requests and responses carry secrets, both lines are wiped
+    let mut line = Zeroizing::new(Vec::new());
+    BufReader::new(std::io::Read::take(stream, MAX_REQUEST_SIZE)).read_until(b'\n', &mut line)?;
+    Ok(serde_json::from_slice(&line)?)
+}
+
+fn write_message<T: Serialize>(mut stream: &UnixStream, message: &T) -> Result<(), ClipassError> {
+    let mut line = Zeroizing::new(serde_json::to_vec(message)?);
+    line.push(b'\n');
+    stream.write_all(&line)?;
+    Ok(())
+}
+
+fn current_uid() -> u32 {
+    // SAFETY: geteuid never fails
+    unsafe { libc::geteuid() }
+}
+
+#[cfg(target_os = "linux")]
+fn peer_uid(stream: &UnixStream) -> Result<u32, ClipassError> {
+    use std::os::fd::AsRawFd;
+    let mut cred = libc::ucred { pid: 0, uid: u32::MAX, gid: u32::MAX };
+    let mut len = size_of::<libc::ucred>() as libc::socklen_t;
+    // SAFETY: cred and len are valid for writes of the advertised size
+    let res = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if res != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(cred.uid)
+}
+
+// Elsewhere the 0700 socket directory is the only barrier
+#[cfg(not(target_os = "linux"))]
+fn peer_uid(_stream: &UnixStream) -> Result<u32, ClipassError> {
+    Ok(current_uid())
+}
@@ -1,46 +1,156 @@
-use std::path::Path;
+use std::env;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use chrono::{DateTime, Local};
 use rpassword;
 use rpassword::prompt_password;
+use crate::clipboard::{detect_backend, Clipboard};
 use crate::command::Command;
+use crate::config::Config;
+use crate::crypto;
+use crate::crypto::{Cipher, KdfParams, Key, Keyfile};
 use crate::error::ClipassError;
-use crate::utils::input_read;
-use crate::vault::vault::Vault;
+use crate::formats;
+use crate::formats::{ExportArgs, ExportFormat, ImportArgs};
+use crate::formats::{bundle, csv, pass};
+use crate::hardening;
+use crate::generator::{GenerateArgs, PassphrasePolicy, PasswordPolicy};
+use crate::search;
+use crate::secret::SecretString;
+use crate::search::Filter;
+use crate::utils;
+use crate::utils::{input_read, read_stdin_value};
+use crate::vault::entry::{Entry, FIELD_NOTES, FIELD_PASSWORD, FIELD_URL, FIELD_USERNAME};
+use crate::vault::backup;
+use crate::vault::key_slot::{Credential, KeySlot, SlotKind};
+use crate::vault::recovery;
+use crate::vault::shamir;
+use crate::vault::shamir::Share;
+use crate::vault::vault::{LockedVault, Vault};
 
-const CLIPASS_VERSION: &str = "0.3.0-alpha";
+pub const CLIPASS_VERSION: &str = "0.3.0-alpha";
+// Master password source for scripts and CI jobs without a terminal
+pub const PASSWORD_ENV: &str = "CLIPASS_PASSWORD";
+// Unlock time targeted by kdf-bench
+const DEFAULT_UNLOCK_MS: u64 = 1000;
 
 pub struct Clipass {
     cli_on: bool,
-    vault: Vault,
+    vault: Option<Vault>,       // None once locked
+    locked: Option<LockedVault>,
     path: String,
+    password_policy: PasswordPolicy,
+    passphrase_policy: PassphrasePolicy,
+    clipboard: Clipboard,
+    auto_lock: Option<Duration>,
+    keyfile: Option<PathBuf>,
+    unlocked_with_key: bool, // from key shares, unlocked again with them
+    file_stamp: Option<FileStamp>, // of the vault file when loaded or last saved
+}
+
+// Tells whether the vault file was written by someone else
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct FileStamp {
+    modified: SystemTime,
+    len: u64,
+    inode: u64,
+}
+
+fn file_stamp(path: &str) -> Option<FileStamp> {
+    use std::os::unix::fs::MetadataExt;
+    let metadata = fs::metadata(path).ok()?;
+    Some(FileStamp { modified: metadata.modified().ok()?, len: metadata.len(), inode: metadata.ino() })
 }
 
 impl Clipass {
-    pub fn new(path: &str) ->  Result<Self, ClipassError> {
-        println!("clipass v{CLIPASS_VERSION}");
-        let vault: Vault;
-        let pass: String = prompt_password("password: ")?;
-        if Path::new(&path).exists() {
-            vault = Vault::load_from_file(pass.as_str(), path)?;
+    pub fn new(path: &str, config: &Config) ->  Result<Self, ClipassError> {
+        hardening::harden_process();
+        let pass = read_master_password()?;
+        let keyfile = config.load_keyfile()?;
+        let credential = Credential::with_keyfile(pass.expose(), keyfile.as_ref());
+        let vault = if Path::new(&path).exists() {
+            Vault::load_with_credential(&credential, path)?
         }
         else {
-            vault = Vault::new_empty(pass.as_str())?;
+            let mut vault = Vault::create(&credential, config.kdf.clone())?;
+            vault.set_cipher(config.cipher);
+            show_recovery_code(&vault.new_recovery_code()?);
+            vault
+        };
+        Ok(Self::with_vault(vault, path, config))
+    }
+
+    // Unlock with the recovery code and set a new master password,
+    // the used code is replaced by a new one
+    pub fn recover(path: &str, config: &Config) -> Result<Self, ClipassError> {
+        hardening::harden_process();
+        let typed = SecretString::new(prompt_password("recovery code: ")?);
+        let code = recovery::normalize(typed.expose())?;
+        let mut vault = Vault::load_with_credential(&Credential::Recovery(code.expose()), path)?;
+
+        let index = pick_password_slot(&vault)?;
+        let password = read_new_password("new master password: ")?;
+        match index {
+            Some(index) => vault.reset_password(index, password.expose())?,
+            None => vault.add_slot(&Credential::password(password.expose()), "default")?,
+        }
+        show_recovery_code(&vault.new_recovery_code()?);
+
+        let mut clipass = Self::with_vault(vault, path, config);
+        clipass.save()?;
+        Ok(clipass)
+    }
+
+    // Unlock with key shares instead of a password, asking until enough were typed
+    pub fn unlock_with_shares(path: &str, config: &Config) -> Result<Self, ClipassError> {
+        hardening::harden_process();
+        let key = read_key_shares()?;
+        let vault = Vault::load_with_credential(&Credential::DataKey(&key), path)?;
+        let mut clipass = Self::with_vault(vault, path, config);
+        clipass.unlocked_with_key = true;
+        Ok(clipass)
+    }
+
+    // Wrap an already unlocked vault
+    pub fn with_vault(mut vault: Vault, path: &str, config: &Config) -> Self {
+        vault.set_backup_count(config.backup_count);
+        Self {
+            cli_on: false,
+            vault: Some(vault),
+            locked: None,
+            path: path.to_string(),
+            password_policy: config.generator.clone(),
+            passphrase_policy: config.passphrase.clone(),
+            clipboard: Clipboard::new(detect_backend(), config.clipboard_timeout()),
+            auto_lock: config.auto_lock(),
+            keyfile: config.keyfile.clone(),
+            unlocked_with_key: false,
+            file_stamp: file_stamp(path),
         }
-        Ok(Self { cli_on: false, vault, path: path.to_string() })
     }
 
     pub fn command_line(&mut self) {
-        println!("vault created at:\t\t{}", self.vault.created_at().format("%c"));
-        println!("vault modified at:\t\t{}", self.vault.modified_at().format("%c"));
+        if let Some(vault) = &self.vault {
+            println!("vault created at:\t\t{}", vault.created_at().format("%c"));
+            println!("vault modified at:\t\t{}", vault.modified_at().format("%c"));
+        }
         println!("help to show available commands");
         self.cli_on = true;
         while self.cli_on {
-            let cmd: Command = match input_read("> ") {
+            let cmd: Command = match self.read_command() {
                 Ok(c) => c,
                 Err(e) => {
                     eprintln!("error: {e}");
                     continue;
                 }
             };
+            if let Err(e) = self.unlock() {
+                eprintln!("error: {e}");
+                continue;
+            }
             let res = match self.run(cmd) {
                 Ok(s) => s,
                 Err(e) => {
@@ -52,18 +162,85 @@ impl Clipass {
         }
     }
 
+    // Prompt for a command, locking the vault when none comes within auto_lock
+    fn read_command(&mut self) -> Result<Command, ClipassError> {
+        print!("> ");
+        io::stdout().flush()?;
+        if let Some(timeout) = self.auto_lock && self.vault.is_some() && !utils::stdin_ready(timeout) {
+            self.lock()?;
+            print!("\nvault locked after inactivity\n> ");
+            io::stdout().flush()?;
+        }
+        input_read::<String>("")?.parse()
+    }
+
+    // Drop the vault and its key from memory, unsaved changes are kept encrypted
+    fn lock(&mut self) -> Result<(), ClipassError> {
+        if let Some(vault) = &self.vault {
+            self.locked = Some(vault.lock()?);
+            self.vault = None;
+        }
+        Ok(())
+    }
+
+    // Ask the password again, or the key shares of a session opened with them
+    fn unlock(&mut self) -> Result<(), ClipassError> {
+        let Some(locked) = &self.locked else {
+            return Ok(());
+        };
+        let vault = match self.unlocked_with_key {
+            true => locked.unlock(&Credential::DataKey(&read_key_shares()?), &self.path)?,
+            false => {
+                let password = SecretString::new(prompt_password("password: ")?);
+                let keyfile = self.load_keyfile()?;
+                locked.unlock(&Credential::with_keyfile(password.expose(), keyfile.as_ref()), &self.path)?
+            },
+        };
+        // Saved elsewhere meanwhile, it is what was just loaded
+        if locked.from_file() {
+            self.file_stamp = file_stamp(&self.path);
+        }
+        self.vault = Some(vault);
+        self.locked = None;
+        Ok(())
+    }
+
+    fn vault(&self) -> Result<&Vault, ClipassError> {
+        self.vault.as_ref().ok_or_else(locked_error)
+    }
+
+    fn vault_mut(&mut self) -> Result<&mut Vault, ClipassError> {
+        self.vault.as_mut().ok_or_else(locked_error)
+    }
+
     pub fn run(&mut self, command: Command) -> Result<String, ClipassError> {
         match command {
             Command::Help => self.help(),
-            Command::Get(id) => {
-                let e = self.get(&id)?.to_string();
-                Ok(e)
-            },
-            Command::Update(id) => self.update(&id),
+            Command::Get(id, field) => self.get(&id, field.as_deref()),
+            Command::Update(id, field) => self.update(&id, field.as_deref()),
+            Command::Set(id, field, stdin) => self.set(&id, &field, stdin),
+            Command::Copy(id, field) => self.copy(&id, &field),
+            Command::Totp(id) => self.totp(&id),
+            Command::Hotp(id) => self.hotp(&id),
             Command::Delete(id) => self.delete(&id),
-            Command::New => self.new_entry(),
-            Command::List => self.list(),
+            Command::New(generate) => self.new_entry(generate.as_ref()),
+            Command::Generate(args) => args.generate(&self.password_policy, &self.passphrase_policy),
+            Command::List(filter) => self.list(filter.as_deref()),
+            Command::Find(query) => self.find(&query),
             Command::Save => self.save(),
+            Command::Passwd => self.passwd(),
+            Command::Keyfile(path) => self.keyfile(path.as_deref()),
+            Command::Slots => self.slots(),
+            Command::AddSlot(label, keyfile) => self.add_slot(label.as_deref(), keyfile.as_deref()),
+            Command::RevokeSlot(index) => self.revoke_slot(index),
+            Command::RecoveryCode => self.recovery_code(),
+            Command::SplitKey(threshold, count) => self.split_key(threshold, count),
+            Command::Reencrypt(cipher) => self.reencrypt(cipher),
+            Command::KdfBench(target) => self.kdf_bench(target),
+            Command::KdfSet(memory, time, parallelism) => self.kdf_set(memory, time, parallelism),
+            Command::Import(args) => self.import(&args),
+            Command::Export(args) => self.export(&args),
+            Command::RestoreBackup(generation) => self.restore_backup(generation),
             Command::Quit => self.quit(),
         }
     }
@@ -72,64 +249,510 @@ impl Clipass {
     pub fn help(&self) -> Result<String, ClipassError> {
         static HELP_STR: &str =
             "commands: \n\
-            \r  - list: list all entries\n\
-            \r  - new: new entry\n\
-            \r  - get <id>: get entry by id\n\
-            \r  - update <id>\n\
+            \r  - list [filter]: list entries, filtered by substring, glob or /regex/\n\
+            \r  - find <query>: fuzzy search entries by id, username and url\n\
+            \r  - new [--generate [options]]: new entry, optionally with a generated password\n\
+            \r  - generate [options]: generate a password\n\
+            \r      -l, --length <n>, --no-lower, --no-upper, --no-digits, --no-symbols, --no-ambiguous\n\
+            \r      --passphrase, -w, --words <n>, --separator <s>, --capitalize\n\
+            \r  - get <id> [field]: get entry or one of its fields by id\n\
+            \r  - copy <id> [field]: copy a field (password by default) to the clipboard\n\
+            \r  - totp <id>: current one-time code of an entry\n\
+            \r  - hotp <id>: next counter based one-time code of an entry\n\
+            \r  - update <id> [field]: update a field of an entry\n\
+            \r  - set <id> [field] [--stdin]: set a field, creating the entry if needed\n\
             \r  - delete <id> \n\
             \r  - save: save to file\n\
+            \r  - passwd: change the master password\n\
+            \r  - keyfile <path> | --remove: require a keyfile to unlock, or stop requiring it\n\
+            \r  - slots: list the key slots, each one a way to unlock the vault\n\
+            \r  - add-slot [label] [--keyfile <path>]: add a password key slot\n\
+            \r  - revoke-slot <slot>: remove a key slot\n\
+            \r  - recovery-code: replace the recovery code\n\
+            \r  - split-key <threshold> <shares>: split the vault key in shares for emergency access\n\
+            \r  - reencrypt --cipher <cipher>: encrypt with aes-256-gcm or xchacha20-poly1305\n\
+            \r  - kdf-bench [milliseconds]: propose key derivation parameters for this host (1000 by default)\n\
+            \r  - kdf set [--memory <MiB>] [--time <n>] [--parallelism <n>]: change the key derivation\n\
+            \r  - import <format> <file> [--keyfile <file>] [--dry-run] [--on-conflict skip|rename|overwrite]:\n\
+            \r      import entries from bitwarden, keepass, pass or csv, --keyfile for keepass, --map for csv\n\
+            \r  - import <bundle> [--dry-run] [--on-conflict skip|rename|overwrite]: import an encrypted bundle\n\
+            \r  - export <format> <path> [filter] [--map <field=column,...>] [--yes]:\n\
+            \r      export entries to a pass store or a plaintext csv file, --yes skips the confirmation\n\
+            \r  - export --encrypted --to <file> <ids|filter>...: export entries to a bundle with its own passphrase\n\
+            \r  - restore-backup [generation]: list backups or restore one\n\
             \r  - help: show this help\n\
             \r  - quit";
         Ok(HELP_STR.to_string())
     }
 
-    pub fn list(&self) -> Result<String, ClipassError> {
+    pub fn list(&self, filter: Option<&str>) -> Result<String, ClipassError> {
+        let filter = filter.map(Filter::parse).transpose()?;
+        let mut ids: Vec<&String> = self.vault()?.get_all().keys()
+            .filter(|id| filter.as_ref().is_none_or(|f| f.matches(id)))
+            .collect();
+        ids.sort();
+        let mut listing = String::new();
+        for id in ids {
+            listing.push_str(format!(" - {id}: ******\n").as_str());
+        }
+        Ok(listing)
+    }
+
+    pub fn find(&self, query: &str) -> Result<String, ClipassError> {
         let mut listing = String::new();
-        for entry in self.vault.get_all() {
-            listing.push_str(format!(" - {}: ******\n", entry.0).as_str());
+        for m in search::find(self.vault()?.get_all(), query) {
+            listing.push_str(format!(" - {} ({})\n", m.id, m.field).as_str());
         }
-        Ok(listing.to_string())
+        Ok(listing)
     }
 
-    pub fn get(&self, id: &String) -> Result<&String, ClipassError> {
-        Ok(self.vault.get_value(id)?)
+    pub fn get(&self, id: &str, field: Option<&str>) -> Result<String, ClipassError> {
+        if let Some(field) = field {
+            return Ok(self.vault()?.get_value(id, field)?.to_string());
+        }
+        let mut output = String::new();
+        for (name, value) in self.vault()?.get_entry(id)?.fields() {
+            output.push_str(format!("{name}: {value}\n").as_str());
+        }
+        Ok(output.trim_end().to_string())
+    }
+
+    pub fn copy(&mut self, id: &str, field: &str) -> Result<String, ClipassError> {
+        let vault = self.vault.as_ref().ok_or_else(locked_error)?;
+        self.clipboard.copy(vault.get_value(id, field)?)?;
+        Ok(format!("copied {id} {field}, clearing in {}s", self.clipboard.timeout().as_secs()))
+    }
+
+    pub fn totp(&self, id: &str) -> Result<String, ClipassError> {
+        let (code, remaining) = self.vault()?.totp(id)?;
+        Ok(format!("{code} ({remaining}s left)"))
+    }
+
+    // Saved right away with the new counter, in every mode, a code is never given twice
+    pub fn hotp(&mut self, id: &str) -> Result<String, ClipassError> {
+        let code = self.vault_mut()?.hotp(id)?;
+        self.save()?;
+        Ok(code)
     }
 
-    pub fn update(&mut self, id: &String) -> Result<String, ClipassError> {
-        if !self.vault.contains_key(id) {
-            return Err(ClipassError::NotFound(id.clone()));
+    pub fn update(&mut self, id: &str, field: Option<&str>) -> Result<String, ClipassError> {
+        if !self.vault()?.contains_key(id) {
+            return Err(ClipassError::NotFound(id.to_string()));
         }
-        let new_value: String = input_read("new value: ")?;
-        self.vault.update(id, new_value.as_str())?;
+        let field: String = match field {
+            Some(f) => f.to_string(),
+            None => input_read("field: ")?,
+        };
+        let new_value = SecretString::new(match field.as_str() {
+            FIELD_PASSWORD => prompt_password("new password: ")?,
+            _ => input_read("new value: ")?,
+        });
+        self.vault_mut()?.update(id, field.as_str(), new_value.expose())?;
         Ok(format!("updated {id}"))
     }
 
-    pub fn delete(&mut self, id: &String) -> Result<String, ClipassError> {
-        self.vault.delete_entry(id)?;
+    pub fn set(&mut self, id: &str, field: &str, stdin: bool) -> Result<String, ClipassError> {
+        let value = SecretString::new(if stdin {
+            read_stdin_value()?
+        } else {
+            prompt_password(format!("{field}: "))?
+        });
+        self.set_value(id, field, value.expose())
+    }
+
+    pub fn set_value(&mut self, id: &str, field: &str, value: &str) -> Result<String, ClipassError> {
+        if !self.vault()?.contains_key(id) {
+            self.vault_mut()?.new_entry(id, Entry::new())?;
+        }
+        self.vault_mut()?.update(id, field, value)?;
+        Ok(format!("updated {id}"))
+    }
+
+    pub fn delete(&mut self, id: &str) -> Result<String, ClipassError> {
+        self.vault_mut()?.delete_entry(id)?;
         Ok(format!("deleted {id}"))
     }
 
-    pub fn new_entry(&mut self) -> Result<String, ClipassError> {
+    pub fn new_entry(&mut self, generate: Option<&GenerateArgs>) -> Result<String, ClipassError> {
         let id: String = input_read("id: ")?;
 
-        if self.vault.contains_key(&id) {
+        if self.vault()?.contains_key(&id) {
             return Err(ClipassError::IdExists(id));
         }
 
-        let value: String = input_read("value: ")?;
+        let mut entry = Entry::new();
+        let username: SecretString = input_read::<String>("username: ")?.into();
+        entry.set_field(FIELD_USERNAME, username.expose())?;
+        let password = SecretString::new(match generate {
+            Some(args) => args.generate(&self.password_policy, &self.passphrase_policy)?,
+            None => prompt_password("password: ")?,
+        });
+        entry.set_field(FIELD_PASSWORD, password.expose())?;
+        let urls: SecretString = input_read::<String>("url(s): ")?.into();
+        entry.set_field(FIELD_URL, urls.expose())?;
+        let notes: SecretString = input_read::<String>("notes: ")?.into();
+        entry.set_field(FIELD_NOTES, notes.expose())?;
+        loop {
+            let name: String = input_read("custom field (empty to finish): ")?;
+            if name.is_empty() {
+                break;
+            }
+            let value: SecretString = input_read::<String>("value: ")?.into();
+            entry.set_field(name.as_str(), value.expose())?;
+        }
 
-        self.vault.new_entry(&id, &value)?;
-        Ok(format!("{id}"))
+        self.vault_mut()?.new_entry(&id, entry)?;
+        Ok(id)
     }
 
-    pub fn quit(&mut self) -> Result<String, ClipassError> {
+    pub fn passwd(&mut self) -> Result<String, ClipassError> {
+        // No current password after key shares, a password slot is reset or added
+        if self.unlocked_with_key {
+            let index = pick_password_slot(self.vault()?)?;
+            let password = read_new_password("new password: ")?;
+            let vault = self.vault_mut()?;
+            match index {
+                Some(index) => vault.reset_password(index, password.expose())?,
+                None => vault.add_slot(&Credential::password(password.expose()), "default")?,
+            }
+            return Ok("master password set, save to apply".to_string());
+        }
+        let old_password = SecretString::new(prompt_password("current password: ")?);
+        let new_password = read_new_password("new password: ")?;
+        let keyfile = self.load_keyfile()?;
+        let current = Credential::with_keyfile(old_password.expose(), keyfile.as_ref());
+        self.vault_mut()?.change_password(&current, new_password.expose())?;
+        Ok("master password changed, save to apply".to_string())
+    }
+
+    pub fn keyfile(&mut self, path: Option<&str>) -> Result<String, ClipassError> {
+        let new_keyfile = path.map(|p| Keyfile::load(Path::new(p))).transpose()?;
+        let password = SecretString::new(prompt_password("current password: ")?);
+        let keyfile = self.load_keyfile()?;
+        let current = Credential::with_keyfile(password.expose(), keyfile.as_ref());
+        let message = match new_keyfile {
+            Some(_) => "keyfile required from now on, save to apply",
+            None => "keyfile no longer required, save to apply",
+        };
+        self.vault_mut()?.set_keyfile(&current, new_keyfile.as_ref())?;
+        self.keyfile = path.map(PathBuf::from);
+        Ok(message.to_string())
+    }
+
+    pub fn slots(&self) -> Result<String, ClipassError> {
+        let mut listing = String::new();
+        for (index, slot) in self.vault()?.slots().iter().enumerate() {
+            listing.push_str(describe_slot(index, slot).as_str());
+        }
+        Ok(listing)
+    }
+
+    pub fn add_slot(&mut self, label: Option<&str>, keyfile: Option<&str>) -> Result<String, ClipassError> {
+        let keyfile = keyfile.map(|p| Keyfile::load(Path::new(p))).transpose()?;
+        self.verify_current("current password: ")?;
+        let password = read_new_password("slot password: ")?;
+        let index = self.vault()?.slots().len();
+        let label = label.map(str::to_string).unwrap_or(format!("slot {index}"));
+        self.vault_mut()?.add_slot(&Credential::with_keyfile(password.expose(), keyfile.as_ref()), &label)?;
+        Ok(format!("added key slot {index}, save to apply"))
+    }
+
+    pub fn revoke_slot(&mut self, index: usize) -> Result<String, ClipassError> {
+        self.verify_current("current password: ")?;
+        self.vault_mut()?.revoke_slot(index)?;
+        Ok(format!("revoked key slot {index}, save to apply"))
+    }
+
+    pub fn recovery_code(&mut self) -> Result<String, ClipassError> {
+        self.verify_current("current password: ")?;
+        show_recovery_code(&self.vault_mut()?.new_recovery_code()?);
+        Ok("previous recovery code revoked, save to apply".to_string())
+    }
+
+    // The vault is saved first, a vault upgraded from a legacy one only gets
+    // its data key on disk then
+    pub fn split_key(&mut self, threshold: u8, count: u8) -> Result<String, ClipassError> {
+        self.verify_current("current password: ")?;
+        let shares = self.vault()?.split_key(threshold, count)?;
         self.save()?;
+        let mut listing = String::new();
+        for share in &shares {
+            listing.push_str(format!("share {} of {count} ({threshold} needed): {}\n", share.index(), share.encode().expose()).as_str());
+        }
+        listing.push_str("give each share to a different person, they stay valid until the vault key changes");
+        Ok(listing)
+    }
+
+    pub fn reencrypt(&mut self, cipher: Cipher) -> Result<String, ClipassError> {
+        self.verify_current("current password: ")?;
+        self.vault_mut()?.set_cipher(cipher);
+        Ok(format!("vault encrypted with {cipher}, save to apply"))
+    }
+
+    // Only proposes parameters, kdf set applies them
+    pub fn kdf_bench(&self, target_ms: Option<u64>) -> Result<String, ClipassError> {
+        let target = Duration::from_millis(target_ms.unwrap_or(DEFAULT_UNLOCK_MS));
+        let current = self.vault()?.kdf_params();
+        let current_time = crypto::benchmark_kdf(current)?;
+        let (proposed, proposed_time) = crypto::calibrate_kdf(target)?;
+        Ok(format!(
+            "current:  {current}: {:.2}s\nproposed: {proposed}: {:.2}s\napply with: kdf set --memory {} --time {} --parallelism {}",
+            current_time.as_secs_f64(), proposed_time.as_secs_f64(),
+            proposed.memory_cost / 1024, proposed.time_cost, proposed.parallelism,
+        ))
+    }
+
+    pub fn kdf_set(&mut self, memory_mib: Option<u32>, time: Option<u32>, parallelism: Option<u32>)
+        -> Result<String, ClipassError>
+    {
+        let current = self.vault()?.kdf_params();
+        let kdf = KdfParams {
+            memory_cost: memory_mib.map_or(current.memory_cost, |m| m.saturating_mul(1024)),
+            time_cost: time.unwrap_or(current.time_cost),
+            parallelism: parallelism.unwrap_or(current.parallelism),
+        };
+        let password = SecretString::new(prompt_password("current password: ")?);
+        let keyfile = self.load_keyfile()?;
+        self.vault_mut()?.set_kdf(&Credential::with_keyfile(password.expose(), keyfile.as_ref()), kdf.clone())?;
+        Ok(format!("key derivation set to {kdf}, save to apply"))
+    }
+
+    pub fn import(&mut self, args: &ImportArgs) -> Result<String, ClipassError> {
+        let password = args.format.password_prompt()
+            .map(|prompt| prompt_password(prompt).map(SecretString::new))
+            .transpose()?;
+        let import = args.read(password.as_ref())?;
+        let report = formats::merge(self.vault_mut()?, import, args.strategy, args.dry_run)?;
+        Ok(report.to_string())
+    }
+
+    pub fn export(&self, args: &ExportArgs) -> Result<String, ClipassError> {
+        let vault = self.vault()?;
+        // An existing id selects that entry only
+        let (ids, filters): (Vec<&String>, Vec<&String>) = args.filters.iter()
+            .partition(|selector| vault.contains_key(selector));
+        let filters = filters.into_iter().map(|f| Filter::parse(f)).collect::<Result<Vec<_>, _>>()?;
+        let entries: Vec<_> = vault.get_all().iter()
+            .filter(|(id, _)| args.filters.is_empty() || ids.contains(id) || filters.iter().any(|f| f.matches(id)))
+            .collect();
+        match args.format {
+            ExportFormat::Bundle => {
+                if entries.is_empty() {
+                    return Err(ClipassError::Input("no entry to export".to_string()));
+                }
+                let passphrase = read_new_password("bundle passphrase: ")?;
+                let count = bundle::export(
+                    entries, Path::new(&args.path), passphrase.expose(), vault.kdf_params().clone(), vault.cipher(),
+                )?;
+                Ok(format!("exported {count} entries to {}, give the passphrase apart from the file", args.path))
+            },
+            ExportFormat::Csv => {
+                eprintln!("{}", csv::PLAINTEXT_WARNING);
+                if !args.yes {
+                    let prompt = format!("write {} entries in clear to {}? type yes to confirm: ", entries.len(), args.path);
+                    if input_read::<String>(&prompt)? != "yes" {
+                        return Ok("export cancelled".to_string());
+                    }
+                }
+                let count = csv::export(entries, Path::new(&args.path), &args.columns)?;
+                Ok(format!("exported {count} entries to {}", args.path))
+            },
+            ExportFormat::Pass => {
+                let report = pass::export(entries, Path::new(&args.path), &pass::Gpg)?;
+                let mut output = format!("exported {} entries to {}", report.written.len(), args.path);
+                for id in &report.existing {
+                    output.push_str(format!("\n - skipped {id}, already in the store").as_str());
+                }
+                for warning in &report.warnings {
+                    output.push_str(format!("\nwarning: {warning}").as_str());
+                }
+                Ok(output)
+            },
+        }
+    }
+
+    // Ask a password opening one of the key slots
+    fn verify_current(&self, prompt: &str) -> Result<(), ClipassError> {
+        // The shares already proved the data key
+        if self.unlocked_with_key {
+            return Ok(());
+        }
+        let password = SecretString::new(prompt_password(prompt)?);
+        let keyfile = self.load_keyfile()?;
+        match self.vault()?.verify(&Credential::with_keyfile(password.expose(), keyfile.as_ref()))? {
+            true => Ok(()),
+            false => Err(ClipassError::WrongPassword),
+        }
+    }
+
+    fn load_keyfile(&self) -> Result<Option<Keyfile>, ClipassError> {
+        self.keyfile.as_deref().map(Keyfile::load).transpose()
+    }
+
+    pub fn restore_backup(&mut self, generation: Option<usize>) -> Result<String, ClipassError> {
+        let path = Path::new(&self.path);
+        let backups = backup::list_backups(path)?;
+        let Some(generation) = generation else {
+            let mut listing = String::new();
+            for b in &backups {
+                let modified_at: DateTime<Local> = DateTime::from(b.modified_at);
+                listing.push_str(format!(" - {}: {}\n", b.generation, modified_at.format("%c")).as_str());
+            }
+            return Ok(listing);
+        };
+        let backup = backups.iter()
+            .find(|b| b.generation == generation)
+            .ok_or(ClipassError::NotFound(format!("backup {generation}")))?;
+
+        // The backup may predate a master password change
+        let pass = SecretString::new(prompt_password("backup password: ")?);
+        let backup_path = backup.path.to_string_lossy().to_string();
+        let keyfile = self.load_keyfile()?;
+        let credential = Credential::with_keyfile(pass.expose(), keyfile.as_ref());
+        let mut restored = Vault::load_with_credential(&credential, backup_path.as_str())?;
+        restored.set_backup_count(self.vault()?.backup_count());
+        self.vault = Some(restored);
+        self.save()?;
+        Ok(format!("restored backup {generation}"))
+    }
+
+    pub fn quit(&mut self) -> Result<String, ClipassError> {
+        // Left as it is when unchanged, it may have been saved elsewhere meanwhile
+        if self.is_updated() {
+            self.save()?;
+        }
+        self.clipboard.clear_now();
         self.cli_on = false;
         Ok("".to_string())
     }
 
+    // Block until a copied value is cleared from the clipboard
+    pub fn wait_clipboard(&mut self) {
+        self.clipboard.wait();
+    }
+
+    pub fn is_updated(&self) -> bool {
+        self.vault.as_ref().is_some_and(Vault::is_updated)
+    }
+
+    // Refused when another session wrote the file since it was loaded
     pub fn save(&mut self) -> Result<String, ClipassError> {
-        self.vault.crypt_to_file(self.path.as_str())?;
+        if file_stamp(&self.path) != self.file_stamp {
+            return Err(ClipassError::Io(format!(
+                "{} was saved elsewhere since it was opened, not overwritten: open it again to apply changes", self.path)));
+        }
+        self.vault()?.crypt_to_file(self.path.as_str())?;
+        self.vault_mut()?.mark_saved();
+        self.file_stamp = file_stamp(&self.path);
         Ok("saved".to_string())
     }
-}
\ No newline at end of file
+
+    // Load the vault file again if another session saved it, unless there are unsaved changes
+    pub fn reload_if_changed(&mut self) -> Result<(), ClipassError> {
+        let stamp = file_stamp(&self.path);
+        if stamp == self.file_stamp || self.vault()?.is_updated() {
+            return Ok(());
+        }
+        let vault = self.vault()?.reload(&self.path)
+            .map_err(|e| ClipassError::AgentError(format!("{} changed and cannot be reloaded ({e})", self.path)))?;
+        self.vault = Some(vault);
+        self.file_stamp = stamp;
+        Ok(())
+    }
+}
+
+// Header of a vault file, without unlocking it
+pub fn inspect_vault(path: &str) -> Result<String, ClipassError> {
+    let info = Vault::inspect(path)?;
+    let mut report = format!(
+        "format:       v{}\n\
+         size:         {} bytes, header {}, content {}\n\
+         created at:   {}\n\
+         modified at:  {}\n\
+         cipher:       {}\n",
+        info.version, info.file_size, info.header_size, info.file_size - info.header_size,
+        info.created_at.format("%c"), info.modified_at.format("%c"), info.cipher,
+    );
+    match info.legacy_kdf {
+        Some(kdf) => report.push_str(format!("key:          from the password, {kdf}\n").as_str()),
+        None => {
+            report.push_str("key slots:\n");
+            for (index, slot) in info.slots.iter().enumerate() {
+                report.push_str(describe_slot(index, slot).as_str());
+            }
+        },
+    }
+    Ok(report)
+}
+
+// Unlock and parse a vault, a wrong password and a damaged file give different errors
+pub fn verify_vault(path: &str, config: &Config) -> Result<String, ClipassError> {
+    hardening::harden_process();
+    let pass = read_master_password()?;
+    let keyfile = config.load_keyfile()?;
+    let vault = Vault::load_with_credential(&Credential::with_keyfile(pass.expose(), keyfile.as_ref()), path)?;
+    Ok(format!("vault ok: {} entries, {} key slots", vault.get_all().len(), vault.slots().len()))
+}
+
+fn describe_slot(index: usize, slot: &KeySlot) -> String {
+    let keyfile = if slot.requires_keyfile() { ", keyfile" } else { "" };
+    format!(" - {index}: {} \"{}\"{keyfile}, {}\n", slot.kind().name(), slot.label(), slot.kdf())
+}
+
+fn read_master_password() -> Result<SecretString, ClipassError> {
+    Ok(SecretString::new(match env::var(PASSWORD_ENV) {
+        Ok(p) => p,
+        Err(_) => prompt_password("password: ")?,
+    }))
+}
+
+// Shown once, on stderr to stay out of scripted output
+fn show_recovery_code(code: &SecretString) {
+    eprintln!("recovery code: {}", code.expose());
+    eprintln!("write it down and keep it offline, it unlocks the vault and will not be shown again");
+}
+
+// Prompt a new password twice
+fn read_new_password(prompt: &str) -> Result<SecretString, ClipassError> {
+    let password = SecretString::new(prompt_password(prompt)?);
+    let confirmation = SecretString::new(prompt_password(format!("confirm {prompt}"))?);
+    if password != confirmation {
+        return Err(ClipassError::Input("passwords do not match".to_string()));
+    }
+    Ok(password)
+}
+
+// The password slot to reset, asked when there are several, None without any
+fn pick_password_slot(vault: &Vault) -> Result<Option<usize>, ClipassError> {
+    let password_slots: Vec<usize> = vault.slots().iter().enumerate()
+        .filter(|(_, slot)| slot.kind() == SlotKind::Password)
+        .map(|(index, _)| index)
+        .collect();
+    match password_slots.as_slice() {
+        [] => Ok(None),
+        [index] => Ok(Some(*index)),
+        _ => {
+            for index in &password_slots {
+                println!(" - {index}: {}", vault.slots()[*index].label());
+            }
+            Ok(Some(input_read("key slot to reset: ")?))
+        },
+    }
+}
+
+fn locked_error() -> ClipassError {
+    ClipassError::Input("the vault is locked".to_string())
+}
+
+// Ask key shares until enough were typed, the data key they rebuild
+fn read_key_shares() -> Result<Key, ClipassError> {
+    let mut shares: Vec<Share> = Vec::new();
+    while shares.first().is_none_or(|s| shares.len() < s.threshold() as usize) {
+        let typed = SecretString::new(prompt_password(format!("key share {}: ", shares.len() + 1))?);
+        match Share::parse(typed.expose()) {
+            Ok(share) => shares.push(share),
+            Err(e) => eprintln!("error: {e}"),
+        }
+    }
+    shamir::combine(&shares)
+}
@@ -0,0 +1,247 @@
+use std::env;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use base64ct::{Base64, Encoding};
+use zeroize::Zeroizing;
+use crate::error::ClipassError;
+
+pub trait ClipboardBackend: Send {
+    fn set(&mut self, value: &str) -> Result<(), ClipassError>;
+    // None when the backend cannot read the clipboard back
+    fn get(&mut self) -> Result<Option<String>, ClipassError>;
+    fn clear(&mut self) -> Result<(), ClipassError>;
+}
+
+/// Clipboard through an external program (wl-copy, xclip, xsel)
+pub struct CommandBackend {
+    copy: &'static [&'static str],
+    paste: &'static [&'static str],
+    clear: Option<&'static [&'static str]>,
+}
+
+impl CommandBackend {
+    pub fn wl_copy() -> Self {
+        Self { copy: &["wl-copy"], paste: &["wl-paste", "--no-newline"], clear: Some(&["wl-copy", "--clear"]) }
+    }
+
+    pub fn xclip() -> Self {
+        Self {
+            copy: &["xclip", "-selection", "clipboard", "-in"],
+            paste: &["xclip", "-selection", "clipboard", "-out"],
+            clear: None,
+        }
+    }
+
+    pub fn xsel() -> Self {
+        Self {
+            copy: &["xsel", "--clipboard", "--input"],
+            paste: &["xsel", "--clipboard", "--output"],
+            clear: Some(&["xsel", "--clipboard", "--delete"]),
+        }
+    }
+
+    fn run(args: &[&str], input: Option<&str>) -> Result<String, ClipassError> {
+        let mut child = Command::new(args[0])
+            .args(&args[1..])
+            .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+            .stdout(if input.is_some() { Stdio::null() } else { Stdio::piped() })
+            .stderr(Stdio::null())
+            .spawn()?;
+        if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+            stdin.write_all(input.as_bytes())?;
+        }
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(ClipassError::Io(format!("{} exited with {}", args[0], output.status)));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+}
+
+impl ClipboardBackend for CommandBackend {
+    fn set(&mut self, value: &str) -> Result<(), ClipassError> {
+        Self::run(self.copy, Some(value)).map(|_| ())
+    }
+
+    fn get(&mut self) -> Result<Option<String>, ClipassError> {
+        Self::run(self.paste, None).map(Some)
+    }
+
+    fn clear(&mut self) -> Result<(), ClipassError> {
+        match self.clear {
+            Some(args) => Self::run(args, None).map(|_| ()),
+            None => self.set(""),
+        }
+    }
+}
+
+/// Clipboard through the OSC 52 terminal escape sequence, works over ssh
+/// but cannot read the clipboard back
+pub struct Osc52Backend;
+
+impl Osc52Backend {
+    fn write(payload: &str) -> Result<(), ClipassError> {
+        let sequence = format!("\x1b]52;c;{payload}\x07");
+        let mut tty = std::fs::OpenOptions::new().write(true).open("/dev/tty")?;
+        tty.write_all(sequence.as_bytes())?;
+        tty.flush()?;
+        Ok(())
+    }
+}
+
+impl ClipboardBackend for Osc52Backend {
+    fn set(&mut self, value: &str) -> Result<(), ClipassError> {
+        let encoded = Zeroizing::new(Base64::encode_string(value.as_bytes()));
+        Self::write(encoded.as_str())
+    }
+
+    fn get(&mut self) -> Result<Option<String>, ClipassError> {
+        Ok(None)
+    }
+
+    fn clear(&mut self) -> Result<(), ClipassError> {
+        // Anything that is not base64 clears the selection
+        Self::write("!")
+    }
+}
+
+/// In-memory clipboard, the content stays readable from clones of the backend
+#[derive(Clone, Default)]
+pub struct MemoryBackend {
+    content: Arc<Mutex<Option<String>>>,
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn content(&self) -> Option<String> {
+        self.content.lock().unwrap().clone()
+    }
+}
+
+impl ClipboardBackend for MemoryBackend {
+    fn set(&mut self, value: &str) -> Result<(), ClipassError> {
+        *self.content.lock().unwrap() = Some(value.to_string());
+        Ok(())
+    }
+
+    fn get(&mut self) -> Result<Option<String>, ClipassError> {
+        Ok(Some(self.content().unwrap_or_default()))
+    }
+
+    fn clear(&mut self) -> Result<(), ClipassError> {
+        *self.content.lock().unwrap() = None;
+        Ok(())
+    }
+}
+
+// Pick the first usable backend for the current session
+pub fn detect_backend() -> Box<dyn ClipboardBackend> {
+    if env::var_os("WAYLAND_DISPLAY").is_some() && in_path("wl-copy") {
+        return Box::new(CommandBackend::wl_copy());
+    }
+    if env::var_os("DISPLAY").is_some() {
+        if in_path("xclip") {
+            return Box::new(CommandBackend::xclip());
+        }
+        if in_path("xsel") {
+            return Box::new(CommandBackend::xsel());
+        }
+    }
+    Box::new(Osc52Backend)
+}
+
+fn in_path(program: &str) -> bool {
+    env::var_os("PATH")
+        .map(|paths| env::split_paths(&paths).any(|dir| dir.join(program).is_file()))
+        .unwrap_or(false)
+}
+
+struct PendingClear {
+    // Sending clears right away, dropping cancels
+    trigger: Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+/// Copies values to a backend and clears them after a timeout,
+/// unless the clipboard content changed in between
+pub struct Clipboard {
+    backend: Arc<Mutex<Box<dyn ClipboardBackend>>>,
+    timeout: Duration,
+    generation: Arc<AtomicU64>,
+    pending: Option<PendingClear>,
+}
+
+impl Clipboard {
+    pub fn new(backend: Box<dyn ClipboardBackend>, timeout: Duration) -> Self {
+        Self {
+            backend: Arc::new(Mutex::new(backend)),
+            timeout,
+            generation: Arc::new(AtomicU64::new(0)),
+            pending: None,
+        }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub fn copy(&mut self, value: &str) -> Result<(), ClipassError> {
+        self.backend.lock().unwrap().set(value)?;
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        // A previous clear must not wipe this new value
+        self.pending = None;
+
+        let (trigger, rx) = channel();
+        let backend = Arc::clone(&self.backend);
+        let current = Arc::clone(&self.generation);
+        let timeout = self.timeout;
+        let value = Zeroizing::new(value.to_string());
+        let handle = thread::spawn(move || {
+            if let Err(RecvTimeoutError::Disconnected) = rx.recv_timeout(timeout) {
+                return;
+            }
+            if current.load(Ordering::SeqCst) != generation {
+                return;
+            }
+            let mut backend = backend.lock().unwrap();
+            let still_ours = match backend.get() {
+                Ok(Some(content)) => Zeroizing::new(content).as_str() == value.as_str(),
+                Ok(None) => true,
+                Err(_) => false,
+            };
+            if still_ours {
+                let _ = backend.clear();
+            }
+        });
+        self.pending = Some(PendingClear { trigger, handle });
+        Ok(())
+    }
+
+    // Clear now instead of waiting for the timeout
+    pub fn clear_now(&mut self) {
+        if let Some(pending) = self.pending.take() {
+            let _ = pending.trigger.send(());
+            let _ = pending.handle.join();
+        }
+    }
+
+    // Block until the pending clear happens
+    pub fn wait(&mut self) {
+        if let Some(pending) = self.pending.take() {
+            let _ = pending.handle.join();
+        }
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+}
@@ -1,14 +1,40 @@
 use std::str::FromStr;
+use crate::crypto::Cipher;
 use crate::error::ClipassError;
+use crate::formats::{ExportArgs, ImportArgs};
+use crate::generator::GenerateArgs;
+use crate::vault::entry::FIELD_PASSWORD;
+
+// Longest kdf-bench target, in milliseconds
+const MAX_KDF_TARGET_MS: u64 = 60_000;
 
 pub enum Command {
     Help,
-    List,
-    Get(String),
-    Update(String),
-    New,
+    List(Option<String>),
+    Find(String),
+    Get(String, Option<String>),
+    Update(String, Option<String>),
+    Set(String, String, bool),
+    Copy(String, String),
+    Totp(String),
+    Hotp(String),
+    New(Option<GenerateArgs>),
+    Generate(GenerateArgs),
     Delete(String),
     Save,
+    Passwd,
+    Keyfile(Option<String>), // None removes the requirement
+    Slots,
+    AddSlot(Option<String>, Option<String>), // label, keyfile
+    RevokeSlot(usize),
+    RecoveryCode,
+    SplitKey(u8, u8), // threshold, shares
+    Reencrypt(Cipher),
+    KdfBench(Option<u64>), // target unlock time in milliseconds
+    KdfSet(Option<u32>, Option<u32>, Option<u32>), // memory in MiB, iterations, lanes
+    Import(ImportArgs),
+    Export(ExportArgs),
+    RestoreBackup(Option<usize>),
     Quit,
 }
 
@@ -17,7 +43,23 @@ impl FromStr for Command {
 
     // Implementing the command handling
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut parts = s.trim().split_whitespace();
+        Command::from_args(s.split_whitespace())
+    }
+}
+
+// Names accepted by Command::from_args
+pub const COMMAND_NAMES: &[&str] = &[
+    "help", "get", "copy", "totp", "hotp", "list", "find", "new", "generate", "save",
+    "passwd", "keyfile", "slots", "add-slot", "revoke-slot", "recovery-code", "split-key", "reencrypt", "kdf-bench", "kdf", "import", "export", "restore-backup", "quit", "delete", "update", "set",
+];
+
+impl Command {
+    // Parse a command already split in arguments, as given on the command line
+    pub fn from_args<'a, I>(args: I) -> Result<Self, ClipassError>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut parts = args.into_iter();
         let cmd_name = parts.next()
             .ok_or(ClipassError::InvalidCommand("Empty".to_string()))?;
 
@@ -26,11 +68,110 @@ impl FromStr for Command {
             "get" => {
                 let arg = parts.next()
                     .ok_or(ClipassError::InvalidCommand("missing argument for 'get'".to_string()))?;
-                Ok(Command::Get(arg.to_string()))
+                let field = parts.next().map(str::to_string);
+                Ok(Command::Get(arg.to_string(), field))
+            },
+            "copy" => {
+                let arg = parts.next()
+                    .ok_or(ClipassError::InvalidCommand("missing argument for 'copy'".to_string()))?;
+                let field = parts.next().unwrap_or(FIELD_PASSWORD);
+                Ok(Command::Copy(arg.to_string(), field.to_string()))
+            },
+            "totp" => {
+                let arg = parts.next()
+                    .ok_or(ClipassError::InvalidCommand("missing argument for 'totp'".to_string()))?;
+                Ok(Command::Totp(arg.to_string()))
+            },
+            "hotp" => {
+                let arg = parts.next()
+                    .ok_or(ClipassError::InvalidCommand("missing argument for 'hotp'".to_string()))?;
+                Ok(Command::Hotp(arg.to_string()))
             },
-            "list" => Ok(Command::List),
-            "new" => Ok(Command::New),
+            "list" => Ok(Command::List(parts.next().map(str::to_string))),
+            "find" => {
+                let query: Vec<&str> = parts.collect();
+                if query.is_empty() {
+                    return Err(ClipassError::InvalidCommand("missing argument for 'find'".to_string()));
+                }
+                Ok(Command::Find(query.join(" ")))
+            },
+            "new" => match parts.next() {
+                None => Ok(Command::New(None)),
+                Some("--generate") => Ok(Command::New(Some(GenerateArgs::from_args(parts)?))),
+                Some(arg) => Err(ClipassError::InvalidCommand(format!("unexpected argument '{arg}' for 'new'"))),
+            },
+            "generate" => Ok(Command::Generate(GenerateArgs::from_args(parts)?)),
             "save" => Ok(Command::Save),
+            "passwd" => Ok(Command::Passwd),
+            "keyfile" => match parts.next() {
+                None => Err(ClipassError::InvalidCommand("missing argument for 'keyfile'".to_string())),
+                Some("--remove") => Ok(Command::Keyfile(None)),
+                Some(path) => Ok(Command::Keyfile(Some(path.to_string()))),
+            },
+            "slots" => Ok(Command::Slots),
+            "add-slot" => {
+                let mut label = None;
+                let mut keyfile = None;
+                while let Some(arg) = parts.next() {
+                    match arg {
+                        "--keyfile" => keyfile = Some(parts.next()
+                            .ok_or(ClipassError::InvalidCommand("missing value for '--keyfile'".to_string()))?
+                            .to_string()),
+                        l if label.is_none() && !l.starts_with("--") => label = Some(l.to_string()),
+                        _ => return Err(ClipassError::InvalidCommand(format!("unexpected argument '{arg}' for 'add-slot'"))),
+                    }
+                }
+                Ok(Command::AddSlot(label, keyfile))
+            },
+            "revoke-slot" => {
+                let index = parts.next()
+                    .ok_or(ClipassError::InvalidCommand("missing argument for 'revoke-slot'".to_string()))?;
+                Ok(Command::RevokeSlot(index.parse()?))
+            },
+            "recovery-code" => Ok(Command::RecoveryCode),
+            "split-key" => {
+                let (Some(threshold), Some(count)) = (parts.next(), parts.next()) else {
+                    return Err(ClipassError::InvalidCommand("usage: split-key <threshold> <shares>".to_string()));
+                };
+                Ok(Command::SplitKey(threshold.parse()?, count.parse()?))
+            },
+            "reencrypt" => match (parts.next(), parts.next()) {
+                (Some("--cipher"), Some(cipher)) => Ok(Command::Reencrypt(cipher.parse()?)),
+                _ => Err(ClipassError::InvalidCommand("usage: reencrypt --cipher <cipher>".to_string())),
+            },
+            "kdf-bench" => {
+                let target: Option<u64> = parts.next().map(str::parse).transpose()?;
+                if target.is_some_and(|ms| ms == 0 || ms > MAX_KDF_TARGET_MS) {
+                    return Err(ClipassError::InvalidCommand(
+                        format!("kdf-bench target must be between 1 and {MAX_KDF_TARGET_MS} ms")));
+                }
+                Ok(Command::KdfBench(target))
+            },
+            "kdf" => {
+                if parts.next() != Some("set") {
+                    return Err(ClipassError::InvalidCommand(
+                        "usage: kdf set [--memory <MiB>] [--time <n>] [--parallelism <n>]".to_string()));
+                }
+                let (mut memory, mut time, mut parallelism) = (None, None, None);
+                while let Some(arg) = parts.next() {
+                    let option = match arg {
+                        "--memory" => &mut memory,
+                        "--time" => &mut time,
+                        "--parallelism" => &mut parallelism,
+                        _ => return Err(ClipassError::InvalidCommand(format!("unexpected argument '{arg}' for 'kdf set'"))),
+                    };
+                    *option = Some(parts.next()
+                        .ok_or(ClipassError::InvalidCommand(format!("missing value for '{arg}'")))?
+                        .parse()?);
+                }
+                Ok(Command::KdfSet(memory, time, parallelism))
+            },
+            "import" => Ok(Command::Import(ImportArgs::from_args(parts)?)),
+            "export" => Ok(Command::Export(ExportArgs::from_args(parts)?)),
+            "restore-backup" => {
+                let generation = parts.next().map(str::parse::<usize>).transpose()?;
+                Ok(Command::RestoreBackup(generation))
+            },
             "quit" => Ok(Command::Quit),
             "delete" => {
                 let arg = parts.next()
@@ -40,7 +181,22 @@ impl FromStr for Command {
             "update" => {
                 let arg = parts.next()
                     .ok_or(ClipassError::InvalidCommand("missing argument for 'update'".to_string()))?;
-                Ok(Command::Update(arg.to_string()))
+                let field = parts.next().map(str::to_string);
+                Ok(Command::Update(arg.to_string(), field))
+            },
+            "set" => {
+                let id = parts.next()
+                    .ok_or(ClipassError::InvalidCommand("missing argument for 'set'".to_string()))?;
+                let mut field = None;
+                let mut stdin = false;
+                for arg in parts {
+                    match arg {
+                        "--stdin" => stdin = true,
+                        f if field.is_none() && !f.starts_with("--") => field = Some(f.to_string()),
+                        _ => return Err(ClipassError::InvalidCommand(format!("unexpected argument '{arg}' for 'set'"))),
+                    }
+                }
+                Ok(Command::Set(id.to_string(), field.unwrap_or(FIELD_PASSWORD.to_string()), stdin))
             },
             _ => Err(ClipassError::InvalidCommand(cmd_name.to_string()))
         }
@@ -0,0 +1,118 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use serde::Deserialize;
+use crate::crypto::{Cipher, KdfParams, Keyfile};
+use crate::error::ClipassError;
+use crate::generator::{PassphrasePolicy, PasswordPolicy};
+use crate::vault::backup::DEFAULT_BACKUP_COUNT;
+
+pub const CONFIG_ENV: &str = "CLIPASS_CONFIG";
+
+/*
+    # $XDG_CONFIG_HOME/clipass/config.toml
+    vault = "~/passwords.clip"
+    keyfile = "~/.ssh/clipass.key"  # second unlock factor, see the keyfile command
+    clipboard_timeout = 20      # seconds
+    auto_lock = 900             # idle seconds before the vault is dropped from memory, 0 never
+    backup_count = 3
+    cipher = "aes-256-gcm"      # for new vaults, or "xchacha20-poly1305"
+
+    [kdf]                       # for new vaults
+    memory_cost = 65536
+    time_cost = 3
+    parallelism = 4
+
+    [generator]
+    length = 20
+    symbols = true
+    exclude_ambiguous = false
+
+    [passphrase]
+    words = 6
+    separator = "-"
+*/
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub vault: Option<PathBuf>,
+    pub keyfile: Option<PathBuf>,
+    pub clipboard_timeout: u64,
+    pub auto_lock: u64,
+    pub backup_count: usize,
+    pub cipher: Cipher,
+    pub kdf: KdfParams,
+    pub generator: PasswordPolicy,
+    pub passphrase: PassphrasePolicy,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            vault: None,
+            keyfile: None,
+            clipboard_timeout: 20,
+            auto_lock: 900,
+            backup_count: DEFAULT_BACKUP_COUNT,
+            cipher: Cipher::default(),
+            kdf: KdfParams::default(),
+            generator: PasswordPolicy::default(),
+            passphrase: PassphrasePolicy::default(),
+        }
+    }
+}
+
+impl Config {
+    // Load `path`, else $CLIPASS_CONFIG, else the XDG config file.
+    // Only an explicitly given file has to exist.
+    pub fn load(path: Option<&Path>) -> Result<Self, ClipassError> {
+        let explicit = path.map(Path::to_path_buf)
+            .or_else(|| env::var_os(CONFIG_ENV).map(PathBuf::from));
+        let path = match explicit {
+            Some(p) => p,
+            None => match default_path() {
+                Some(p) if p.exists() => p,
+                _ => return Ok(Self::default()),
+            },
+        };
+        let content = fs::read_to_string(&path)
+            .map_err(|e| ClipassError::Io(format!("{}: {e}", path.display())))?;
+        Self::parse(&content)
+    }
+
+    pub fn parse(content: &str) -> Result<Self, ClipassError> {
+        let mut config: Config = toml::from_str(content)
+            .map_err(|e| ClipassError::ConfigError(e.to_string()))?;
+        config.vault = config.vault.map(|v| expand_home(&v));
+        config.keyfile = config.keyfile.map(|k| expand_home(&k));
+        config.kdf.check().map_err(|e| ClipassError::ConfigError(format!("[kdf] {e}")))?;
+        Ok(config)
+    }
+
+    pub fn clipboard_timeout(&self) -> Duration {
+        Duration::from_secs(self.clipboard_timeout)
+    }
+
+    pub fn auto_lock(&self) -> Option<Duration> {
+        (self.auto_lock > 0).then(|| Duration::from_secs(self.auto_lock))
+    }
+
+    pub fn load_keyfile(&self) -> Result<Option<Keyfile>, ClipassError> {
+        self.keyfile.as_deref().map(Keyfile::load).transpose()
+    }
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_dir.join("clipass").join("config.toml"))
+}
+
+fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), env::var_os("HOME")) {
+        (Ok(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => path.to_path_buf(),
+    }
+}
@@ -1,25 +1,123 @@
-use aes_gcm::aead::generic_array::{typenum, GenericArray};
-use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
-use aes_gcm::aead::{Aead, Payload};
-use aes_gcm::aead::consts::U12;
-use argon2::{Argon2, PasswordHasher};
+use aes_gcm::aead::generic_array::GenericArray;
+use aes_gcm::{Aes256Gcm, KeyInit};
+use aes_gcm::aead::{Aead, AeadInPlace, Buffer, Payload};
+use chacha20poly1305::XChaCha20Poly1305;
+use argon2::Argon2;
 use argon2::password_hash::rand_core::RngCore;
 use argon2::password_hash::SaltString;
 use rand::thread_rng;
-use zeroize::{Zeroize, ZeroizeOnDrop};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+use zeroize::Zeroizing;
+use serde::Deserialize;
 use crate::error::ClipassError;
+use crate::hardening::LockedBytes;
+use crate::vault::SALT_SIZE;
 
-#[derive(Clone, ZeroizeOnDrop)]
-pub struct Key(GenericArray<u8, typenum::U32>);
+pub const KEY_SIZE: usize = 32;
+
+// 256 bits key in locked memory, wiped on drop
+#[derive(Clone)]
+pub struct Key(LockedBytes);
 
 impl Key {
-    fn new(data: GenericArray<u8, typenum::U32>) -> Self {
-        Self(data)
+    fn zeroed() -> Self {
+        Self(LockedBytes::zeroed(KEY_SIZE))
+    }
+
+    // Random data key
+    pub fn generate() -> Self {
+        let mut key = Self::zeroed();
+        thread_rng().fill_bytes(key.0.as_mut_slice());
+        key
+    }
+
+    // Key rebuilt from key shares
+    pub(crate) fn from_bytes(bytes: LockedBytes) -> Self {
+        Self(bytes)
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        self.0.as_slice()
+    }
+
+    // Constant time comparison
+    pub fn matches(&self, other: &Key) -> bool {
+        self.0.as_slice().iter()
+            .zip(other.0.as_slice().iter())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+    }
+}
+
+/// AEAD encrypting the vault content
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+pub enum Cipher {
+    #[default]
+    #[serde(rename = "aes-256-gcm")]
+    Aes256Gcm,
+    // Constant time without AES instructions, 192 bits nonces
+    #[serde(rename = "xchacha20-poly1305")]
+    XChaCha20Poly1305,
+}
+
+impl Cipher {
+    pub fn id(&self) -> u8 {
+        match self {
+            Cipher::Aes256Gcm => 1,
+            Cipher::XChaCha20Poly1305 => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Result<Self, ClipassError> {
+        match id {
+            1 => Ok(Cipher::Aes256Gcm),
+            2 => Ok(Cipher::XChaCha20Poly1305),
+            _ => Err(ClipassError::HeaderError(format!("unknown cipher {id}"))),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Cipher::Aes256Gcm => "aes-256-gcm",
+            Cipher::XChaCha20Poly1305 => "xchacha20-poly1305",
+        }
+    }
+
+    pub fn nonce_size(&self) -> usize {
+        match self {
+            Cipher::Aes256Gcm => 12,
+            Cipher::XChaCha20Poly1305 => 24,
+        }
+    }
+}
+
+impl FromStr for Cipher {
+    type Err = ClipassError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "aes-256-gcm" => Ok(Cipher::Aes256Gcm),
+            "xchacha20-poly1305" => Ok(Cipher::XChaCha20Poly1305),
+            _ => Err(ClipassError::InvalidCommand(format!("unknown cipher '{s}', expected aes-256-gcm or xchacha20-poly1305"))),
+        }
+    }
+}
+
+impl fmt::Display for Cipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
     }
 }
 
 pub const KDF_SIZE: usize = 12;
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct KdfParams {
     pub memory_cost: u32,     // Argon2 m_cost (en KB)
     pub time_cost: u32,       // Argon2 t_cost (iterations)
@@ -36,48 +134,181 @@ impl Default for KdfParams {
     }
 }
 
+// Bounds of parameters read from a vault or set by the user
+pub const MIN_MEMORY_COST: u32 = 8 * 1024;          // 8 MiB
+pub const MAX_MEMORY_COST: u32 = 4 * 1024 * 1024;   // 4 GiB
+pub const MAX_TIME_COST: u32 = 64;
+pub const MAX_PARALLELISM: u32 = 64;
+// Calibration stops raising memory here and adds iterations instead
+const CALIBRATION_MAX_MEMORY: u32 = 1024 * 1024;    // 1 GiB
+
+impl KdfParams {
+    // What is unsafe or absurd about these parameters
+    pub fn check(&self) -> Result<(), String> {
+        if !(MIN_MEMORY_COST..=MAX_MEMORY_COST).contains(&self.memory_cost) {
+            return Err(format!("argon2 memory of {} KiB, expected {MIN_MEMORY_COST} to {MAX_MEMORY_COST}", self.memory_cost));
+        }
+        if !(1..=MAX_TIME_COST).contains(&self.time_cost) {
+            return Err(format!("argon2 time cost of {}, expected 1 to {MAX_TIME_COST}", self.time_cost));
+        }
+        if !(1..=MAX_PARALLELISM).contains(&self.parallelism) {
+            return Err(format!("argon2 parallelism of {}, expected 1 to {MAX_PARALLELISM}", self.parallelism));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for KdfParams {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "argon2id {} MiB, {} iterations, {} lanes", self.memory_cost / 1024, self.time_cost, self.parallelism)
+    }
+}
+
+// Time one key derivation with `params` on this host
+pub fn benchmark_kdf(params: &KdfParams) -> Result<Duration, ClipassError> {
+    let salt = generate_salt()?;
+    let start = Instant::now();
+    derive_key("benchmark", &salt, Some(params.clone()), None)?;
+    Ok(start.elapsed())
+}
+
+// Parameters taking about `target` to derive a key on this host, and their measured time.
+// Memory grows first, as it is what makes GPU attacks expensive, then iterations.
+pub fn calibrate_kdf(target: Duration) -> Result<(KdfParams, Duration), ClipassError> {
+    let parallelism = thread::available_parallelism()
+        .map_or(1, |n| n.get() as u32)
+        .min(KdfParams::default().parallelism);
+    let probe = KdfParams { memory_cost: MIN_MEMORY_COST, time_cost: 1, parallelism };
+    let scale = target.as_secs_f64() / benchmark_kdf(&probe)?.as_secs_f64().max(1e-6);
 
-pub fn derive_key(password: &str, salt: &SaltString, o_kdf_params: Option<KdfParams>) -> Result<(Key, KdfParams), ClipassError> {
-    let kdf_params = o_kdf_params.unwrap_or_else(|| KdfParams::default());
-    let argon2 = Argon2::new(
-        argon2::Algorithm::Argon2id,
-        argon2::Version::V0x13,
-        argon2::Params::new(kdf_params.memory_cost, kdf_params.time_cost, kdf_params.parallelism, None)?
-    );
+    let work = MIN_MEMORY_COST as f64 * scale; // KiB passes
+    // Clamped before the cast, a long target would overflow u32
+    let memory_mib = (work / 1024.0).clamp((MIN_MEMORY_COST / 1024) as f64, (CALIBRATION_MAX_MEMORY / 1024) as f64);
+    let memory_cost = memory_mib as u32 * 1024;
+    let time_cost = ((work / memory_cost as f64).round() as u32).clamp(1, MAX_TIME_COST);
+    let params = KdfParams { memory_cost, time_cost, parallelism };
+    let elapsed = benchmark_kdf(&params)?;
+    Ok((params, elapsed))
+}
 
-    let hash = argon2.hash_password(password.as_bytes(), salt)?;
-    let hash_output = hash.hash.ok_or(ClipassError::CryptoError("hash error".to_string()))?;
-    let key_bytes = hash_output.as_bytes();
+/// SHA-256 of a keyfile, mixed into Argon2 as its secret input so the
+/// password alone cannot derive the key
+#[derive(Clone)]
+pub struct Keyfile(LockedBytes);
 
-    let mut key_array = [0u8; 32];
-    let len = std::cmp::min(key_bytes.len(), 32);
-    key_array[..len].copy_from_slice(&key_bytes[..len]);
+impl Keyfile {
+    pub fn load(path: &Path) -> Result<Self, ClipassError> {
+        let mut file = File::open(path)
+            .map_err(|e| ClipassError::Io(format!("keyfile {}: {e}", path.display())))?;
+        let mut hasher = Sha256::new();
+        let mut chunk = Zeroizing::new([0u8; 8192]);
+        loop {
+            let read = file.read(chunk.as_mut_slice())?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&chunk[..read]);
+        }
+        Ok(Self(LockedBytes::from_slice(&hasher.finalize())))
+    }
 
-    let key = Key::new(*GenericArray::from_slice(&key_array));
+    pub fn from_bytes(data: &[u8]) -> Self {
+        Self(LockedBytes::from_slice(&Sha256::digest(data)))
+    }
+}
 
-    key_array.zeroize();
+pub fn derive_key(password: &str, salt: &SaltString, o_kdf_params: Option<KdfParams>, keyfile: Option<&Keyfile>)
+    -> Result<(Key, KdfParams), ClipassError>
+{
+    let kdf_params = o_kdf_params.unwrap_or_default();
+    let params = argon2::Params::new(kdf_params.memory_cost, kdf_params.time_cost, kdf_params.parallelism, None)?;
+    let argon2 = match keyfile {
+        None => Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params),
+        Some(keyfile) => Argon2::new_with_secret(
+            keyfile.0.as_slice(), argon2::Algorithm::Argon2id, argon2::Version::V0x13, params,
+        )?,
+    };
+
+    let mut salt_bytes = [0u8; SALT_SIZE];
+    let salt_bytes = salt.decode_b64(&mut salt_bytes)?;
+
+    // Hash straight into the key, PasswordHash would keep an unwiped copy
+    let mut key = Key::zeroed();
+    argon2.hash_password_into(password.as_bytes(), salt_bytes, key.0.as_mut_slice())?;
     Ok((key, kdf_params))
 }
 
-pub fn generate_nonce() -> Nonce<U12> {
-    let mut nonce_bytes = [0u8; 12];
-    thread_rng().fill_bytes(&mut nonce_bytes);
-    return Nonce::from(nonce_bytes);
+pub fn generate_salt() -> Result<SaltString, ClipassError> {
+    let mut salt_bytes = [0u8; SALT_SIZE];
+    thread_rng().fill_bytes(&mut salt_bytes);
+    Ok(SaltString::encode_b64(&salt_bytes)?)
 }
 
-pub fn encrypt_data(key: &Key, nonce: &Nonce<U12>, plaintext: &[u8], header_bytes: &[u8])
+pub fn generate_nonce(cipher: Cipher) -> Vec<u8> {
+    let mut nonce = vec![0u8; cipher.nonce_size()];
+    thread_rng().fill_bytes(&mut nonce);
+    nonce
+}
+
+fn check_nonce(cipher: Cipher, nonce: &[u8]) -> Result<(), ClipassError> {
+    match nonce.len() == cipher.nonce_size() {
+        true => Ok(()),
+        false => Err(ClipassError::CryptoError(format!("{cipher} nonce has a wrong size"))),
+    }
+}
+
+pub fn encrypt_data(cipher: Cipher, key: &Key, nonce: &[u8], plaintext: &[u8], header_bytes: &[u8])
     -> Result<Vec<u8>, ClipassError>
 {
-    let cipher = Aes256Gcm::new(&key.0);
-    let ciphertext = cipher.encrypt(&nonce, Payload { msg: plaintext, aad: header_bytes })?;
+    check_nonce(cipher, nonce)?;
+    let key = GenericArray::from_slice(key.0.as_slice());
+    let payload = Payload { msg: plaintext, aad: header_bytes };
+    let ciphertext = match cipher {
+        Cipher::Aes256Gcm => Aes256Gcm::new(key).encrypt(GenericArray::from_slice(nonce), payload)?,
+        Cipher::XChaCha20Poly1305 => XChaCha20Poly1305::new(key).encrypt(GenericArray::from_slice(nonce), payload)?,
+    };
     Ok(ciphertext)
 }
 
-pub fn decrypt_data(key: &Key, nonce: &Nonce<U12>, ciphertext: &[u8], header_bytes: &[u8])
-    -> Result<Vec<u8>, ClipassError>
+pub fn decrypt_data(cipher: Cipher, key: &Key, nonce: &[u8], ciphertext: &[u8], header_bytes: &[u8])
+    -> Result<LockedBytes, ClipassError>
 {
-    let cipher = Aes256Gcm::new(&key.0);
-    let nonce = GenericArray::from_slice(nonce);
-    let plaintext = cipher.decrypt(nonce, Payload { msg: ciphertext, aad: header_bytes })?;
+    check_nonce(cipher, nonce)?;
+    let key = GenericArray::from_slice(key.0.as_slice());
+    // Decrypted in place so the plaintext never leaves locked memory
+    let mut plaintext = LockedBytes::from_slice(ciphertext);
+    match cipher {
+        Cipher::Aes256Gcm => Aes256Gcm::new(key)
+            .decrypt_in_place(GenericArray::from_slice(nonce), header_bytes, &mut plaintext)?,
+        Cipher::XChaCha20Poly1305 => XChaCha20Poly1305::new(key)
+            .decrypt_in_place(GenericArray::from_slice(nonce), header_bytes, &mut plaintext)?,
+    }
     Ok(plaintext)
 }
+
+impl Buffer for LockedBytes {
+    fn extend_from_slice(&mut self, other: &[u8]) -> aes_gcm::aead::Result<()> {
+        LockedBytes::extend_from_slice(self, other);
+        Ok(())
+    }
+
+    fn truncate(&mut self, len: usize) {
+        LockedBytes::truncate(self, len)
+    }
+}
+
+pub const WRAPPED_KEY_SIZE: usize = KEY_SIZE + 16; // AES-GCM tag
+
+// Encrypt a key under another one, `aad` binding the metadata stored next to it.
+// Always AES-256-GCM: a slot cannot be rewrapped without its credential.
+pub fn wrap_key(kek: &Key, nonce: &[u8], key: &Key, aad: &[u8]) -> Result<Vec<u8>, ClipassError> {
+    encrypt_data(Cipher::Aes256Gcm, kek, nonce, key.0.as_slice(), aad)
+}
+
+pub fn unwrap_key(kek: &Key, nonce: &[u8], wrapped: &[u8], aad: &[u8]) -> Result<Key, ClipassError> {
+    let key = decrypt_data(Cipher::Aes256Gcm, kek, nonce, wrapped, aad)?;
+    if key.len() != KEY_SIZE {
+        return Err(ClipassError::CryptoError("wrapped key has a wrong size".to_string()));
+    }
+    Ok(Key(key))
+}
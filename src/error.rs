@@ -8,6 +8,8 @@ use std::time::SystemTimeError;
 #[derive(Debug)]
 pub enum ClipassError {
     NotFound(String),
+    VaultNotFound(String),
+    FieldNotFound(String, String),
     InvalidCommand(String),
     Io(String),
     IdExists(String),
@@ -18,12 +20,19 @@ pub enum ClipassError {
     SerdeError(String),
     TimeError(String),
     HeaderError(String),
+    Corrupted(String),
+    WrongPassword,
+    KeyfileRequired,
+    AgentError(String),
+    ConfigError(String),
 }
 
 impl fmt::Display for ClipassError {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
             ClipassError::NotFound(s) => write!(f, "unfindable entry {s}"),
+            ClipassError::VaultNotFound(path) => write!(f, "no vault at {path}"),
+            ClipassError::FieldNotFound(id, field) => write!(f, "unfindable field {field} in {id}"),
             ClipassError::InvalidCommand(cmd) => write!(f, "invalid command: {cmd}"),
             ClipassError::Io(err) => write!(f, "io error: {err}"),
             ClipassError::IdExists(id) => write!(f, "id exists already: {id}"),
@@ -34,12 +43,31 @@ impl fmt::Display for ClipassError {
             ClipassError::SerdeError(err) => write!(f, "serde error: {err}"),
             ClipassError::TimeError(err) => write!(f, "time error: {err}"),
             ClipassError::HeaderError(err) => write!(f, "header error: {err}"),
+            ClipassError::Corrupted(err) => write!(f, "corrupted vault: {err}"),
+            ClipassError::WrongPassword => write!(f, "wrong password"),
+            ClipassError::KeyfileRequired => write!(f, "this vault requires a keyfile (--keyfile <path>)"),
+            ClipassError::AgentError(err) => write!(f, "agent error: {err}"),
+            ClipassError::ConfigError(err) => write!(f, "config error: {err}"),
         }
     }
 }
 
 impl std::error::Error for ClipassError {}
 
+impl ClipassError {
+    // Process exit code used by the non-interactive mode
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            ClipassError::InvalidCommand(_) | ClipassError::Input(_) => 2,
+            ClipassError::NotFound(_) | ClipassError::FieldNotFound(_, _) | ClipassError::VaultNotFound(_) => 3,
+            ClipassError::WrongPassword | ClipassError::KeyfileRequired => 4,
+            ClipassError::AgentError(_) => 5,
+            ClipassError::Corrupted(_) | ClipassError::HeaderError(_) => 6,
+            _ => 1,
+        }
+    }
+}
+
 impl From<std::io::Error> for ClipassError {
     fn from(value: Error) -> Self {
         ClipassError::Io(format!("{value}"))
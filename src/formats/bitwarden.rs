@@ -0,0 +1,183 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+use serde::Deserialize;
+use zeroize::Zeroizing;
+use crate::error::ClipassError;
+use crate::formats::csv::Table;
+use crate::formats::{entry_id, set_custom, set_otp, Import};
+use crate::secret::SecretString;
+use crate::vault::entry::{Entry, FIELD_NOTES, FIELD_PASSWORD, FIELD_URL, FIELD_USERNAME};
+
+/*
+  Bitwarden unencrypted exports, JSON or CSV (told apart by the first
+  character). Logins keep username, password, uris, notes and totp, custom
+  fields become custom fields. Cards, identities and SSH keys have their
+  values as custom fields. Items are filed under their folder, or their
+  first collection in organization exports.
+  Encrypted exports are refused: export again as "json" or "csv".
+*/
+
+const TYPE_LOGIN: u8 = 1;
+const FIELD_TYPE_LINKED: u8 = 3;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Export {
+    #[serde(default)]
+    encrypted: bool,
+    #[serde(default)]
+    password_protected: bool,
+    #[serde(default)]
+    folders: Vec<Folder>,
+    #[serde(default)]
+    collections: Vec<Folder>,
+    #[serde(default)]
+    items: Vec<Item>,
+}
+
+#[derive(Deserialize)]
+struct Folder {
+    id: String,
+    name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Item {
+    #[serde(rename = "type")]
+    kind: u8,
+    #[serde(default)]
+    name: String,
+    folder_id: Option<String>,
+    collection_ids: Option<Vec<String>>,
+    notes: Option<SecretString>,
+    fields: Option<Vec<Field>>,
+    login: Option<Login>,
+    // Values of cards, identities and SSH keys
+    card: Option<BTreeMap<String, Option<SecretString>>>,
+    identity: Option<BTreeMap<String, Option<SecretString>>>,
+    ssh_key: Option<BTreeMap<String, Option<SecretString>>>,
+}
+
+#[derive(Deserialize)]
+struct Field {
+    name: Option<String>,
+    value: Option<SecretString>,
+    #[serde(rename = "type", default)]
+    kind: u8,
+}
+
+#[derive(Deserialize)]
+struct Login {
+    uris: Option<Vec<Uri>>,
+    username: Option<SecretString>,
+    password: Option<SecretString>,
+    totp: Option<SecretString>,
+}
+
+#[derive(Deserialize)]
+struct Uri {
+    uri: Option<SecretString>,
+}
+
+pub fn read(path: &Path) -> Result<Import, ClipassError> {
+    let text = Zeroizing::new(fs::read_to_string(path)?);
+    match text.trim_start_matches('\u{feff}').trim_start().starts_with('{') {
+        true => parse_json(&text),
+        false => parse_csv(&text),
+    }
+}
+
+pub fn parse_json(text: &str) -> Result<Import, ClipassError> {
+    let export: Export = serde_json::from_str(text.trim_start_matches('\u{feff}'))
+        .map_err(|e| ClipassError::Input(format!("bitwarden export: {e}")))?;
+    if export.encrypted || export.password_protected {
+        return Err(ClipassError::Input(
+            "bitwarden export: encrypted exports cannot be read, export as json or csv".to_string()));
+    }
+    let folders: HashMap<&str, &str> = export.folders.iter().chain(&export.collections)
+        .map(|f| (f.id.as_str(), f.name.as_str()))
+        .collect();
+
+    let mut import = Import::default();
+    for item in &export.items {
+        let folder = item.folder_id.as_deref()
+            .or(item.collection_ids.as_ref().and_then(|ids| ids.first()).map(String::as_str))
+            .and_then(|id| folders.get(id))
+            .copied()
+            .unwrap_or_default();
+        let id = entry_id(&[folder, &item.name]);
+        let mut entry = Entry::new();
+        if let Some(login) = &item.login {
+            set(&mut entry, FIELD_USERNAME, login.username.as_ref())?;
+            set(&mut entry, FIELD_PASSWORD, login.password.as_ref())?;
+            let uris: Vec<&str> = login.uris.iter().flatten()
+                .filter_map(|u| u.uri.as_ref().map(SecretString::expose))
+                .collect();
+            entry.set_field(FIELD_URL, &uris.join(" "))?;
+            if let Some(totp) = &login.totp {
+                set_otp(&mut entry, &id, totp.expose(), &mut import.warnings)?;
+            }
+        } else if item.kind == TYPE_LOGIN {
+            import.warnings.push(format!("{id}: login without login data"));
+        }
+        set(&mut entry, FIELD_NOTES, item.notes.as_ref())?;
+        for values in [&item.card, &item.identity, &item.ssh_key].into_iter().flatten() {
+            for (name, value) in values {
+                if let Some(value) = value {
+                    set_custom(&mut entry, name, value.expose())?;
+                }
+            }
+        }
+        for field in item.fields.iter().flatten().filter(|f| f.kind != FIELD_TYPE_LINKED) {
+            if let Some(value) = &field.value {
+                set_custom(&mut entry, field.name.as_deref().unwrap_or_default(), value.expose())?;
+            }
+        }
+        import.entries.push((id, entry));
+    }
+    Ok(import)
+}
+
+// folder,favorite,type,name,notes,fields,reprompt,login_uri,login_username,login_password,login_totp
+// with "collections" instead of "folder" in organization exports
+pub fn parse_csv(text: &str) -> Result<Import, ClipassError> {
+    let table = Table::parse(text)?;
+    if table.column("name").is_none() || table.column("type").is_none() {
+        return Err(ClipassError::Input("bitwarden export: missing name or type column".to_string()));
+    }
+    let mut import = Import::default();
+    for row in &table.rows {
+        let folder = match table.get(row, "folder") {
+            "" => table.get(row, "collections").split(',').next().unwrap_or_default(),
+            folder => folder,
+        };
+        let id = entry_id(&[folder, table.get(row, "name")]);
+        let kind = table.get(row, "type");
+        if kind != "login" && kind != "note" {
+            import.warnings.push(format!("{id}: unsupported item type '{kind}', skipped"));
+            continue;
+        }
+        let mut entry = Entry::new();
+        entry.set_field(FIELD_USERNAME, table.get(row, "login_username"))?;
+        entry.set_field(FIELD_PASSWORD, table.get(row, "login_password"))?;
+        entry.set_field(FIELD_URL, &table.get(row, "login_uri").replace(',', " "))?;
+        entry.set_field(FIELD_NOTES, table.get(row, "notes"))?;
+        let totp = table.get(row, "login_totp");
+        if !totp.is_empty() {
+            set_otp(&mut entry, &id, totp, &mut import.warnings)?;
+        }
+        // One "name: value" per line
+        for line in table.get(row, "fields").lines() {
+            let (name, value) = line.split_once(": ").unwrap_or((line, ""));
+            set_custom(&mut entry, name, value)?;
+        }
+        import.entries.push((id, entry));
+    }
+    Ok(import)
+}
+
+fn set(entry: &mut Entry, field: &str, value: Option<&SecretString>) -> Result<(), ClipassError> {
+    entry.set_field(field, value.map_or("", SecretString::expose))
+}
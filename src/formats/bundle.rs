@@ -0,0 +1,46 @@
+use std::path::Path;
+use crate::crypto::{Cipher, KdfParams};
+use crate::error::ClipassError;
+use crate::formats::Import;
+use crate::vault::entry::Entry;
+use crate::vault::vault::Vault;
+
+/*
+  Encrypted bundle: a vault file holding only the exported entries, with a
+  single key slot for its own passphrase, so entries can be handed over
+  without the vault password. Being a plain vault it can also be opened
+  with clipass directly, and any vault can be imported as a bundle.
+  No backups are kept and an existing file is never replaced.
+*/
+
+// Write entries into a new bundle, returns their count
+pub fn export<'a, I>(entries: I, path: &Path, passphrase: &str, kdf: KdfParams, cipher: Cipher)
+    -> Result<usize, ClipassError>
+where
+    I: IntoIterator<Item = (&'a String, &'a Entry)>,
+{
+    if path.exists() {
+        return Err(ClipassError::Input(format!("bundle: {} exists already, not replaced", path.display())));
+    }
+    let mut bundle = Vault::new_empty_with_kdf(passphrase, kdf)?;
+    bundle.set_cipher(cipher);
+    bundle.set_backup_count(0);
+    for (id, entry) in entries {
+        bundle.new_entry(id, entry.clone())?;
+    }
+    let path = path.to_str()
+        .ok_or(ClipassError::Input(format!("bundle: {} is not a valid path", path.display())))?;
+    bundle.crypt_to_file(path)?;
+    Ok(bundle.get_all().len())
+}
+
+pub fn read(path: &Path, passphrase: &str) -> Result<Import, ClipassError> {
+    let path = path.to_str()
+        .ok_or(ClipassError::Input(format!("bundle: {} is not a valid path", path.display())))?;
+    let bundle = Vault::load_from_file(passphrase, path)?;
+    let mut entries: Vec<(String, Entry)> = bundle.get_all().iter()
+        .map(|(id, entry)| (id.clone(), entry.clone()))
+        .collect();
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(Import { entries, warnings: Vec::new() })
+}
@@ -0,0 +1,291 @@
+use std::collections::BTreeSet;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+use std::str::FromStr;
+use zeroize::Zeroizing;
+use crate::error::ClipassError;
+use crate::formats::{entry_id, free_id, set_custom, set_otp, Import};
+use crate::secret::SecretString;
+use crate::vault::entry::{Entry, FIELD_NOTES, FIELD_OTP, FIELD_PASSWORD, FIELD_URL, FIELD_USERNAME};
+
+/*
+  RFC 4180 CSV: comma separated fields, CRLF or LF line ends, fields with
+  commas, quotes or line breaks between double quotes, a quote doubled
+  inside them. The first record names the columns, matched ignoring case,
+  spaces, dashes and underscores ("Login Name" is login_name).
+
+  Generic import and export map a column to each field, by default the
+  first one found among:
+    - name     : name, title                       (the entry id)
+    - url      : url, uri, website, login_uri      (urls space separated)
+    - username : username, login, user, login_username, email
+    - password : password, login_password
+    - notes    : notes, note, comments
+    - otp      : otp, totp, otpauth, login_totp
+  "--map name=Account,url=Site" gives other columns. The remaining columns
+  are custom fields, rows without a name are named after their url host.
+  Export writes these columns then one per custom field, in clear: the file
+  is readable by its owner only and never replaces an existing one.
+*/
+
+const NAME: &str = "name";
+
+// Fields a column maps to, with the columns looked for by default
+const COLUMNS: &[(&str, &[&str])] = &[
+    (NAME, &["name", "title"]),
+    (FIELD_URL, &["url", "uri", "website", "login_uri"]),
+    (FIELD_USERNAME, &["username", "login", "user", "login_username", "email"]),
+    (FIELD_PASSWORD, &["password", "login_password"]),
+    (FIELD_NOTES, &["notes", "note", "comments"]),
+    (FIELD_OTP, &["otp", "totp", "otpauth", "login_totp"]),
+];
+
+/// Shown before writing a plaintext export
+pub const PLAINTEXT_WARNING: &str = "\
+    *** WARNING ***\n\
+    the export holds the selected passwords in clear, anyone able to read the\n\
+    file can use them: keep it off synced folders and delete it once imported";
+
+/// Columns given with --map, the other fields use the default ones
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CsvMapping(Vec<(String, String)>);
+
+impl CsvMapping {
+    fn given(&self, field: &str) -> Option<&str> {
+        self.0.iter().find(|(f, _)| f == field).map(|(_, column)| column.as_str())
+    }
+}
+
+// field=column,field=column
+impl FromStr for CsvMapping {
+    type Err = ClipassError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut columns: Vec<(String, String)> = Vec::new();
+        for pair in s.split(',').filter(|pair| !pair.is_empty()) {
+            let Some((field, column)) = pair.split_once('=').filter(|(_, column)| !column.is_empty()) else {
+                return Err(ClipassError::InvalidCommand(format!("invalid column mapping '{pair}', expected field=column")));
+            };
+            if !COLUMNS.iter().any(|(f, _)| *f == field) {
+                return Err(ClipassError::InvalidCommand(format!(
+                    "unknown csv field '{field}', expected name, url, username, password, notes or otp")));
+            }
+            columns.retain(|(f, _)| f != field);
+            columns.push((field.to_string(), column.to_string()));
+        }
+        Ok(Self(columns))
+    }
+}
+
+pub fn read(path: &Path, mapping: &CsvMapping) -> Result<Import, ClipassError> {
+    let text = Zeroizing::new(fs::read_to_string(path)?);
+    parse(&text, mapping)
+}
+
+pub fn parse(text: &str, mapping: &CsvMapping) -> Result<Import, ClipassError> {
+    let table = Table::parse(text)?;
+    let mut mapped: Vec<(&str, usize)> = Vec::new();
+    for (field, defaults) in COLUMNS {
+        let index = match mapping.given(field) {
+            Some(column) => Some(table.column(column)
+                .ok_or_else(|| ClipassError::Input(format!("csv: no column '{column}' for the {field}")))?),
+            None => defaults.iter().find_map(|column| table.column(column)),
+        };
+        mapped.extend(index.map(|index| (*field, index)));
+    }
+    if !mapped.iter().any(|(field, _)| *field == FIELD_PASSWORD) {
+        return Err(ClipassError::Input("csv: no password column, give it with --map password=<column>".to_string()));
+    }
+
+    let mut import = Import::default();
+    for row in &table.rows {
+        let value = |field: &str| mapped.iter()
+            .find(|(f, _)| *f == field)
+            .and_then(|(_, index)| row.get(*index))
+            .map_or("", SecretString::expose);
+        let id = match value(NAME).trim() {
+            "" => entry_id(&[host(value(FIELD_URL))]),
+            name => entry_id(&[name]),
+        };
+        let mut entry = Entry::new();
+        for field in [FIELD_USERNAME, FIELD_PASSWORD, FIELD_URL, FIELD_NOTES] {
+            entry.set_field(field, value(field))?;
+        }
+        if !value(FIELD_OTP).is_empty() {
+            set_otp(&mut entry, &id, value(FIELD_OTP), &mut import.warnings)?;
+        }
+        for (index, cell) in row.iter().enumerate() {
+            if let Some(column) = table.header.get(index) && !mapped.iter().any(|(_, i)| *i == index) {
+                set_custom(&mut entry, column, cell.expose())?;
+            }
+        }
+        import.entries.push((id, entry));
+    }
+    Ok(import)
+}
+
+// Host of the first url
+fn host(urls: &str) -> &str {
+    let url = urls.split_whitespace().next().unwrap_or_default();
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    rest.split(['/', ':', '?', '#']).next().unwrap_or_default()
+}
+
+// Write entries sorted by id into a new file, returns their count
+pub fn export<'a, I>(entries: I, path: &Path, mapping: &CsvMapping) -> Result<usize, ClipassError>
+where
+    I: IntoIterator<Item = (&'a String, &'a Entry)>,
+{
+    let mut entries: Vec<_> = entries.into_iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    let mut header: Vec<String> = COLUMNS.iter()
+        .map(|(field, defaults)| mapping.given(field).unwrap_or(defaults[0]).to_string())
+        .collect();
+    let custom: BTreeSet<&str> = entries.iter()
+        .flat_map(|(_, entry)| entry.custom().keys().map(String::as_str))
+        .collect();
+    for name in &custom {
+        let taken = |column: &str| header.iter().any(|h| same_column(h, column));
+        let column = if taken(name) { free_id(name, taken) } else { name.to_string() };
+        header.push(column);
+    }
+
+    let mut content = Zeroizing::new(String::new());
+    write_record(&mut content, header.iter().map(String::as_str));
+    for (id, entry) in &entries {
+        let urls = entry.urls().join(" ");
+        let standard = [
+            id.as_str(),
+            &urls,
+            entry.username().unwrap_or_default(),
+            entry.password().unwrap_or_default(),
+            entry.notes().unwrap_or_default(),
+            entry.field(FIELD_OTP).unwrap_or_default(),
+        ];
+        let custom = custom.iter().map(|name| entry.field(name).unwrap_or_default());
+        write_record(&mut content, standard.into_iter().chain(custom));
+    }
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path).map_err(|e| match e.kind() {
+        ErrorKind::AlreadyExists => ClipassError::Input(format!("csv: {} exists already, not replaced", path.display())),
+        _ => e.into(),
+    })?;
+    file.write_all(content.as_bytes())?;
+    file.sync_all()?;
+    Ok(entries.len())
+}
+
+// Fields quoted when they hold a comma, a quote or a line break
+fn write_record<'a>(out: &mut String, fields: impl IntoIterator<Item = &'a str>) {
+    for (i, field) in fields.into_iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        if !field.contains([',', '"', '\r', '\n']) {
+            out.push_str(field);
+            continue;
+        }
+        out.push('"');
+        for c in field.chars() {
+            if c == '"' {
+                out.push('"');
+            }
+            out.push(c);
+        }
+        out.push('"');
+    }
+    out.push_str("\r\n");
+}
+
+pub(crate) struct Table {
+    header: Vec<String>,
+    pub rows: Vec<Vec<SecretString>>,
+}
+
+impl Table {
+    pub fn parse(text: &str) -> Result<Self, ClipassError> {
+        let mut records = parse_records(text.strip_prefix('\u{feff}').unwrap_or(text))?.into_iter();
+        let header = records.next()
+            .ok_or(ClipassError::Input("csv: empty file".to_string()))?
+            .iter()
+            .map(|name| name.expose().trim().to_string())
+            .collect();
+        Ok(Self { header, rows: records.collect() })
+    }
+
+    pub fn column(&self, name: &str) -> Option<usize> {
+        self.header.iter().position(|h| same_column(h, name))
+    }
+
+    // Value of a column in a row, empty when the column or the value is missing
+    pub fn get<'a>(&self, row: &'a [SecretString], name: &str) -> &'a str {
+        self.column(name)
+            .and_then(|index| row.get(index))
+            .map_or("", SecretString::expose)
+    }
+}
+
+fn parse_records(text: &str) -> Result<Vec<Vec<SecretString>>, ClipassError> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if field.is_empty() => {
+                let start = line;
+                loop {
+                    match chars.next() {
+                        Some('"') if chars.peek() == Some(&'"') => {
+                            chars.next();
+                            field.push('"');
+                        },
+                        Some('"') => break,
+                        Some(c) => {
+                            line += usize::from(c == '\n');
+                            field.push(c);
+                        },
+                        None => return Err(ClipassError::Input(format!("csv line {start}: unterminated quote"))),
+                    }
+                }
+            },
+            ',' => record.push(SecretString::new(std::mem::take(&mut field))),
+            '\r' if chars.peek() == Some(&'\n') => {},
+            '\n' => {
+                line += 1;
+                record.push(SecretString::new(std::mem::take(&mut field)));
+                end_record(&mut records, std::mem::take(&mut record));
+            },
+            c => field.push(c),
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(SecretString::new(field));
+        end_record(&mut records, record);
+    }
+    Ok(records)
+}
+
+// Blank lines are no records
+fn end_record(records: &mut Vec<Vec<SecretString>>, record: Vec<SecretString>) {
+    if record.len() > 1 || record.first().is_some_and(|f| !f.is_empty()) {
+        records.push(record);
+    }
+}
+
+fn same_column(a: &str, b: &str) -> bool {
+    let letters = |s: &str| s.chars()
+        .filter(|c| !matches!(c, ' ' | '-' | '_'))
+        .flat_map(char::to_lowercase)
+        .collect::<String>();
+    letters(a) == letters(b)
+}
@@ -0,0 +1,525 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use aes::Aes256;
+use aes::cipher::{BlockDecryptMut, BlockEncrypt, KeyInit, KeyIvInit, StreamCipher};
+use aes::cipher::block_padding::Pkcs7;
+use argon2::Argon2;
+use base64ct::{Base64, Encoding};
+use chacha20::ChaCha20;
+use flate2::read::GzDecoder;
+use hmac::{Hmac, Mac};
+use roxmltree::{Document, Node, NodeId};
+use salsa20::Salsa20;
+use sha2::{Digest, Sha256, Sha512};
+use zeroize::Zeroizing;
+use crate::crypto::{KdfParams, MAX_MEMORY_COST, MAX_PARALLELISM, MAX_TIME_COST};
+use crate::error::ClipassError;
+use crate::formats::{entry_id, set_custom, set_otp, Import};
+use crate::secret::SecretString;
+use crate::vault::entry::{Entry, FIELD_NOTES, FIELD_PASSWORD, FIELD_URL, FIELD_USERNAME};
+
+/*
+              *** KEEPASS KDBX 4 ***
+*****************************************
+  - Signatures {8}, version {4}     : minor u16, major u16 (4)
+  - Header fields                   : id u8, size u32, data, up to id 0
+      2 cipher uuid, 3 compression, 4 master seed, 7 iv, 11 kdf parameters
+  - SHA-256 of the header {32}
+  - HMAC-SHA-256 of the header {32}
+  - HMAC blocks                     : hmac {32}, size u32, data, up to size 0
+The blocks hold the encrypted (AES-256-CBC or ChaCha20), optionally gzipped,
+inner header (random stream, attachments) followed by the XML database.
+
+Key: SHA-256 of SHA-256(password) and the keyfile key, through Argon2d,
+Argon2id or AES-KDF. Protected XML values are xored with the inner random
+stream (ChaCha20 or Salsa20) in document order.
+
+Entries are filed under their group path, the root group and the recycle
+bin left out. Previous passwords from the history are kept as custom
+fields ("old-password", "old-password-2"...), text attachments too,
+binary attachments are reported and skipped.
+****************************************
+*/
+
+const SIGNATURE: [u8; 8] = [0x03, 0xd9, 0xa2, 0x9a, 0x67, 0xfb, 0x4b, 0xb5];
+const MAJOR_VERSION: u16 = 4;
+
+const CIPHER_AES256: [u8; 16] = uuid(0x31c1f2e6_bf71_4350_be58_05216afc5aff);
+const CIPHER_CHACHA20: [u8; 16] = uuid(0xd6038a2b_8b6f_4cb5_a524_339a31dbb59a);
+const KDF_AES: [u8; 16] = uuid(0xc9d9f39a_628a_4460_bf74_0d08c18a4fea);
+const KDF_ARGON2D: [u8; 16] = uuid(0xef636ddf_8c29_444b_91f7_a9a403e30a0c);
+const KDF_ARGON2ID: [u8; 16] = uuid(0x9e298b19_56db_4773_b23d_fc3ec6f0a1e6);
+
+const HEADER_END: u8 = 0;
+const HEADER_CIPHER: u8 = 2;
+const HEADER_COMPRESSION: u8 = 3;
+const HEADER_MASTER_SEED: u8 = 4;
+const HEADER_IV: u8 = 7;
+const HEADER_KDF: u8 = 11;
+
+const INNER_STREAM_ID: u8 = 1;
+const INNER_STREAM_KEY: u8 = 2;
+const INNER_BINARY: u8 = 3;
+const STREAM_SALSA20: u32 = 2;
+const STREAM_CHACHA20: u32 = 3;
+const SALSA20_NONCE: [u8; 8] = [0xe8, 0x30, 0x09, 0x4b, 0x97, 0x20, 0x5d, 0x2a];
+
+// Enough for any sane database, refuses files asking for hours of AES-KDF
+const MAX_AES_ROUNDS: u64 = 1 << 30;
+
+const fn uuid(value: u128) -> [u8; 16] {
+    value.to_be_bytes()
+}
+
+enum InnerStream {
+    ChaCha20(ChaCha20),
+    Salsa20(Salsa20),
+}
+
+impl InnerStream {
+    fn apply(&mut self, data: &mut [u8]) {
+        match self {
+            InnerStream::ChaCha20(cipher) => cipher.apply_keystream(data),
+            InnerStream::Salsa20(cipher) => cipher.apply_keystream(data),
+        }
+    }
+}
+
+// Decrypted payload: inner random stream, attachments and XML
+struct Database {
+    stream: InnerStream,
+    binaries: Vec<Zeroizing<Vec<u8>>>,
+    xml: Zeroizing<Vec<u8>>,
+}
+
+// Little endian reads, a short file being an error
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn bytes(&mut self, size: usize) -> Result<&'a [u8], ClipassError> {
+        let bytes = self.data.get(self.position..self.position.saturating_add(size))
+            .ok_or(keepass_error("truncated file"))?;
+        self.position += size;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, ClipassError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ClipassError> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap_or_default()))
+    }
+
+    fn u32(&mut self) -> Result<u32, ClipassError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap_or_default()))
+    }
+
+    // Type, size and data fields up to the end field
+    fn fields(&mut self) -> Result<Vec<(u8, &'a [u8])>, ClipassError> {
+        let mut fields = Vec::new();
+        loop {
+            let id = self.u8()?;
+            let size = self.u32()? as usize;
+            let data = self.bytes(size)?;
+            if id == HEADER_END {
+                return Ok(fields);
+            }
+            fields.push((id, data));
+        }
+    }
+}
+
+// An empty password with a keyfile is taken as no password, like KeePassXC
+pub fn read(path: &Path, password: &str, keyfile: Option<&Path>) -> Result<Import, ClipassError> {
+    let data = Zeroizing::new(fs::read(path)?);
+    let keyfile = keyfile.map(load_keyfile).transpose()?;
+    let mut composite = Sha256::new();
+    if !password.is_empty() || keyfile.is_none() {
+        composite.update(Sha256::digest(password.as_bytes()));
+    }
+    if let Some(keyfile) = &keyfile {
+        composite.update(keyfile.as_slice());
+    }
+    let composite = Zeroizing::new(composite.finalize().to_vec());
+    let mut database = decrypt(&data, &composite)?;
+    parse_xml(&mut database)
+}
+
+fn decrypt(data: &[u8], composite: &[u8]) -> Result<Database, ClipassError> {
+    let mut reader = Reader::new(data);
+    if reader.bytes(SIGNATURE.len()).ok() != Some(SIGNATURE.as_slice()) {
+        return Err(keepass_error("not a keepass database"));
+    }
+    let _minor = reader.u16()?;
+    match reader.u16()? {
+        MAJOR_VERSION => {},
+        3 => return Err(keepass_error("KDBX 3 databases are not supported, save it as KDBX 4 first")),
+        major => return Err(keepass_error(&format!("unsupported version {major}"))),
+    }
+
+    let fields: HashMap<u8, &[u8]> = reader.fields()?.into_iter().collect();
+    let header = &data[..reader.position];
+    if reader.bytes(32)? != Sha256::digest(header).as_slice() {
+        return Err(keepass_error("corrupted header"));
+    }
+    let field = |id: u8, name: &str| fields.get(&id).copied()
+        .ok_or(keepass_error(&format!("missing {name} in the header")));
+    let master_seed = field(HEADER_MASTER_SEED, "master seed")?;
+    let transformed = transform_key(composite, field(HEADER_KDF, "key derivation")?)?;
+
+    let mut hasher = Sha512::new();
+    hasher.update(master_seed);
+    hasher.update(transformed.as_slice());
+    hasher.update([1u8]);
+    let hmac_key = Zeroizing::new(hasher.finalize().to_vec());
+    if !hmac_matches(&hmac_key, u64::MAX, &[header], reader.bytes(32)?)? {
+        return Err(ClipassError::WrongPassword);
+    }
+
+    // HMAC blocks, an empty block ends the stream
+    let mut encrypted = Vec::new();
+    for index in 0u64.. {
+        let hmac = reader.bytes(32)?;
+        let size_bytes = reader.bytes(4)?;
+        let size = u32::from_le_bytes(size_bytes.try_into().unwrap_or_default()) as usize;
+        let block = reader.bytes(size)?;
+        if !hmac_matches(&hmac_key, index, &[&index.to_le_bytes(), size_bytes, block], hmac)? {
+            return Err(keepass_error(&format!("corrupted block {index}")));
+        }
+        if size == 0 {
+            break;
+        }
+        encrypted.extend_from_slice(block);
+    }
+
+    let mut hasher = Sha256::new();
+    hasher.update(master_seed);
+    hasher.update(transformed.as_slice());
+    let key = Zeroizing::new(hasher.finalize().to_vec());
+    let iv = field(HEADER_IV, "iv")?;
+    let cipher = field(HEADER_CIPHER, "cipher")?;
+    let invalid_key = |_| keepass_error("invalid key or iv size");
+    let mut payload = Zeroizing::new(if cipher == CIPHER_AES256 {
+        cbc::Decryptor::<Aes256>::new_from_slices(&key, iv).map_err(invalid_key)?
+            .decrypt_padded_vec_mut::<Pkcs7>(&encrypted)
+            .map_err(|_| keepass_error("invalid padding"))?
+    } else if cipher == CIPHER_CHACHA20 {
+        ChaCha20::new_from_slices(&key, iv).map_err(invalid_key)?.apply_keystream(&mut encrypted);
+        encrypted
+    } else {
+        return Err(keepass_error("unsupported cipher, only AES-256 and ChaCha20 are"));
+    });
+    if field(HEADER_COMPRESSION, "compression")? != [0, 0, 0, 0] {
+        let mut inflated = Zeroizing::new(Vec::new());
+        GzDecoder::new(payload.as_slice()).read_to_end(&mut inflated)
+            .map_err(|e| keepass_error(&format!("invalid compressed data: {e}")))?;
+        payload = inflated;
+    }
+    read_inner(&payload)
+}
+
+fn read_inner(payload: &[u8]) -> Result<Database, ClipassError> {
+    let mut reader = Reader::new(payload);
+    let (mut stream_id, mut stream_key, mut binaries) = (None, None, Vec::new());
+    for (id, data) in reader.fields()? {
+        match id {
+            INNER_STREAM_ID => stream_id = data.try_into().ok().map(u32::from_le_bytes),
+            INNER_STREAM_KEY => stream_key = Some(data),
+            // A flags byte first
+            INNER_BINARY if !data.is_empty() => binaries.push(Zeroizing::new(data[1..].to_vec())),
+            _ => {},
+        }
+    }
+    let stream_key = stream_key.ok_or(keepass_error("missing inner stream key"))?;
+    let stream = match stream_id {
+        Some(STREAM_CHACHA20) => {
+            let hash = Zeroizing::new(Sha512::digest(stream_key).to_vec());
+            InnerStream::ChaCha20(ChaCha20::new_from_slices(&hash[..32], &hash[32..44])
+                .map_err(|_| keepass_error("invalid inner stream key"))?)
+        },
+        Some(STREAM_SALSA20) => {
+            let hash = Zeroizing::new(Sha256::digest(stream_key).to_vec());
+            InnerStream::Salsa20(Salsa20::new_from_slices(&hash, &SALSA20_NONCE)
+                .map_err(|_| keepass_error("invalid inner stream key"))?)
+        },
+        _ => return Err(keepass_error("unsupported inner stream")),
+    };
+    let xml = Zeroizing::new(payload[reader.position..].to_vec());
+    Ok(Database { stream, binaries, xml })
+}
+
+fn transform_key(composite: &[u8], kdf: &[u8]) -> Result<Zeroizing<Vec<u8>>, ClipassError> {
+    let params = variant_dictionary(kdf)?;
+    let bytes = |name: &str| params.get(name).copied()
+        .ok_or(keepass_error(&format!("missing key derivation parameter {name}")));
+    let number = |name: &str| -> Result<u64, ClipassError> {
+        match bytes(name)? {
+            [a, b, c, d] => Ok(u32::from_le_bytes([*a, *b, *c, *d]) as u64),
+            value => Ok(u64::from_le_bytes(value.try_into()
+                .map_err(|_| keepass_error(&format!("invalid key derivation parameter {name}")))?)),
+        }
+    };
+    let kdf_uuid = bytes("$UUID")?;
+    let mut key = Zeroizing::new(vec![0u8; 32]);
+    if kdf_uuid == KDF_ARGON2D || kdf_uuid == KDF_ARGON2ID {
+        // Memory in bytes, bounded like clipass' own key derivation
+        let kdf = KdfParams {
+            memory_cost: u32::try_from(number("M")? / 1024).unwrap_or(u32::MAX),
+            time_cost: u32::try_from(number("I")?).unwrap_or(u32::MAX),
+            parallelism: u32::try_from(number("P")?).unwrap_or(u32::MAX),
+        };
+        if kdf.memory_cost > MAX_MEMORY_COST || kdf.time_cost > MAX_TIME_COST || kdf.parallelism > MAX_PARALLELISM {
+            return Err(keepass_error(&format!("key derivation too expensive: {kdf}")));
+        }
+        let algorithm = match kdf_uuid == KDF_ARGON2D {
+            true => argon2::Algorithm::Argon2d,
+            false => argon2::Algorithm::Argon2id,
+        };
+        let version = match number("V")? {
+            0x10 => argon2::Version::V0x10,
+            _ => argon2::Version::V0x13,
+        };
+        let params = argon2::Params::new(kdf.memory_cost, kdf.time_cost, kdf.parallelism, Some(32))?;
+        Argon2::new(algorithm, version, params).hash_password_into(composite, bytes("S")?, &mut key)?;
+    } else if kdf_uuid == KDF_AES {
+        let rounds = number("R")?;
+        if rounds > MAX_AES_ROUNDS {
+            return Err(keepass_error(&format!("key derivation too expensive: {rounds} AES rounds")));
+        }
+        let cipher = Aes256::new_from_slice(bytes("S")?).map_err(|_| keepass_error("invalid AES-KDF seed"))?;
+        let mut blocks = Zeroizing::new(composite.to_vec());
+        for _ in 0..rounds {
+            for block in blocks.chunks_exact_mut(16) {
+                cipher.encrypt_block(block.into());
+            }
+        }
+        key.copy_from_slice(&Sha256::digest(blocks.as_slice()));
+    } else {
+        return Err(keepass_error("unsupported key derivation"));
+    }
+    Ok(key)
+}
+
+// version u16, then type u8, name size u32, name, value size u32, value, up to type 0
+fn variant_dictionary(data: &[u8]) -> Result<HashMap<String, &[u8]>, ClipassError> {
+    let mut reader = Reader::new(data);
+    if reader.u16()? >> 8 != 1 {
+        return Err(keepass_error("unsupported parameters version"));
+    }
+    let mut values = HashMap::new();
+    while reader.u8()? != 0 {
+        let size = reader.u32()? as usize;
+        let name = String::from_utf8_lossy(reader.bytes(size)?).to_string();
+        let size = reader.u32()? as usize;
+        values.insert(name, reader.bytes(size)?);
+    }
+    Ok(values)
+}
+
+fn hmac_matches(hmac_key: &[u8], index: u64, parts: &[&[u8]], expected: &[u8]) -> Result<bool, ClipassError> {
+    let mut hasher = Sha512::new();
+    hasher.update(index.to_le_bytes());
+    hasher.update(hmac_key);
+    let block_key = Zeroizing::new(hasher.finalize().to_vec());
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&block_key)
+        .map_err(|_| keepass_error("invalid hmac key"))?;
+    for part in parts {
+        mac.update(part);
+    }
+    Ok(mac.verify_slice(expected).is_ok())
+}
+
+// XML keyfiles (v1 base64, v2 hex), 32 raw bytes, 64 hex characters, or any file hashed
+fn load_keyfile(path: &Path) -> Result<Zeroizing<Vec<u8>>, ClipassError> {
+    let data = Zeroizing::new(fs::read(path)?);
+    if let Ok(text) = std::str::from_utf8(&data) {
+        if let Ok(document) = Document::parse(text)
+            && let Some(key) = document.descendants().find(|n| n.has_tag_name("Data"))
+        {
+            let value: String = key.text().unwrap_or_default().split_whitespace().collect();
+            let version = document.descendants().find(|n| n.has_tag_name("Version"))
+                .and_then(|n| n.text()).unwrap_or_default();
+            let key = match version.starts_with("2.") {
+                true => hex_decode(&value),
+                false => Base64::decode_vec(&value).ok(),
+            };
+            return key.filter(|k| k.len() == 32).map(Zeroizing::new)
+                .ok_or(keepass_error("invalid xml keyfile"));
+        }
+        if text.len() == 64 && let Some(key) = hex_decode(text) {
+            return Ok(Zeroizing::new(key));
+        }
+    }
+    match data.len() {
+        32 => Ok(data),
+        _ => Ok(Zeroizing::new(Sha256::digest(data.as_slice()).to_vec())),
+    }
+}
+
+fn hex_decode(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..value.len()).step_by(2)
+        .map(|i| value.get(i..i + 2).and_then(|pair| u8::from_str_radix(pair, 16).ok()))
+        .collect()
+}
+
+fn parse_xml(database: &mut Database) -> Result<Import, ClipassError> {
+    let xml = std::str::from_utf8(&database.xml).map_err(|_| keepass_error("invalid xml encoding"))?;
+    let document = Document::parse(xml).map_err(|e| keepass_error(&format!("invalid xml: {e}")))?;
+
+    // Every protected value consumes the stream, in document order
+    let mut protected = HashMap::new();
+    for node in document.descendants().filter(|n| n.has_tag_name("Value") && n.attribute("Protected") == Some("True")) {
+        let mut value = Zeroizing::new(Base64::decode_vec(node.text().unwrap_or_default())
+            .map_err(|_| keepass_error("invalid protected value"))?);
+        database.stream.apply(&mut value);
+        let value = std::str::from_utf8(&value).map_err(|_| keepass_error("invalid protected value"))?;
+        protected.insert(node.id(), SecretString::from(value));
+    }
+
+    let root = document.root_element();
+    let recycle_bin = child(root, "Meta")
+        .filter(|meta| child_text(*meta, "RecycleBinEnabled") == "True")
+        .map(|meta| child_text(meta, "RecycleBinUUID"));
+    let top_group = child(root, "Root").and_then(|r| child(r, "Group"))
+        .ok_or(keepass_error("no root group"))?;
+    let mut walker = Walker { protected, binaries: &database.binaries, recycle_bin, import: Import::default() };
+    walker.group(top_group, &mut Vec::new())?;
+    Ok(walker.import)
+}
+
+struct Walker<'a> {
+    protected: HashMap<NodeId, SecretString>,
+    binaries: &'a [Zeroizing<Vec<u8>>],
+    recycle_bin: Option<&'a str>,
+    import: Import,
+}
+
+impl<'a> Walker<'a> {
+    fn group(&mut self, group: Node<'a, 'a>, path: &mut Vec<&'a str>) -> Result<(), ClipassError> {
+        for node in group.children().filter(Node::is_element) {
+            match node.tag_name().name() {
+                "Entry" => {
+                    let entry = self.entry(node, path)?;
+                    self.import.entries.push(entry);
+                },
+                "Group" if Some(child_text(node, "UUID")) == self.recycle_bin => {},
+                "Group" => {
+                    path.push(child_text(node, "Name"));
+                    self.group(node, path)?;
+                    path.pop();
+                },
+                _ => {},
+            }
+        }
+        Ok(())
+    }
+
+    fn entry(&mut self, node: Node, path: &[&str]) -> Result<(String, Entry), ClipassError> {
+        let strings = self.strings(node);
+        let value = |key: &str| strings.iter().find(|(k, _)| *k == key).map_or("", |(_, v)| v.expose());
+        let mut parts = path.to_vec();
+        parts.push(value("Title"));
+        let id = entry_id(&parts);
+
+        let mut entry = Entry::new();
+        entry.set_field(FIELD_USERNAME, value("UserName"))?;
+        entry.set_field(FIELD_PASSWORD, value("Password"))?;
+        entry.set_field(FIELD_URL, value("URL"))?;
+        entry.set_field(FIELD_NOTES, value("Notes"))?;
+        if let Some(otp) = otp_uri(value("otp"), &strings) {
+            set_otp(&mut entry, &id, otp.expose(), &mut self.import.warnings)?;
+        }
+        for (key, value) in &strings {
+            let known = ["Title", "UserName", "Password", "URL", "Notes", "otp"].contains(key);
+            if !known && !key.starts_with("TimeOtp-") {
+                set_custom(&mut entry, key, value.expose())?;
+            }
+        }
+
+        // Newest first, each previous password once
+        let mut previous: Vec<&str> = vec![value("Password")];
+        let history: Vec<Node> = child(node, "History")
+            .map(|h| h.children().filter(|n| n.has_tag_name("Entry")).collect())
+            .unwrap_or_default();
+        let history_strings: Vec<_> = history.iter().rev().map(|old| self.strings(*old)).collect();
+        for old in &history_strings {
+            if let Some((_, password)) = old.iter().find(|(k, _)| *k == "Password")
+                && !password.is_empty() && !previous.contains(&password.expose())
+            {
+                set_custom(&mut entry, "old-password", password.expose())?;
+                previous.push(password.expose());
+            }
+        }
+
+        for binary in node.children().filter(|n| n.has_tag_name("Binary")) {
+            let name = child_text(binary, "Key");
+            let content = child(binary, "Value")
+                .and_then(|v| v.attribute("Ref"))
+                .and_then(|r| r.parse::<usize>().ok())
+                .and_then(|r| self.binaries.get(r));
+            match content.map(|c| std::str::from_utf8(c)) {
+                Some(Ok(text)) => set_custom(&mut entry, name, text)?,
+                _ => self.import.warnings.push(format!("{id}: attachment {name} is not text, not imported")),
+            }
+        }
+        Ok((id, entry))
+    }
+
+    // Key and value of the String children, protected values decrypted
+    fn strings<'n>(&self, node: Node<'n, 'n>) -> Vec<(&'n str, SecretString)> {
+        node.children().filter(|n| n.has_tag_name("String"))
+            .map(|string| {
+                let value = child(string, "Value").map(|v| match self.protected.get(&v.id()) {
+                    Some(secret) => secret.clone(),
+                    None => SecretString::from(v.text().unwrap_or_default()),
+                });
+                (child_text(string, "Key"), value.unwrap_or_default())
+            })
+            .collect()
+    }
+}
+
+// KeePassXC "otp" uri, or KeePass 2 TimeOtp-* settings
+fn otp_uri(otp: &str, strings: &[(&str, SecretString)]) -> Option<SecretString> {
+    if !otp.is_empty() {
+        return Some(SecretString::from(otp));
+    }
+    let setting = |key: &str| strings.iter().find(|(k, _)| *k == key).map(|(_, v)| v.expose());
+    let secret = setting("TimeOtp-Secret-Base32").filter(|s| !s.is_empty())?;
+    let mut uri = format!("otpauth://totp/?secret={}", secret.replace(' ', ""));
+    if let Some(digits) = setting("TimeOtp-Length") {
+        uri.push_str(&format!("&digits={digits}"));
+    }
+    if let Some(period) = setting("TimeOtp-Period") {
+        uri.push_str(&format!("&period={period}"));
+    }
+    if let Some(algorithm) = setting("TimeOtp-Algorithm") {
+        uri.push_str(&format!("&algorithm={}", algorithm.trim_start_matches("HMAC-").replace('-', "")));
+    }
+    Some(SecretString::new(uri))
+}
+
+fn child<'a>(node: Node<'a, 'a>, name: &str) -> Option<Node<'a, 'a>> {
+    node.children().find(|n| n.has_tag_name(name))
+}
+
+fn child_text<'a>(node: Node<'a, 'a>, name: &str) -> &'a str {
+    child(node, name).and_then(|n| n.text()).unwrap_or_default()
+}
+
+fn keepass_error(message: &str) -> ClipassError {
+    ClipassError::Input(format!("keepass: {message}"))
+}
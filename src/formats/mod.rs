@@ -0,0 +1,367 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use crate::error::ClipassError;
+use crate::secret::SecretString;
+use crate::vault::entry::{Entry, FIELD_NOTES, FIELD_OTP, FIELD_PASSWORD, FIELD_URL, FIELD_USERNAME};
+use crate::vault::vault::Vault;
+use self::csv::CsvMapping;
+
+pub mod bitwarden;
+pub mod bundle;
+pub mod csv;
+pub mod keepass;
+pub mod pass;
+
+/*
+                *** IMPORT ***
+*****************************************
+Each format reads a file into an Import: vault ids with their entries,
+ids built from the folder path and the item name ("work/GitHub"), spaces
+replaced by dashes so they can be typed in commands.
+
+merge() adds them to the vault. An id taken by an earlier item of the file
+is renamed ("id-2"), an id already in the vault follows the strategy:
+  - skip      : keep the vault entry (default)
+  - rename    : import under the first free "id-N"
+  - overwrite : replace the vault entry
+A dry run reports the same without touching the vault.
+****************************************
+*/
+
+const STANDARD_FIELDS: &[&str] = &[FIELD_USERNAME, FIELD_PASSWORD, FIELD_URL, FIELD_NOTES, FIELD_OTP];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImportFormat {
+    Bitwarden,
+    Bundle,
+    Csv,
+    KeePass,
+    Pass,
+}
+
+impl ImportFormat {
+    // Formats read from an encrypted file ask for its password
+    pub fn password_prompt(&self) -> Option<&'static str> {
+        match self {
+            ImportFormat::Bitwarden | ImportFormat::Csv | ImportFormat::Pass => None,
+            ImportFormat::Bundle => Some("bundle passphrase: "),
+            ImportFormat::KeePass => Some("keepass password: "),
+        }
+    }
+}
+
+impl FromStr for ImportFormat {
+    type Err = ClipassError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bitwarden" => Ok(ImportFormat::Bitwarden),
+            "bundle" => Ok(ImportFormat::Bundle),
+            "csv" => Ok(ImportFormat::Csv),
+            "keepass" => Ok(ImportFormat::KeePass),
+            "pass" => Ok(ImportFormat::Pass),
+            _ => Err(ClipassError::InvalidCommand(format!("unknown import format '{s}'"))),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ImportStrategy {
+    #[default]
+    Skip,
+    Rename,
+    Overwrite,
+}
+
+impl FromStr for ImportStrategy {
+    type Err = ClipassError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(ImportStrategy::Skip),
+            "rename" => Ok(ImportStrategy::Rename),
+            "overwrite" => Ok(ImportStrategy::Overwrite),
+            _ => Err(ClipassError::InvalidCommand(format!("unknown conflict strategy '{s}'"))),
+        }
+    }
+}
+
+// import <format> <file> [--keyfile <file>] [--map <field=column,...>] [--dry-run] [--on-conflict skip|rename|overwrite]
+// or import <bundle> [--dry-run] [--on-conflict ...]
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImportArgs {
+    pub format: ImportFormat,
+    pub path: String,
+    pub keyfile: Option<String>, // keepass only
+    pub columns: CsvMapping,     // csv only
+    pub strategy: ImportStrategy,
+    pub dry_run: bool,
+}
+
+impl ImportArgs {
+    pub fn from_args<'a, I>(args: I) -> Result<Self, ClipassError>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut args = args.into_iter().peekable();
+        let usage = || ClipassError::InvalidCommand(
+            "usage: import <format> <file> [--keyfile <file>] [--map <field=column,...>] [--dry-run] [--on-conflict skip|rename|overwrite]"
+                .to_string());
+        let first = args.next().ok_or_else(usage)?;
+        let (format, path) = match first.parse() {
+            Ok(format) => (format, args.next().filter(|p| !p.starts_with("--")).ok_or_else(usage)?),
+            Err(e) if first.starts_with("--") || args.peek().is_some_and(|a| !a.starts_with("--")) => return Err(e),
+            // A lone file is a bundle
+            Err(_) => (ImportFormat::Bundle, first),
+        };
+        let path = path.to_string();
+        let mut import_args = ImportArgs {
+            format, path, keyfile: None, columns: CsvMapping::default(), strategy: ImportStrategy::default(), dry_run: false,
+        };
+        while let Some(arg) = args.next() {
+            match arg {
+                "--dry-run" => import_args.dry_run = true,
+                "--keyfile" if format == ImportFormat::KeePass => import_args.keyfile = Some(args.next()
+                    .ok_or(ClipassError::InvalidCommand("missing value for '--keyfile'".to_string()))?
+                    .to_string()),
+                "--map" if format == ImportFormat::Csv => import_args.columns = args.next()
+                    .ok_or(ClipassError::InvalidCommand("missing value for '--map'".to_string()))?
+                    .parse()?,
+                "--on-conflict" => import_args.strategy = args.next()
+                    .ok_or(ClipassError::InvalidCommand("missing value for '--on-conflict'".to_string()))?
+                    .parse()?,
+                _ => return Err(ClipassError::InvalidCommand(format!("unexpected argument '{arg}' for 'import'"))),
+            }
+        }
+        Ok(import_args)
+    }
+
+    // Read the file in the requested format, with the password asked for by password_prompt()
+    pub fn read(&self, password: Option<&SecretString>) -> Result<Import, ClipassError> {
+        let path = Path::new(&self.path);
+        match self.format {
+            ImportFormat::Bitwarden => bitwarden::read(path),
+            ImportFormat::Bundle => bundle::read(path, password.map_or("", SecretString::expose)),
+            ImportFormat::Csv => csv::read(path, &self.columns),
+            ImportFormat::KeePass => keepass::read(
+                path, password.map_or("", SecretString::expose), self.keyfile.as_deref().map(Path::new),
+            ),
+            ImportFormat::Pass => pass::read(path, &pass::Gpg),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    Bundle,
+    Csv,
+    Pass,
+}
+
+impl FromStr for ExportFormat {
+    type Err = ClipassError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(ExportFormat::Csv),
+            "pass" => Ok(ExportFormat::Pass),
+            _ => Err(ClipassError::InvalidCommand(format!("unknown export format '{s}'"))),
+        }
+    }
+}
+
+// export <format> <path> [filter] [--map <field=column,...>] [--yes]
+// or export --encrypted --to <file> <ids|filter>...
+// An existing id selects that entry only, anything else is a filter as given to list.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExportArgs {
+    pub format: ExportFormat,
+    pub path: String,
+    pub filters: Vec<String>, // at most one but for bundles
+    pub columns: CsvMapping, // csv only
+    pub yes: bool,           // csv only, no confirmation before writing in clear
+}
+
+impl ExportArgs {
+    pub fn from_args<'a, I>(args: I) -> Result<Self, ClipassError>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut args = args.into_iter().peekable();
+        if args.next_if_eq(&"--encrypted").is_some() {
+            return Self::bundle_from_args(args);
+        }
+        let (Some(format), Some(path)) = (args.next(), args.next()) else {
+            return Err(ClipassError::InvalidCommand(
+                "usage: export <format> <path> [filter] [--map <field=column,...>] [--yes]".to_string()));
+        };
+        let format = format.parse()?;
+        let mut export_args = ExportArgs {
+            format, path: path.to_string(), filters: Vec::new(), columns: CsvMapping::default(), yes: false,
+        };
+        while let Some(arg) = args.next() {
+            match arg {
+                "--map" if format == ExportFormat::Csv => export_args.columns = args.next()
+                    .ok_or(ClipassError::InvalidCommand("missing value for '--map'".to_string()))?
+                    .parse()?,
+                "--yes" if format == ExportFormat::Csv => export_args.yes = true,
+                filter if export_args.filters.is_empty() && !filter.starts_with("--") =>
+                    export_args.filters.push(filter.to_string()),
+                _ => return Err(ClipassError::InvalidCommand(format!("unexpected argument '{arg}' for 'export'"))),
+            }
+        }
+        Ok(export_args)
+    }
+
+    // After --encrypted: --to <file> <ids|filter>..., at least one
+    fn bundle_from_args<'a, I>(mut args: I) -> Result<Self, ClipassError>
+    where
+        I: Iterator<Item = &'a str>,
+    {
+        let usage = || ClipassError::InvalidCommand("usage: export --encrypted --to <file> <ids|filter>...".to_string());
+        let (mut path, mut filters) = (None, Vec::new());
+        while let Some(arg) = args.next() {
+            match arg {
+                "--to" if path.is_none() => path = Some(args.next().ok_or_else(usage)?.to_string()),
+                filter if !filter.starts_with("--") => filters.push(filter.to_string()),
+                _ => return Err(ClipassError::InvalidCommand(format!("unexpected argument '{arg}' for 'export'"))),
+            }
+        }
+        match (path, filters.is_empty()) {
+            (Some(path), false) => Ok(ExportArgs {
+                format: ExportFormat::Bundle, path, filters, columns: CsvMapping::default(), yes: false,
+            }),
+            _ => Err(usage()),
+        }
+    }
+}
+
+/// Entries read from another manager, in file order
+#[derive(Debug, Default)]
+pub struct Import {
+    pub entries: Vec<(String, Entry)>,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub added: Vec<String>,
+    pub renamed: Vec<(String, String)>,
+    pub overwritten: Vec<String>,
+    pub skipped: Vec<String>,
+    pub warnings: Vec<String>,
+    pub dry_run: bool,
+}
+
+impl ImportReport {
+    pub fn imported(&self) -> usize {
+        self.added.len() + self.renamed.len() + self.overwritten.len()
+    }
+}
+
+impl fmt::Display for ImportReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.dry_run {
+            writeln!(f, "dry run, the vault is left untouched")?;
+        }
+        writeln!(f, "added {}, renamed {}, overwritten {}, skipped {}",
+            self.added.len(), self.renamed.len(), self.overwritten.len(), self.skipped.len())?;
+        for (from, to) in &self.renamed {
+            writeln!(f, " - renamed {from} to {to}")?;
+        }
+        for id in &self.overwritten {
+            writeln!(f, " - overwrote {id}")?;
+        }
+        for id in &self.skipped {
+            writeln!(f, " - skipped {id}, already in the vault")?;
+        }
+        for warning in &self.warnings {
+            writeln!(f, "warning: {warning}")?;
+        }
+        if !self.dry_run && self.imported() > 0 {
+            write!(f, "save to apply")?;
+        }
+        Ok(())
+    }
+}
+
+// Add imported entries to the vault, see the strategies above
+pub fn merge(vault: &mut Vault, import: Import, strategy: ImportStrategy, dry_run: bool)
+    -> Result<ImportReport, ClipassError>
+{
+    let mut report = ImportReport { warnings: import.warnings, dry_run, ..ImportReport::default() };
+    let mut taken: HashSet<String> = HashSet::new();
+    for (id, entry) in import.entries {
+        let in_vault = vault.contains_key(&id);
+        let target = if taken.contains(&id) || (in_vault && strategy == ImportStrategy::Rename) {
+            let free = free_id(&id, |candidate| vault.contains_key(candidate) || taken.contains(candidate));
+            report.renamed.push((id, free.clone()));
+            free
+        } else if in_vault && strategy == ImportStrategy::Skip {
+            report.skipped.push(id);
+            continue;
+        } else if in_vault {
+            report.overwritten.push(id.clone());
+            id
+        } else {
+            report.added.push(id.clone());
+            id
+        };
+        if !dry_run {
+            if vault.contains_key(&target) {
+                vault.delete_entry(&target)?;
+            }
+            vault.new_entry(&target, entry)?;
+        }
+        taken.insert(target);
+    }
+    Ok(report)
+}
+
+// First "id-N" from 2 that is not taken
+fn free_id(id: &str, taken: impl Fn(&str) -> bool) -> String {
+    (2..).map(|n| format!("{id}-{n}"))
+        .find(|candidate| !taken(candidate))
+        .unwrap_or_default()
+}
+
+// Vault id from a folder path and a name, empty parts left out
+pub(crate) fn entry_id(parts: &[&str]) -> String {
+    let id = parts.iter()
+        .map(|part| part.trim().trim_matches('/'))
+        .filter(|part| !part.is_empty())
+        .map(dashed)
+        .collect::<Vec<_>>()
+        .join("/");
+    if id.is_empty() { "unnamed".to_string() } else { id }
+}
+
+// Set a custom field, renamed when it would replace a standard or existing field
+pub(crate) fn set_custom(entry: &mut Entry, name: &str, value: &str) -> Result<(), ClipassError> {
+    if value.is_empty() {
+        return Ok(());
+    }
+    let name = match dashed(name.trim()) {
+        n if n.is_empty() => "field".to_string(),
+        n => n,
+    };
+    let taken = |n: &str| STANDARD_FIELDS.contains(&n) || entry.custom().contains_key(n);
+    let name = if taken(&name) { free_id(&name, taken) } else { name };
+    entry.set_field(&name, value)
+}
+
+// Secrets clipass cannot generate codes for (Steam guard...) are kept as a custom field
+pub(crate) fn set_otp(entry: &mut Entry, id: &str, value: &str, warnings: &mut Vec<String>) -> Result<(), ClipassError> {
+    if entry.set_field(FIELD_OTP, value).is_err() {
+        warnings.push(format!("{id}: unsupported totp secret, kept as the totp field"));
+        set_custom(entry, "totp", value)?;
+    }
+    Ok(())
+}
+
+// Whitespace runs replaced by a dash
+fn dashed(value: &str) -> String {
+    value.split_whitespace().collect::<Vec<_>>().join("-")
+}
@@ -0,0 +1,237 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+use crate::error::ClipassError;
+use crate::formats::{entry_id, set_custom, set_otp, Import};
+use crate::secret::SecretString;
+use crate::vault::entry::{Entry, FIELD_NOTES, FIELD_OTP, FIELD_PASSWORD, FIELD_URL, FIELD_USERNAME};
+
+/*
+  pass (password-store): one encrypted file per entry, its path in the store
+  being the entry id (web/github.gpg is web/github). The first line is the
+  password, then "key: value" lines:
+    - login, user, username, email : username (the first one)
+    - url                          : url, once per url
+    - otpauth://...                : otp, as written by pass-otp
+    - any other key                : custom field
+  and the remaining lines are notes. Export writes the same layout, without
+  replacing files already in the store. A field spanning several lines
+  would be read back as notes, it is left out with a warning, and an entry
+  whose password does is not written at all.
+  Hidden files and directories (.git, .gpg-id) are not entries.
+*/
+
+const USERNAME_KEYS: &[&str] = &["login", "user", "username", "email"];
+const GPG_ID: &str = ".gpg-id";
+
+/// How the store files are decrypted and encrypted
+pub trait PassCipher {
+    // Extension of the entry files, without the dot
+    fn extension(&self) -> &str;
+    fn decrypt(&self, path: &Path) -> Result<SecretString, ClipassError>;
+    fn encrypt(&self, path: &Path, content: &str) -> Result<(), ClipassError>;
+}
+
+/// gpg, encrypting for the recipients of the nearest .gpg-id like pass does
+pub struct Gpg;
+
+impl PassCipher for Gpg {
+    fn extension(&self) -> &str {
+        "gpg"
+    }
+
+    fn decrypt(&self, path: &Path) -> Result<SecretString, ClipassError> {
+        let output = Command::new("gpg")
+            .args(["--quiet", "--batch", "--use-agent", "--decrypt"])
+            .arg(path)
+            .stdin(Stdio::null())
+            .output()?;
+        if !output.status.success() {
+            return Err(gpg_error(path, &output.stderr));
+        }
+        let content = String::from_utf8(output.stdout)
+            .map_err(|_| ClipassError::Input(format!("pass: {} is not text", path.display())))?;
+        Ok(SecretString::new(content))
+    }
+
+    fn encrypt(&self, path: &Path, content: &str) -> Result<(), ClipassError> {
+        let recipients = path.ancestors()
+            .map(|dir| dir.join(GPG_ID))
+            .find(|gpg_id| gpg_id.is_file())
+            .ok_or(ClipassError::Input("pass: no .gpg-id in the store, run pass init first".to_string()))?;
+        let mut command = Command::new("gpg");
+        command.args(["--quiet", "--batch", "--yes", "--no-encrypt-to", "--encrypt", "--output"]).arg(path);
+        for recipient in fs::read_to_string(recipients)?.lines().map(str::trim).filter(|r| !r.is_empty()) {
+            command.args(["--recipient", recipient]);
+        }
+        let mut child = command.stdin(Stdio::piped()).stdout(Stdio::null()).stderr(Stdio::piped()).spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(content.as_bytes())?;
+        }
+        let output = child.wait_with_output()?;
+        match output.status.success() {
+            true => Ok(()),
+            false => Err(gpg_error(path, &output.stderr)),
+        }
+    }
+}
+
+/// Unencrypted .txt files, for tests
+pub struct Plaintext;
+
+impl PassCipher for Plaintext {
+    fn extension(&self) -> &str {
+        "txt"
+    }
+
+    fn decrypt(&self, path: &Path) -> Result<SecretString, ClipassError> {
+        Ok(SecretString::new(fs::read_to_string(path)?))
+    }
+
+    fn encrypt(&self, path: &Path, content: &str) -> Result<(), ClipassError> {
+        Ok(fs::write(path, content)?)
+    }
+}
+
+pub fn read(store: &Path, cipher: &dyn PassCipher) -> Result<Import, ClipassError> {
+    if !store.is_dir() {
+        return Err(ClipassError::Input(format!("pass: {} is not a directory", store.display())));
+    }
+    let mut files = Vec::new();
+    find_entries(store, cipher.extension(), &mut files)?;
+    let mut import = Import::default();
+    for file in files {
+        let relative = file.strip_prefix(store).unwrap_or(&file).with_extension("");
+        let parts: Vec<String> = relative.components().map(|c| c.as_os_str().to_string_lossy().to_string()).collect();
+        let id = entry_id(&parts.iter().map(String::as_str).collect::<Vec<_>>());
+        let content = cipher.decrypt(&file)?;
+        let entry = parse_entry(&id, content.expose(), &mut import.warnings)?;
+        import.entries.push((id, entry));
+    }
+    Ok(import)
+}
+
+// Entry files under dir, sorted, hidden ones left out
+fn find_entries(dir: &Path, extension: &str, files: &mut Vec<PathBuf>) -> Result<(), ClipassError> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<Result<_, _>>()?;
+    paths.sort();
+    for path in paths {
+        if path.file_name().is_some_and(|n| n.to_string_lossy().starts_with('.')) {
+            continue;
+        }
+        if path.is_dir() {
+            find_entries(&path, extension, files)?;
+        } else if path.extension().is_some_and(|e| e == extension) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn parse_entry(id: &str, content: &str, warnings: &mut Vec<String>) -> Result<Entry, ClipassError> {
+    let mut entry = Entry::new();
+    let mut lines = content.lines();
+    entry.set_field(FIELD_PASSWORD, lines.next().unwrap_or_default())?;
+    let (mut urls, mut notes) = (Vec::new(), Vec::new());
+    for line in lines {
+        if line.starts_with("otpauth://") {
+            set_otp(&mut entry, id, line, warnings)?;
+            continue;
+        }
+        let Some((key, value)) = line.split_once(": ").filter(|(k, _)| !k.trim().is_empty()) else {
+            notes.push(line);
+            continue;
+        };
+        let key_lower = key.trim().to_lowercase();
+        if USERNAME_KEYS.contains(&key_lower.as_str()) && entry.username().is_none() {
+            entry.set_field(FIELD_USERNAME, value.trim())?;
+        } else if key_lower == FIELD_URL {
+            urls.push(value.trim());
+        } else {
+            set_custom(&mut entry, key, value.trim())?;
+        }
+    }
+    entry.set_field(FIELD_URL, &urls.join(" "))?;
+    entry.set_field(FIELD_NOTES, notes.join("\n").trim())?;
+    Ok(entry)
+}
+
+/// Entries written by export()
+#[derive(Debug, Default)]
+pub struct PassExport {
+    pub written: Vec<String>,
+    pub existing: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+// Write entries into the store, files already there are left alone
+pub fn export<'a, I>(entries: I, store: &Path, cipher: &dyn PassCipher) -> Result<PassExport, ClipassError>
+where
+    I: IntoIterator<Item = (&'a String, &'a Entry)>,
+{
+    let mut report = PassExport::default();
+    let mut entries: Vec<_> = entries.into_iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    for (id, entry) in entries {
+        // Appended, ids may hold dots (web/github.com)
+        let path = store.join(format!("{}.{}", entry_path(id)?.display(), cipher.extension()));
+        if path.exists() {
+            report.existing.push(id.clone());
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let Some(content) = format_entry(id, entry, &mut report.warnings) else {
+            continue;
+        };
+        cipher.encrypt(&path, content.expose())?;
+        report.written.push(id.clone());
+    }
+    Ok(report)
+}
+
+// Relative path of an entry, refusing ids that would leave the store
+fn entry_path(id: &str) -> Result<PathBuf, ClipassError> {
+    let path = PathBuf::from(id);
+    let normal = path.components().all(|c| matches!(c, Component::Normal(_)));
+    if !normal || id.ends_with('/') || path.file_name().is_none_or(|n| n.to_string_lossy().starts_with('.')) {
+        return Err(ClipassError::Input(format!("pass: id {id} cannot be a path in the store")));
+    }
+    Ok(path)
+}
+
+// None when the password spans several lines, it would be cut at the first one
+fn format_entry(id: &str, entry: &Entry, warnings: &mut Vec<String>) -> Option<SecretString> {
+    let password = entry.password().unwrap_or_default();
+    if password.contains('\n') {
+        warnings.push(format!("{id}: password spans several lines, entry not exported"));
+        return None;
+    }
+    let mut content = String::new();
+    let _ = writeln!(content, "{password}");
+    for (name, value) in entry.fields() {
+        let _ = match name {
+            FIELD_PASSWORD | FIELD_NOTES => Ok(()),
+            _ if value.contains('\n') => {
+                warnings.push(format!("{id}: field {name} spans several lines, not exported"));
+                Ok(())
+            },
+            FIELD_USERNAME => writeln!(content, "login: {value}"),
+            FIELD_OTP => writeln!(content, "{value}"),
+            _ => writeln!(content, "{name}: {value}"),
+        };
+    }
+    if let Some(notes) = entry.notes() {
+        let _ = writeln!(content, "{notes}");
+    }
+    Some(SecretString::new(content))
+}
+
+fn gpg_error(path: &Path, stderr: &[u8]) -> ClipassError {
+    ClipassError::Input(format!("gpg failed on {}: {}", path.display(), String::from_utf8_lossy(stderr).trim()))
+}
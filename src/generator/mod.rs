@@ -0,0 +1,190 @@
+use rand::seq::SliceRandom;
+use rand::thread_rng;
+use rand::Rng;
+use serde::Deserialize;
+use crate::error::ClipassError;
+
+const LOWERCASE: &str = "abcdefghijklmnopqrstuvwxyz";
+const UPPERCASE: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGITS: &str = "0123456789";
+const SYMBOLS: &str = "!#$%&()*+,-./:;<=>?@[]^_{|}~";
+// Characters easily mistaken for one another
+const AMBIGUOUS: &str = "Il1O0o|";
+
+// Bounds of generated secrets, beyond them the input is a mistake
+pub const MAX_LENGTH: usize = 1024;
+pub const MAX_WORDS: usize = 256;
+
+// EFF short wordlist (CC BY 3.0), "<dice>\t<word>" per line
+const WORDLIST: &str = include_str!("eff_short_wordlist.txt");
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PasswordPolicy {
+    pub length: usize,
+    pub lowercase: bool,
+    pub uppercase: bool,
+    pub digits: bool,
+    pub symbols: bool,
+    pub exclude_ambiguous: bool,
+    pub require_each_class: bool, // at least one character of every enabled class
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            length: 20,
+            lowercase: true,
+            uppercase: true,
+            digits: true,
+            symbols: true,
+            exclude_ambiguous: false,
+            require_each_class: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PassphrasePolicy {
+    pub words: usize,
+    pub separator: String,
+    pub capitalize: bool,
+}
+
+impl Default for PassphrasePolicy {
+    fn default() -> Self {
+        Self { words: 6, separator: "-".to_string(), capitalize: false }
+    }
+}
+
+pub fn generate_password(policy: &PasswordPolicy) -> Result<String, ClipassError> {
+    let classes: Vec<Vec<char>> = [
+        (policy.lowercase, LOWERCASE),
+        (policy.uppercase, UPPERCASE),
+        (policy.digits, DIGITS),
+        (policy.symbols, SYMBOLS),
+    ]
+        .iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, chars)| chars.chars()
+            .filter(|c| !policy.exclude_ambiguous || !AMBIGUOUS.contains(*c))
+            .collect())
+        .collect();
+
+    if policy.length == 0 || policy.length > MAX_LENGTH {
+        return Err(ClipassError::Input(format!("password length must be between 1 and {MAX_LENGTH}")));
+    }
+    if classes.is_empty() {
+        return Err(ClipassError::Input("no character class enabled".to_string()));
+    }
+    if policy.require_each_class && policy.length < classes.len() {
+        return Err(ClipassError::Input(format!(
+            "length {} is too short for {} required character classes", policy.length, classes.len()
+        )));
+    }
+
+    let mut rng = thread_rng();
+    let alphabet: Vec<char> = classes.concat();
+    let mut password: Vec<char> = Vec::with_capacity(policy.length);
+    if policy.require_each_class {
+        for class in &classes {
+            password.push(class[rng.gen_range(0..class.len())]);
+        }
+    }
+    while password.len() < policy.length {
+        password.push(alphabet[rng.gen_range(0..alphabet.len())]);
+    }
+    password.shuffle(&mut rng);
+    Ok(password.into_iter().collect())
+}
+
+pub fn generate_passphrase(policy: &PassphrasePolicy) -> Result<String, ClipassError> {
+    if policy.words == 0 || policy.words > MAX_WORDS {
+        return Err(ClipassError::Input(format!("a passphrase needs between 1 and {MAX_WORDS} words")));
+    }
+    let wordlist = wordlist();
+    let mut rng = thread_rng();
+    let words: Vec<String> = (0..policy.words)
+        .map(|_| {
+            let word = wordlist[rng.gen_range(0..wordlist.len())];
+            match policy.capitalize {
+                true => word[..1].to_uppercase() + &word[1..],
+                false => word.to_string(),
+            }
+        })
+        .collect();
+    Ok(words.join(&policy.separator))
+}
+
+pub fn wordlist() -> Vec<&'static str> {
+    WORDLIST.lines()
+        .filter_map(|line| line.split('\t').nth(1))
+        .collect()
+}
+
+/// Generator options given to the `generate` and `new --generate` commands,
+/// applied over the default policies
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GenerateArgs {
+    pub passphrase: bool,
+    pub length: Option<usize>,
+    pub no_lowercase: bool,
+    pub no_uppercase: bool,
+    pub no_digits: bool,
+    pub no_symbols: bool,
+    pub exclude_ambiguous: bool,
+    pub words: Option<usize>,
+    pub separator: Option<String>,
+    pub capitalize: bool,
+}
+
+impl GenerateArgs {
+    pub fn from_args<'a, I>(args: I) -> Result<Self, ClipassError>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut generate_args = GenerateArgs::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next()
+                .ok_or(ClipassError::InvalidCommand(format!("missing value for '{name}'")));
+            match arg {
+                "--passphrase" => generate_args.passphrase = true,
+                "-l" | "--length" => generate_args.length = Some(value(arg)?.parse()?),
+                "--no-lower" => generate_args.no_lowercase = true,
+                "--no-upper" => generate_args.no_uppercase = true,
+                "--no-digits" => generate_args.no_digits = true,
+                "--no-symbols" => generate_args.no_symbols = true,
+                "--no-ambiguous" => generate_args.exclude_ambiguous = true,
+                "-w" | "--words" => generate_args.words = Some(value(arg)?.parse()?),
+                "--separator" => generate_args.separator = Some(value(arg)?.to_string()),
+                "--capitalize" => generate_args.capitalize = true,
+                _ => return Err(ClipassError::InvalidCommand(format!("unknown generator option '{arg}'"))),
+            }
+        }
+        Ok(generate_args)
+    }
+
+    pub fn generate(&self, password_policy: &PasswordPolicy, passphrase_policy: &PassphrasePolicy)
+        -> Result<String, ClipassError>
+    {
+        if self.passphrase {
+            let mut policy = passphrase_policy.clone();
+            policy.words = self.words.unwrap_or(policy.words);
+            if let Some(separator) = &self.separator {
+                policy.separator = separator.clone();
+            }
+            policy.capitalize |= self.capitalize;
+            return generate_passphrase(&policy);
+        }
+        let mut policy = password_policy.clone();
+        policy.length = self.length.unwrap_or(policy.length);
+        policy.lowercase &= !self.no_lowercase;
+        policy.uppercase &= !self.no_uppercase;
+        policy.digits &= !self.no_digits;
+        policy.symbols &= !self.no_symbols;
+        policy.exclude_ambiguous |= self.exclude_ambiguous;
+        generate_password(&policy)
+    }
+}
@@ -0,0 +1,278 @@
+/*
+        *** CLIPASS MEMORY HARDENING ***
+*****************************************
+Zeroizing secrets is not enough when their pages can be swapped out or
+written to a core dump, so:
+
+  - harden_process() disables core dumps (RLIMIT_CORE = 0, and
+    PR_SET_DUMPABLE = 0 on Linux, which also blocks ptrace by the user)
+  - LockedBytes allocates from an arena of mmap'd pages that are mlock'ed
+    and, on Linux, excluded from core dumps with MADV_DONTDUMP
+
+Keys, secret strings and decrypted vault contents live in LockedBytes.
+Small buffers share 64 KiB chunks split in power of two slots, bigger
+ones get their own mapping. Freed memory is wiped before reuse.
+
+Nothing here is fatal: when RLIMIT_MEMLOCK or the platform refuses,
+a warning is printed once and the memory stays usable, only unlocked.
+****************************************
+*/
+
+use std::alloc::{self, Layout};
+use std::fmt;
+use std::io;
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, Once};
+use zeroize::Zeroize;
+
+const MIN_SLOT: usize = 16;
+const MAX_SLOT: usize = 4096;
+const SLOT_CLASSES: usize = 9; // 16 << 8 == 4096
+const CHUNK_SIZE: usize = 64 * 1024;
+
+static HARDEN: Once = Once::new();
+static LOCK_WARNED: AtomicBool = AtomicBool::new(false);
+static ARENA: Mutex<Arena> = Mutex::new(Arena { free: [const { Vec::new() }; SLOT_CLASSES] });
+
+// Disable core dumps for the whole process, once
+pub fn harden_process() {
+    HARDEN.call_once(|| {
+        if let Err(e) = disable_core_dumps() {
+            eprintln!("warning: could not disable core dumps ({e})");
+        }
+    });
+}
+
+fn disable_core_dumps() -> io::Result<()> {
+    let limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+    if unsafe { libc::setrlimit(libc::RLIMIT_CORE, &limit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    #[cfg(target_os = "linux")]
+    if unsafe { libc::prctl(libc::PR_SET_DUMPABLE, 0, 0, 0, 0) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn warn_unlocked(error: io::Error) {
+    if !LOCK_WARNED.swap(true, Ordering::Relaxed) {
+        eprintln!("warning: could not lock secret memory ({error}), it may be swapped to disk");
+    }
+}
+
+fn page_size() -> usize {
+    match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+        size if size > 0 => size as usize,
+        _ => 4096,
+    }
+}
+
+// Locked, non dumpable pages, or plain heap pages when mmap is refused.
+// Returns the address and whether it was mapped.
+fn map_region(size: usize) -> (usize, bool) {
+    let addr = unsafe {
+        libc::mmap(ptr::null_mut(), size, libc::PROT_READ | libc::PROT_WRITE,
+                   libc::MAP_PRIVATE | libc::MAP_ANONYMOUS, -1, 0)
+    };
+    if addr == libc::MAP_FAILED {
+        warn_unlocked(io::Error::last_os_error());
+        let layout = Layout::from_size_align(size, page_size()).expect("region layout");
+        let addr = unsafe { alloc::alloc_zeroed(layout) };
+        if addr.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+        return (addr as usize, false);
+    }
+    #[cfg(target_os = "linux")]
+    unsafe {
+        libc::madvise(addr, size, libc::MADV_DONTDUMP);
+    }
+    if unsafe { libc::mlock(addr, size) } != 0 {
+        warn_unlocked(io::Error::last_os_error());
+    }
+    (addr as usize, true)
+}
+
+fn unmap_region(addr: usize, size: usize, mapped: bool) {
+    unsafe {
+        if mapped {
+            libc::munlock(addr as *const libc::c_void, size);
+            libc::munmap(addr as *mut libc::c_void, size);
+        } else {
+            alloc::dealloc(addr as *mut u8, Layout::from_size_align(size, page_size()).expect("region layout"));
+        }
+    }
+}
+
+// Free slots per size class, chunks are kept for the process lifetime
+struct Arena {
+    free: [Vec<usize>; SLOT_CLASSES],
+}
+
+impl Arena {
+    fn take(&mut self, class: usize) -> usize {
+        let size = MIN_SLOT << class;
+        if self.free[class].is_empty() {
+            let (chunk, _) = map_region(CHUNK_SIZE);
+            self.free[class].extend((0..CHUNK_SIZE / size).rev().map(|i| chunk + i * size));
+        }
+        self.free[class].pop().expect("refilled slot class")
+    }
+}
+
+fn arena() -> std::sync::MutexGuard<'static, Arena> {
+    ARENA.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Byte buffer in locked memory, wiped when dropped or moved to a bigger
+/// allocation. Debug never shows the content.
+pub struct LockedBytes {
+    addr: usize,
+    len: usize,
+    cap: usize,
+    mapped: bool,
+}
+
+impl LockedBytes {
+    pub fn new() -> Self {
+        Self { addr: 0, len: 0, cap: 0, mapped: false }
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        if capacity == 0 {
+            return Self::new();
+        }
+        if capacity <= MAX_SLOT {
+            let class = capacity.next_power_of_two().max(MIN_SLOT).trailing_zeros()
+                - MIN_SLOT.trailing_zeros();
+            let addr = arena().take(class as usize);
+            return Self { addr, len: 0, cap: MIN_SLOT << class, mapped: false };
+        }
+        let cap = capacity.div_ceil(page_size()) * page_size();
+        let (addr, mapped) = map_region(cap);
+        Self { addr, len: 0, cap, mapped }
+    }
+
+    pub fn zeroed(len: usize) -> Self {
+        let mut bytes = Self::with_capacity(len);
+        // Slots and regions are always handed out wiped
+        bytes.len = len;
+        bytes
+    }
+
+    pub fn from_slice(data: &[u8]) -> Self {
+        let mut bytes = Self::with_capacity(data.len());
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        match self.cap {
+            0 => &[],
+            _ => unsafe { slice::from_raw_parts(self.addr as *const u8, self.len) },
+        }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        match self.cap {
+            0 => &mut [],
+            _ => unsafe { slice::from_raw_parts_mut(self.addr as *mut u8, self.len) },
+        }
+    }
+
+    pub fn extend_from_slice(&mut self, data: &[u8]) {
+        let needed = self.len + data.len();
+        if needed > self.cap {
+            let mut grown = Self::with_capacity(needed.max(self.cap * 2));
+            grown.extend_from_slice(self.as_slice());
+            // The old allocation is wiped when dropped here
+            *self = grown;
+        }
+        if !data.is_empty() {
+            unsafe { ptr::copy_nonoverlapping(data.as_ptr(), (self.addr as *mut u8).add(self.len), data.len()) };
+            self.len = needed;
+        }
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        if len < self.len {
+            self.as_mut_slice()[len..].zeroize();
+            self.len = len;
+        }
+    }
+}
+
+impl Default for LockedBytes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for LockedBytes {
+    fn drop(&mut self) {
+        if self.cap == 0 {
+            return;
+        }
+        unsafe { slice::from_raw_parts_mut(self.addr as *mut u8, self.cap) }.zeroize();
+        if self.cap <= MAX_SLOT {
+            let class = (self.cap.trailing_zeros() - MIN_SLOT.trailing_zeros()) as usize;
+            arena().free[class].push(self.addr);
+        } else {
+            unmap_region(self.addr, self.cap, self.mapped);
+        }
+    }
+}
+
+impl Clone for LockedBytes {
+    fn clone(&self) -> Self {
+        Self::from_slice(self.as_slice())
+    }
+}
+
+impl PartialEq for LockedBytes {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl Eq for LockedBytes {}
+
+impl AsRef<[u8]> for LockedBytes {
+    fn as_ref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl AsMut<[u8]> for LockedBytes {
+    fn as_mut(&mut self) -> &mut [u8] {
+        self.as_mut_slice()
+    }
+}
+
+impl io::Write for LockedBytes {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl fmt::Debug for LockedBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "LockedBytes({} bytes)", self.len)
+    }
+}
@@ -2,5 +2,14 @@ pub mod vault;
 pub mod clipass;
 pub mod command;
 pub mod utils;
+pub mod clipboard;
+pub mod agent;
 pub mod error;
-mod crypto;
\ No newline at end of file
+pub mod config;
+pub mod generator;
+pub mod otp;
+pub mod search;
+pub mod crypto;
+pub mod hardening;
+pub mod secret;
+pub mod formats;
\ No newline at end of file
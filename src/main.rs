@@ -1,24 +1,210 @@
-use crate::clipass::Clipass;
-use crate::error::ClipassError;
+use clipass::agent;
+use clipass::agent::{Agent, AgentClient, AgentRequest, DEFAULT_LOCK_TIMEOUT};
+use clipass::clipass::{inspect_vault, verify_vault, Clipass, CLIPASS_VERSION};
+use clipass::command::{Command, COMMAND_NAMES};
+use clipass::config::Config;
+use clipass::error::ClipassError;
+use clipass::hardening;
+use clipass::secret::SecretString;
+use clipass::utils;
 
 use std::env;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
 
-mod command;
-mod crypto;
-mod error;
-mod clipass;
-mod utils;
-mod vault;
+fn main() -> ExitCode {
+    hardening::harden_process();
+    match run(env::args().skip(1).collect()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
+
+// clipass [--config <file>] [--keyfile <file>] agent [<vault>] [--timeout <secs>] | --status | --lock
+// clipass [--config <file>] [--keyfile <file>] [<vault>] [<command> [args...]]
+// clipass [--config <file>] --recover [<vault>]
+// clipass [--config <file>] unlock --shares [<vault>]
+// clipass [--config <file>] [--keyfile <file>] inspect | verify [<vault>]
+// Without a vault argument the configured vault is used, a command runs once and exits
+fn run(mut args: Vec<String>) -> Result<(), ClipassError> {
+    let mut config_path = None;
+    let mut keyfile = None;
+    let mut recover = false;
+    while let Some(option @ ("--config" | "--keyfile" | "--recover")) = args.first().map(String::as_str) {
+        if option == "--recover" {
+            recover = true;
+            args.remove(0);
+            continue;
+        }
+        let value = args.get(1)
+            .ok_or(ClipassError::InvalidCommand(format!("missing value for '{option}'")))?;
+        match option {
+            "--config" => config_path = Some(PathBuf::from(value)),
+            _ => keyfile = Some(PathBuf::from(value)),
+        }
+        args.drain(..2);
+    }
+    let mut config = Config::load(config_path.as_deref())?;
+    config.keyfile = keyfile.or(config.keyfile);
+    let default_vault = config.vault.as_ref().map(|v| v.to_string_lossy().to_string());
 
-fn main() -> Result<(), ClipassError> {
-    let args: Vec<String> = env::args().collect();
+    let first = args.first().map(String::as_str);
+    if first == Some("agent") {
+        return run_agent(&args[1..], &config);
+    }
+    if first == Some("unlock") {
+        return run_unlock(&args[1..], &config);
+    }
+    if let Some(name @ ("inspect" | "verify")) = first {
+        let path = match &args[1..] {
+            [] => default_vault.ok_or(ClipassError::InvalidCommand(format!("missing vault for '{name}'")))?,
+            [path] => path.clone(),
+            [_, arg, ..] => return Err(ClipassError::InvalidCommand(format!("unexpected argument '{arg}' for '{name}'"))),
+        };
+        let output = match name {
+            "inspect" => inspect_vault(&path)?,
+            _ => verify_vault(&path, &config)?,
+        };
+        print_output(&output);
+        return Ok(());
+    }
+    let (path, command_args) = match first {
+        Some(name) if COMMAND_NAMES.contains(&name) && !Path::new(name).exists() => {
+            let path = default_vault
+                .ok_or(ClipassError::ConfigError("no vault given nor configured".to_string()))?;
+            (Some(path), &args[..])
+        },
+        Some(path) => (Some(path.to_string()), &args[1..]),
+        None => (default_vault, &args[..]),
+    };
+    match (path, command_args.is_empty()) {
+        (Some(path), true) if recover => run_recover(&path, &config),
+        (_, _) if recover => Err(ClipassError::InvalidCommand("--recover takes a vault only".to_string())),
+        (Some(path), false) => run_once(&path, command_args, &config),
+        (path, _) => run_interactive(path, &config),
+    }
+}
+
+// Unlock with the recovery code, set a new master password and carry on interactively
+fn run_recover(path: &str, config: &Config) -> Result<(), ClipassError> {
+    println!("clipass v{CLIPASS_VERSION}");
+    let mut clipass = Clipass::recover(path, config)?;
+    println!("new master password saved");
+    clipass.command_line();
+    Ok(())
+}
+
+// Unlock with key shares when nobody knows a master password
+fn run_unlock(args: &[String], config: &Config) -> Result<(), ClipassError> {
+    let (Some("--shares"), path) = (args.first().map(String::as_str), args.get(1)) else {
+        return Err(ClipassError::InvalidCommand("usage: unlock --shares [<vault>]".to_string()));
+    };
+    if args.len() > 2 {
+        return Err(ClipassError::InvalidCommand(format!("unexpected argument '{}' for 'unlock'", args[2])));
+    }
+    let path = path.cloned()
+        .or(config.vault.as_ref().map(|v| v.to_string_lossy().to_string()))
+        .ok_or(ClipassError::InvalidCommand("missing vault for 'unlock'".to_string()))?;
+
+    println!("clipass v{CLIPASS_VERSION}");
+    let mut clipass = Clipass::unlock_with_shares(&path, config)?;
+    clipass.command_line();
+    Ok(())
+}
 
-    let path= match args.get(1) {
-        Some(p) => p.clone(),
+fn run_interactive(path: Option<String>, config: &Config) -> Result<(), ClipassError> {
+    let path = match path {
+        Some(p) => p,
         None => utils::input_read("vault path: ")?,
     };
 
-    let mut clipass = Clipass::new(path.as_str())?;
+    println!("clipass v{CLIPASS_VERSION}");
+    let mut clipass = Clipass::new(path.as_str(), config)?;
     clipass.command_line();
     Ok(())
 }
+
+// Only opens an existing vault, a mistyped path must not create one
+fn run_once(path: &str, args: &[String], config: &Config) -> Result<(), ClipassError> {
+    let command = Command::from_args(args.iter().map(String::as_str))?;
+    if !Path::new(path).is_file() {
+        return Err(ClipassError::VaultNotFound(path.to_string()));
+    }
+    let stdin = match command {
+        Command::Set(_, _, true) => Some(SecretString::new(utils::read_stdin_value()?)),
+        _ => None,
+    };
+
+    // An agent holding this vault answers without asking for the password
+    let client = AgentClient::new(&agent::socket_path());
+    if let Some(response) = client.run(path, args, stdin.clone()) {
+        print_output(&response.output);
+        return match response.error {
+            None => Ok(()),
+            Some(e) => {
+                eprintln!("error: {e}");
+                std::process::exit(response.code.into())
+            },
+        };
+    }
+
+    let mut clipass = Clipass::new(path, config)?;
+    let output = match (command, stdin) {
+        (Command::Set(id, field, true), Some(value)) => clipass.set_value(&id, &field, value.expose())?,
+        (command, _) => clipass.run(command)?,
+    };
+    if clipass.is_updated() {
+        clipass.save()?;
+    }
+    print_output(&output);
+    clipass.wait_clipboard();
+    Ok(())
+}
+
+fn run_agent(args: &[String], config: &Config) -> Result<(), ClipassError> {
+    let socket_path = agent::socket_path();
+    let mut path = None;
+    let mut timeout = config.auto_lock().unwrap_or(DEFAULT_LOCK_TIMEOUT);
+    let mut args = args.iter().map(String::as_str);
+    while let Some(arg) = args.next() {
+        match arg {
+            "--status" | "--lock" => {
+                let request = AgentRequest { vault: String::new(), args: vec![arg[2..].to_string()], stdin: None };
+                let response = AgentClient::new(&socket_path).request(&request)?;
+                print_output(response.error.as_deref().unwrap_or(&response.output));
+                return Ok(());
+            },
+            "--timeout" => {
+                let secs: u64 = args.next()
+                    .ok_or(ClipassError::InvalidCommand("missing value for '--timeout'".to_string()))?
+                    .parse()?;
+                timeout = Duration::from_secs(secs);
+            },
+            p if path.is_none() => path = Some(p),
+            _ => return Err(ClipassError::InvalidCommand(format!("unexpected argument '{arg}' for 'agent'"))),
+        }
+    }
+    let default_vault = config.vault.as_ref().map(|v| v.to_string_lossy().to_string());
+    let path = path.map(str::to_string)
+        .or(default_vault)
+        .ok_or(ClipassError::InvalidCommand("missing vault for 'agent'".to_string()))?;
+
+    // Like one-shot runs, a mistyped path must not create a vault to serve
+    if !Path::new(&path).is_file() {
+        return Err(ClipassError::VaultNotFound(path));
+    }
+    let clipass = Clipass::new(&path, config)?;
+    eprintln!("agent listening on {}", socket_path.display());
+    Agent::new(clipass, &path, &socket_path, timeout)?.serve()
+}
+
+fn print_output(output: &str) {
+    let output = output.trim_end();
+    if !output.is_empty() {
+        println!("{output}");
+    }
+}
@@ -0,0 +1,278 @@
+use std::fmt;
+use std::fmt::Write;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+use zeroize::Zeroizing;
+use crate::error::ClipassError;
+use crate::hardening::LockedBytes;
+use crate::secret::SecretString;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+const DEFAULT_DIGITS: u32 = 6;
+const DEFAULT_PERIOD: u64 = 30;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OtpAlgorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl OtpAlgorithm {
+    fn name(&self) -> &'static str {
+        match self {
+            OtpAlgorithm::Sha1 => "SHA1",
+            OtpAlgorithm::Sha256 => "SHA256",
+            OtpAlgorithm::Sha512 => "SHA512",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OtpKind {
+    Totp { period: u64 },   // RFC 6238
+    Hotp { counter: u64 },  // RFC 4226
+}
+
+/// One-time password seed, stored in the vault as its otpauth:// URI
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "SecretString", into = "SecretString")]
+pub struct Otp {
+    secret: LockedBytes,
+    algorithm: OtpAlgorithm,
+    digits: u32,
+    kind: OtpKind,
+    label: String,
+    issuer: Option<String>,
+    uri: SecretString,
+}
+
+impl Otp {
+    // Parse an otpauth:// URI, or a bare base32 secret taken as a default TOTP
+    pub fn parse(value: &str) -> Result<Self, ClipassError> {
+        let value = value.trim();
+        let Some(rest) = value.strip_prefix("otpauth://") else {
+            return Ok(Self::build(
+                base32_decode(value)?, OtpAlgorithm::Sha1, DEFAULT_DIGITS,
+                OtpKind::Totp { period: DEFAULT_PERIOD }, String::new(), None,
+            ));
+        };
+
+        let (kind_name, rest) = rest.split_once('/')
+            .ok_or(otp_error("missing otpauth type"))?;
+        let (label, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let mut secret = None;
+        let mut issuer = None;
+        let mut algorithm = OtpAlgorithm::Sha1;
+        let mut digits = DEFAULT_DIGITS;
+        let mut period = DEFAULT_PERIOD;
+        let mut counter = None;
+        for pair in query.split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = Zeroizing::new(percent_decode(value)?);
+            match key.to_ascii_lowercase().as_str() {
+                "secret" => secret = Some(base32_decode(&value)?),
+                "issuer" => issuer = Some(value.to_string()),
+                "algorithm" => algorithm = match value.to_ascii_uppercase().as_str() {
+                    "SHA1" => OtpAlgorithm::Sha1,
+                    "SHA256" => OtpAlgorithm::Sha256,
+                    "SHA512" => OtpAlgorithm::Sha512,
+                    _ => return Err(otp_error(&format!("unsupported algorithm {}", value.as_str()))),
+                },
+                "digits" => digits = value.parse()?,
+                "period" => period = value.parse()?,
+                "counter" => counter = Some(value.parse()?),
+                _ => {},
+            }
+        }
+        if !(6..=9).contains(&digits) {
+            return Err(otp_error("digits must be between 6 and 9"));
+        }
+        let kind = match kind_name.to_ascii_lowercase().as_str() {
+            "totp" if period > 0 => OtpKind::Totp { period },
+            "totp" => return Err(otp_error("period must be positive")),
+            "hotp" => OtpKind::Hotp { counter: counter.ok_or(otp_error("missing hotp counter"))? },
+            _ => return Err(otp_error(&format!("unknown otpauth type {kind_name}"))),
+        };
+        let secret = secret.ok_or(otp_error("missing secret"))?;
+        Ok(Self::build(secret, algorithm, digits, kind, percent_decode(label)?, issuer))
+    }
+
+    fn build(secret: Vec<u8>, algorithm: OtpAlgorithm, digits: u32, kind: OtpKind,
+             label: String, issuer: Option<String>) -> Self
+    {
+        let secret = LockedBytes::from_slice(&Zeroizing::new(secret));
+        let mut otp = Self { secret, algorithm, digits, kind, label, issuer, uri: SecretString::default() };
+        otp.uri = otp.build_uri();
+        otp
+    }
+
+    fn build_uri(&self) -> SecretString {
+        let (kind, param) = match self.kind {
+            OtpKind::Totp { period } => ("totp", format!("period={period}")),
+            OtpKind::Hotp { counter } => ("hotp", format!("counter={counter}")),
+        };
+        let secret = Zeroizing::new(base32_encode(self.secret.as_slice()));
+        let mut uri = format!(
+            "otpauth://{kind}/{}?secret={}&algorithm={}&digits={}&{param}",
+            percent_encode(&self.label), secret.as_str(), self.algorithm.name(), self.digits
+        );
+        if let Some(issuer) = &self.issuer {
+            let _ = write!(uri, "&issuer={}", percent_encode(issuer));
+        }
+        SecretString::new(uri)
+    }
+
+    pub fn uri(&self) -> &str {
+        self.uri.expose()
+    }
+
+    pub fn kind(&self) -> OtpKind {
+        self.kind
+    }
+
+    // Current TOTP code at `unix_time` and the seconds it stays valid
+    pub fn totp(&self, unix_time: u64) -> Result<(String, u64), ClipassError> {
+        let OtpKind::Totp { period } = self.kind else {
+            return Err(otp_error("not a totp secret"));
+        };
+        let code = self.code(unix_time / period)?;
+        Ok((code, period - unix_time % period))
+    }
+
+    // Next HOTP code, the counter moves forward
+    pub fn next_hotp(&mut self) -> Result<String, ClipassError> {
+        let OtpKind::Hotp { counter } = self.kind else {
+            return Err(otp_error("not a hotp secret"));
+        };
+        let next = counter.checked_add(1).ok_or(otp_error("hotp counter exhausted"))?;
+        let code = self.code(counter)?;
+        self.kind = OtpKind::Hotp { counter: next };
+        self.uri = self.build_uri();
+        Ok(code)
+    }
+
+    pub fn code(&self, counter: u64) -> Result<String, ClipassError> {
+        let value = hotp(self.secret.as_slice(), counter, self.algorithm)?;
+        let code = value % 10u32.pow(self.digits);
+        Ok(format!("{code:0width$}", width = self.digits as usize))
+    }
+}
+
+// Leaves the secret out
+impl fmt::Debug for Otp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Otp")
+            .field("algorithm", &self.algorithm)
+            .field("digits", &self.digits)
+            .field("kind", &self.kind)
+            .field("label", &self.label)
+            .field("issuer", &self.issuer)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TryFrom<SecretString> for Otp {
+    type Error = ClipassError;
+
+    fn try_from(value: SecretString) -> Result<Self, Self::Error> {
+        Otp::parse(value.expose())
+    }
+}
+
+impl From<Otp> for SecretString {
+    fn from(value: Otp) -> Self {
+        value.uri
+    }
+}
+
+// RFC 4226 dynamic truncation of HMAC(secret, counter)
+pub fn hotp(secret: &[u8], counter: u64, algorithm: OtpAlgorithm) -> Result<u32, ClipassError> {
+    let message = counter.to_be_bytes();
+    let digest = match algorithm {
+        OtpAlgorithm::Sha1 => mac::<Hmac<Sha1>>(secret, &message)?,
+        OtpAlgorithm::Sha256 => mac::<Hmac<Sha256>>(secret, &message)?,
+        OtpAlgorithm::Sha512 => mac::<Hmac<Sha512>>(secret, &message)?,
+    };
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let bytes: [u8; 4] = digest[offset..offset + 4].try_into()
+        .map_err(|_| otp_error("short digest"))?;
+    Ok(u32::from_be_bytes(bytes) & 0x7fff_ffff)
+}
+
+fn mac<M: Mac + hmac::digest::KeyInit>(key: &[u8], message: &[u8]) -> Result<Vec<u8>, ClipassError> {
+    let mut mac = <M as hmac::digest::KeyInit>::new_from_slice(key)
+        .map_err(|_| otp_error("invalid key length"))?;
+    mac.update(message);
+    Ok(mac.finalize().into_bytes().to_vec())
+}
+
+pub fn base32_decode(value: &str) -> Result<Vec<u8>, ClipassError> {
+    let mut bytes = Vec::new();
+    let mut buffer: u64 = 0;
+    let mut bits = 0;
+    for c in value.chars().filter(|c| !c.is_whitespace() && *c != '-' && *c != '=') {
+        let index = BASE32_ALPHABET.iter()
+            .position(|a| *a as char == c.to_ascii_uppercase())
+            .ok_or(otp_error(&format!("invalid base32 character '{c}'")))?;
+        buffer = (buffer << 5) | index as u64;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    if bytes.is_empty() {
+        return Err(otp_error("empty secret"));
+    }
+    Ok(bytes)
+}
+
+pub fn base32_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    let mut buffer: u64 = 0;
+    let mut bits = 0;
+    for byte in bytes {
+        buffer = (buffer << 8) | *byte as u64;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    encoded
+}
+
+fn percent_decode(value: &str) -> Result<String, ClipassError> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut iter = value.bytes();
+    while let Some(b) = iter.next() {
+        match b {
+            b'%' => {
+                let hex: String = [iter.next(), iter.next()].iter().flatten().map(|b| *b as char).collect();
+                bytes.push(u8::from_str_radix(&hex, 16).map_err(|_| otp_error("invalid percent encoding"))?);
+            },
+            b'+' => bytes.push(b' '),
+            _ => bytes.push(b),
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| otp_error("invalid utf-8 in uri"))
+}
+
+fn percent_encode(value: &str) -> String {
+    value.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b':' | b'@' => (b as char).to_string(),
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+fn otp_error(message: &str) -> ClipassError {
+    ClipassError::Input(format!("otp: {message}"))
+}
@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+use regex::{Regex, RegexBuilder};
+use crate::error::ClipassError;
+use crate::vault::entry::Entry;
+
+/// Filter over entry ids given to `list`:
+/// `/regex/`, a glob when it holds `*`, `?` or `[`, else a substring.
+/// Matching ignores case.
+pub enum Filter {
+    Substring(String),
+    Glob(String),
+    Regex(Regex),
+}
+
+impl Filter {
+    pub fn parse(pattern: &str) -> Result<Self, ClipassError> {
+        if let Some(re) = pattern.strip_prefix('/').and_then(|p| p.strip_suffix('/')) {
+            let regex = RegexBuilder::new(re)
+                .case_insensitive(true)
+                .build()
+                .map_err(|e| ClipassError::Input(format!("invalid regex: {e}")))?;
+            return Ok(Filter::Regex(regex));
+        }
+        if pattern.contains(['*', '?', '[']) {
+            return Ok(Filter::Glob(pattern.to_lowercase()));
+        }
+        Ok(Filter::Substring(pattern.to_lowercase()))
+    }
+
+    pub fn matches(&self, id: &str) -> bool {
+        match self {
+            Filter::Substring(s) => id.to_lowercase().contains(s.as_str()),
+            Filter::Glob(g) => glob_match(g, &id.to_lowercase()),
+            Filter::Regex(r) => r.is_match(id),
+        }
+    }
+}
+
+// Shell style glob: `*` any run, `?` any character, `[abc]`/`[a-z]`/`[!a]` classes
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Last `*` position and the text position it is currently matching up to
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        let step = match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+                continue;
+            },
+            Some('?') => Some(1),
+            Some('[') => match_class(&pattern[p..], text[t]),
+            Some(c) if *c == text[t] => Some(1),
+            _ => None,
+        };
+        match (step, backtrack) {
+            (Some(len), _) => {
+                p += len;
+                t += 1;
+            },
+            (None, Some((star, matched))) => {
+                p = star + 1;
+                t = matched + 1;
+                backtrack = Some((star, matched + 1));
+            },
+            (None, None) => return false,
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+// Length of the class pattern when `c` matches it, an unclosed `[` is a literal
+fn match_class(pattern: &[char], c: char) -> Option<usize> {
+    let Some(end) = pattern.iter().skip(1).position(|x| *x == ']').map(|i| i + 1) else {
+        return (c == '[').then_some(1);
+    };
+    let mut class = &pattern[1..end];
+    let negate = class.first() == Some(&'!');
+    if negate {
+        class = &class[1..];
+    }
+    let mut matched = false;
+    let mut i = 0;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == '-' {
+            matched |= class[i] <= c && c <= class[i + 2];
+            i += 3;
+        } else {
+            matched |= class[i] == c;
+            i += 1;
+        }
+    }
+    (matched != negate).then_some(end + 1)
+}
+
+// Subsequence match of `query` in `candidate`, rewarding consecutive
+// characters and word starts, None when some query character is missing
+pub fn fuzzy_score(query: &str, candidate: &str) -> Option<i64> {
+    let candidate: Vec<char> = candidate.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut position = 0;
+    let mut previous: Option<usize> = None;
+    for q in query.to_lowercase().chars().filter(|c| !c.is_whitespace()) {
+        let found = position + candidate[position..].iter().position(|c| *c == q)?;
+        score += 1;
+        if previous.is_some_and(|p| p + 1 == found) {
+            score += 5;
+        }
+        if found == 0 || !candidate[found - 1].is_alphanumeric() {
+            score += 3;
+        }
+        // Late and scattered matches rank lower
+        score -= (found - position) as i64;
+        previous = Some(found);
+        position = found + 1;
+    }
+    if candidate.len() == position && previous.is_some() {
+        score += 2;
+    }
+    Some(score)
+}
+
+pub struct SearchMatch<'a> {
+    pub id: &'a str,
+    pub score: i64,
+    pub field: &'static str,
+}
+
+// Rank entries against `query`, on their id, username and urls
+pub fn find<'a>(entries: &'a HashMap<String, Entry>, query: &str) -> Vec<SearchMatch<'a>> {
+    let mut matches: Vec<SearchMatch> = entries.iter()
+        .filter_map(|(id, entry)| {
+            let mut candidates = vec![("id", id.as_str())];
+            if let Some(username) = entry.username() {
+                candidates.push(("username", username));
+            }
+            candidates.extend(entry.urls().into_iter().map(|url| ("url", url)));
+            candidates.into_iter()
+                .filter_map(|(field, candidate)| fuzzy_score(query, candidate).map(|score| (field, score)))
+                // Favor the id when it ties with another field
+                .max_by_key(|(field, score)| (*score, *field == "id"))
+                .map(|(field, score)| SearchMatch { id: id.as_str(), score, field })
+        })
+        .collect();
+    matches.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.id.cmp(b.id)));
+    matches
+}
@@ -0,0 +1,107 @@
+use std::fmt;
+use std::io;
+use std::str;
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use zeroize::{Zeroize, ZeroizeOnDrop};
+use crate::hardening::LockedBytes;
+
+/// A string holding secret data: kept in locked memory, wiped on drop, never printed.
+/// There is deliberately no `Display`, read it through `expose()`.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct SecretString(LockedBytes);
+
+impl SecretString {
+    // Moves the value to locked memory and wipes the original
+    pub fn new(mut value: String) -> Self {
+        let secret = Self(LockedBytes::from_slice(value.as_bytes()));
+        value.zeroize();
+        secret
+    }
+
+    pub fn expose(&self) -> &str {
+        // Only ever built from a str
+        unsafe { str::from_utf8_unchecked(self.0.as_slice()) }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+// LockedBytes wipes itself on drop
+impl ZeroizeOnDrop for SecretString {}
+
+impl From<String> for SecretString {
+    fn from(value: String) -> Self {
+        Self::new(value)
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(value: &str) -> Self {
+        Self(LockedBytes::from_slice(value.as_bytes()))
+    }
+}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SecretString(***)")
+    }
+}
+
+impl Serialize for SecretString {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.expose())
+    }
+}
+
+impl<'de> Deserialize<'de> for SecretString {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(SecretStringVisitor)
+    }
+}
+
+// Copies borrowed strings straight to locked memory, without a heap String in between
+struct SecretStringVisitor;
+
+impl Visitor<'_> for SecretStringVisitor {
+    type Value = SecretString;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a string")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        Ok(SecretString::from(value))
+    }
+
+    fn visit_string<E: de::Error>(self, value: String) -> Result<Self::Value, E> {
+        Ok(SecretString::new(value))
+    }
+}
+
+/// Growable byte buffer in locked memory that wipes every allocation it
+/// leaves behind, for serializing secrets without copies lingering on the heap
+#[derive(Default)]
+pub struct SecretBuffer(LockedBytes);
+
+impl SecretBuffer {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self(LockedBytes::with_capacity(capacity))
+    }
+
+    pub fn into_inner(self) -> LockedBytes {
+        self.0
+    }
+}
+
+impl io::Write for SecretBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -1,6 +1,7 @@
 use std::io;
 use std::io::{Read, Write};
 use std::str::FromStr;
+use std::time::Duration;
 use crate::error::ClipassError;
 
 pub fn input_read<T>(ask_msg: &str) -> Result<T, ClipassError>
@@ -33,4 +34,21 @@ where
             Err(e) => eprintln!("invalid input {} ({})", line.trim(), e),
         }
     }
-}
\ No newline at end of file
+}
+
+// Read a whole value from stdin, without its trailing newline
+pub fn read_stdin_value() -> Result<String, ClipassError> {
+    let mut buf = String::new();
+    io::stdin().read_to_string(&mut buf)?;
+    let len = buf.trim_end_matches(['\n', '\r']).len();
+    buf.truncate(len);
+    Ok(buf)
+}
+
+// Whether stdin has input within `timeout`, also true when it cannot be polled
+pub fn stdin_ready(timeout: Duration) -> bool {
+    let mut fd = libc::pollfd { fd: libc::STDIN_FILENO, events: libc::POLLIN, revents: 0 };
+    let timeout = i32::try_from(timeout.as_millis()).unwrap_or(i32::MAX);
+    // SAFETY: a single valid pollfd
+    unsafe { libc::poll(&mut fd, 1, timeout) != 0 }
+}
@@ -0,0 +1,126 @@
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use aes_gcm::aead::rand_core::RngCore;
+use rand::thread_rng;
+use crate::error::ClipassError;
+
+pub const DEFAULT_BACKUP_COUNT: usize = 3;
+const BACKUP_EXTENSION: &str = "bak";
+
+/// A rotated copy of a vault file, generation 1 being the most recent
+pub struct Backup {
+    pub generation: usize,
+    pub path: PathBuf,
+    pub modified_at: SystemTime,
+}
+
+pub fn backup_path(path: &Path, generation: usize) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{BACKUP_EXTENSION}.{generation}"));
+    path.with_file_name(name)
+}
+
+// Shift every generation by one and copy the current file as generation 1,
+// the oldest generations beyond `count` are dropped
+pub fn rotate_backups(path: &Path, count: usize) -> Result<(), ClipassError> {
+    if count == 0 || !path.exists() {
+        return Ok(());
+    }
+    for backup in list_backups(path)? {
+        if backup.generation >= count {
+            fs::remove_file(&backup.path)?;
+        }
+    }
+    for generation in (1..count).rev() {
+        let from = backup_path(path, generation);
+        if from.exists() {
+            fs::rename(&from, backup_path(path, generation + 1))?;
+        }
+    }
+    fs::copy(path, backup_path(path, 1))?;
+    Ok(())
+}
+
+// Copy the current file as <vault>.<suffix>, unless that copy already exists
+pub fn keep_copy(path: &Path, suffix: &str) -> Result<PathBuf, ClipassError> {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{suffix}"));
+    let copy = path.with_file_name(name);
+    if !copy.exists() {
+        fs::copy(path, &copy)?;
+    }
+    Ok(copy)
+}
+
+pub fn list_backups(path: &Path) -> Result<Vec<Backup>, ClipassError> {
+    let dir = parent_dir(path);
+    let prefix = format!(
+        "{}.{BACKUP_EXTENSION}.",
+        path.file_name().unwrap_or_default().to_string_lossy()
+    );
+    let mut backups = Vec::new();
+    for dir_entry in fs::read_dir(dir)? {
+        let dir_entry = dir_entry?;
+        let name = dir_entry.file_name().to_string_lossy().to_string();
+        let generation = match name.strip_prefix(&prefix).map(str::parse::<usize>) {
+            Some(Ok(g)) if g > 0 => g,
+            _ => continue,
+        };
+        backups.push(Backup {
+            generation,
+            path: dir_entry.path(),
+            modified_at: dir_entry.metadata()?.modified()?,
+        });
+    }
+    backups.sort_by_key(|b| b.generation);
+    Ok(backups)
+}
+
+// Write to a temporary file next to `path`, fsync it and rename it over `path`
+// so that a crash leaves either the old or the new file, never a truncated one
+pub fn write_atomic(path: &Path, chunks: &[&[u8]]) -> Result<(), ClipassError> {
+    let dir = parent_dir(path);
+    let tmp_path = dir.join(format!(
+        ".{}.tmp-{:016x}",
+        path.file_name().unwrap_or_default().to_string_lossy(),
+        thread_rng().next_u64()
+    ));
+
+    let res = write_and_sync(&tmp_path, chunks)
+        .and_then(|_| Ok(fs::rename(&tmp_path, path)?));
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+        return res;
+    }
+
+    // Persist the rename itself
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+fn write_and_sync(path: &Path, chunks: &[&[u8]]) -> Result<(), ClipassError> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    for chunk in chunks {
+        file.write_all(chunk)?;
+    }
+    file.sync_all()?;
+    Ok(())
+}
+
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use serde::de::{self, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use crate::error::ClipassError;
+use crate::otp::Otp;
+use crate::secret::SecretString;
+
+pub const FIELD_USERNAME: &str = "username";
+pub const FIELD_PASSWORD: &str = "password";
+pub const FIELD_URL: &str = "url";
+pub const FIELD_NOTES: &str = "notes";
+pub const FIELD_OTP: &str = "otp";
+
+/// A vault entry: the well known login fields plus free custom fields.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    username: Option<SecretString>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    password: Option<SecretString>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    urls: Vec<SecretString>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    notes: Option<SecretString>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    otp: Option<Otp>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    custom: BTreeMap<String, SecretString>,
+}
+
+impl Entry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_password(password: &str) -> Self {
+        Self { password: Some(password.into()), ..Self::default() }
+    }
+
+    pub fn username(&self) -> Option<&str> {
+        self.username.as_ref().map(SecretString::expose)
+    }
+
+    pub fn password(&self) -> Option<&str> {
+        self.password.as_ref().map(SecretString::expose)
+    }
+
+    pub fn urls(&self) -> Vec<&str> {
+        self.urls.iter().map(SecretString::expose).collect()
+    }
+
+    pub fn notes(&self) -> Option<&str> {
+        self.notes.as_ref().map(SecretString::expose)
+    }
+
+    pub fn otp(&self) -> Option<&Otp> {
+        self.otp.as_ref()
+    }
+
+    pub fn otp_mut(&mut self) -> Option<&mut Otp> {
+        self.otp.as_mut()
+    }
+
+    pub fn custom(&self) -> &BTreeMap<String, SecretString> {
+        &self.custom
+    }
+
+    // Get a field by name, "url" being the first url of the entry
+    pub fn field(&self, name: &str) -> Option<&str> {
+        match name {
+            FIELD_USERNAME => self.username(),
+            FIELD_PASSWORD => self.password(),
+            FIELD_URL => self.urls.first().map(SecretString::expose),
+            FIELD_NOTES => self.notes(),
+            FIELD_OTP => self.otp.as_ref().map(Otp::uri),
+            _ => self.custom.get(name).map(SecretString::expose),
+        }
+    }
+
+    // Set a field by name, an empty value removes it.
+    // "url" takes a whitespace separated list of urls,
+    // "otp" an otpauth:// uri or a base32 totp secret.
+    pub fn set_field(&mut self, name: &str, value: &str) -> Result<(), ClipassError> {
+        let value = (!value.is_empty()).then(|| SecretString::from(value));
+        match name {
+            FIELD_USERNAME => self.username = value,
+            FIELD_PASSWORD => self.password = value,
+            FIELD_URL => {
+                self.urls = value.iter()
+                    .flat_map(|v| v.expose().split_whitespace())
+                    .map(SecretString::from)
+                    .collect();
+            },
+            FIELD_NOTES => self.notes = value,
+            FIELD_OTP => self.otp = value.as_ref().map(|v| Otp::parse(v.expose())).transpose()?,
+            _ => match value {
+                Some(v) => { self.custom.insert(name.to_string(), v); },
+                None => { self.custom.remove(name); },
+            },
+        }
+        Ok(())
+    }
+
+    // All the fields that hold a value, in display order
+    pub fn fields(&self) -> Vec<(&str, &str)> {
+        let mut fields = Vec::new();
+        if let Some(u) = self.username() {
+            fields.push((FIELD_USERNAME, u));
+        }
+        if let Some(p) = self.password() {
+            fields.push((FIELD_PASSWORD, p));
+        }
+        for url in &self.urls {
+            fields.push((FIELD_URL, url.expose()));
+        }
+        if let Some(n) = self.notes() {
+            fields.push((FIELD_NOTES, n));
+        }
+        if let Some(otp) = &self.otp {
+            fields.push((FIELD_OTP, otp.uri()));
+        }
+        for (name, value) in &self.custom {
+            fields.push((name.as_str(), value.expose()));
+        }
+        fields
+    }
+}
+
+/// On-disk representation of an entry: v3 vaults stored bare strings,
+/// which are read back as password-only entries.
+pub(crate) enum StoredEntry {
+    Legacy(SecretString),
+    Entry(Box<Entry>),
+}
+
+// Told apart by the JSON type, unlike an untagged enum nothing is buffered,
+// values go straight to SecretString
+impl<'de> Deserialize<'de> for StoredEntry {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(StoredEntryVisitor)
+    }
+}
+
+struct StoredEntryVisitor;
+
+impl<'de> Visitor<'de> for StoredEntryVisitor {
+    type Value = StoredEntry;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an entry or a legacy password string")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        Ok(StoredEntry::Legacy(SecretString::from(value)))
+    }
+
+    fn visit_string<E: de::Error>(self, value: String) -> Result<Self::Value, E> {
+        Ok(StoredEntry::Legacy(SecretString::new(value)))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+        let entry = Entry::deserialize(de::value::MapAccessDeserializer::new(map))?;
+        Ok(StoredEntry::Entry(Box::new(entry)))
+    }
+}
+
+impl From<StoredEntry> for Entry {
+    fn from(value: StoredEntry) -> Self {
+        match value {
+            StoredEntry::Legacy(password) => Entry { password: Some(password), ..Entry::default() },
+            StoredEntry::Entry(entry) => *entry,
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use std::io::Read;
+use argon2::password_hash::SaltString;
+use crate::crypto;
+use crate::crypto::{Cipher, KdfParams, Key, Keyfile, WRAPPED_KEY_SIZE};
+use crate::error::ClipassError;
+use crate::vault::{NONCE_SIZE, SALT_SIZE};
+
+/*
+              *** KEY SLOT ***
+*****************************************
+The vault content is encrypted with a random data key, each slot holds
+that key wrapped by a key derived from one credential.
+
+  - Kind {1}            : 1 password, 2 recovery code
+  - Flags {1}           : 0x1 a keyfile is required
+  - Label length {1}
+  - Label {n}           : utf-8, at most MAX_LABEL_SIZE
+  - KDF {12}
+  - Salt {32}
+  - Nonce {12}
+  - Wrapped key {48}    : AES-256-GCM of the data key, everything
+                          before the nonce as associated data
+****************************************
+*/
+
+pub const MAX_SLOTS: usize = 32;
+pub const MAX_LABEL_SIZE: usize = 64;
+const SLOT_FLAG_KEYFILE: u8 = 0x1;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SlotKind {
+    Password,
+    Recovery,
+}
+
+impl SlotKind {
+    fn id(&self) -> u8 {
+        match self {
+            SlotKind::Password => 1,
+            SlotKind::Recovery => 2,
+        }
+    }
+
+    fn from_id(id: u8) -> Result<Self, ClipassError> {
+        match id {
+            1 => Ok(SlotKind::Password),
+            2 => Ok(SlotKind::Recovery),
+            _ => Err(ClipassError::HeaderError(format!("unknown key slot kind {id}"))),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SlotKind::Password => "password",
+            SlotKind::Recovery => "recovery",
+        }
+    }
+}
+
+/// What a user unlocks a vault with
+pub enum Credential<'a> {
+    Password(&'a str, Option<&'a Keyfile>),
+    Recovery(&'a str), // as given by recovery::normalize
+    DataKey(&'a Key),  // rebuilt from key shares, opens no slot
+}
+
+impl<'a> Credential<'a> {
+    pub fn password(password: &'a str) -> Self {
+        Credential::Password(password, None)
+    }
+
+    pub fn with_keyfile(password: &'a str, keyfile: Option<&'a Keyfile>) -> Self {
+        Credential::Password(password, keyfile)
+    }
+
+    fn kind(&self) -> Option<SlotKind> {
+        match self {
+            Credential::Password(..) => Some(SlotKind::Password),
+            Credential::Recovery(_) => Some(SlotKind::Recovery),
+            Credential::DataKey(_) => None,
+        }
+    }
+
+    pub(crate) fn keyfile(&self) -> Option<&'a Keyfile> {
+        match self {
+            Credential::Password(_, keyfile) => *keyfile,
+            _ => None,
+        }
+    }
+
+    fn secret(&self) -> &str {
+        match self {
+            Credential::Password(password, _) => password,
+            Credential::Recovery(code) => code,
+            Credential::DataKey(_) => "",
+        }
+    }
+}
+
+fn slot_kind(credential: &Credential) -> Result<SlotKind, ClipassError> {
+    credential.kind().ok_or(ClipassError::Input("a data key cannot seal a key slot".to_string()))
+}
+
+#[derive(Clone)]
+pub struct KeySlot {
+    kind: SlotKind,
+    keyfile: bool,
+    label: String,
+    kdf: KdfParams,
+    salt: SaltString,
+    nonce: Vec<u8>,
+    wrapped: Vec<u8>,
+}
+
+impl KeySlot {
+    // Wrap `data_key` with a key derived from `credential`
+    pub fn seal(credential: &Credential, label: &str, kdf: KdfParams, data_key: &Key) -> Result<Self, ClipassError> {
+        slot_kind(credential)?;
+        let salt = crypto::generate_salt()?;
+        let (kek, kdf) = crypto::derive_key(credential.secret(), &salt, Some(kdf), credential.keyfile())?;
+        Self::seal_with_kek(credential, label, kdf, salt, &kek, data_key)
+    }
+
+    // Same as seal, from an already derived key encryption key
+    pub(crate) fn seal_with_kek(credential: &Credential, label: &str, kdf: KdfParams, salt: SaltString,
+                                kek: &Key, data_key: &Key) -> Result<Self, ClipassError>
+    {
+        if label.len() > MAX_LABEL_SIZE {
+            return Err(ClipassError::Input(format!("slot label longer than {MAX_LABEL_SIZE} bytes")));
+        }
+        let mut slot = Self {
+            kind: slot_kind(credential)?,
+            keyfile: credential.keyfile().is_some(),
+            label: label.to_string(),
+            kdf,
+            salt,
+            nonce: crypto::generate_nonce(Cipher::Aes256Gcm),
+            wrapped: Vec::new(),
+        };
+        slot.wrapped = crypto::wrap_key(kek, &slot.nonce, data_key, &slot.metadata()?)?;
+        Ok(slot)
+    }
+
+    // The data key, None when the credential does not open this slot
+    pub fn open(&self, credential: &Credential) -> Result<Option<Key>, ClipassError> {
+        if credential.kind() != Some(self.kind) || (self.keyfile && credential.keyfile().is_none()) {
+            return Ok(None);
+        }
+        let keyfile = credential.keyfile().filter(|_| self.keyfile);
+        let (kek, _) = crypto::derive_key(credential.secret(), &self.salt, Some(self.kdf.clone()), keyfile)?;
+        Ok(crypto::unwrap_key(&kek, &self.nonce, &self.wrapped, &self.metadata()?).ok())
+    }
+
+    pub fn kind(&self) -> SlotKind {
+        self.kind
+    }
+
+    pub fn requires_keyfile(&self) -> bool {
+        self.keyfile
+    }
+
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    pub fn kdf(&self) -> &KdfParams {
+        &self.kdf
+    }
+
+    // Everything authenticated along the wrapped key
+    fn metadata(&self) -> Result<Vec<u8>, ClipassError> {
+        let mut buf = Vec::new();
+        buf.push(self.kind.id());
+        buf.push(if self.keyfile { SLOT_FLAG_KEYFILE } else { 0 });
+        buf.push(self.label.len() as u8);
+        buf.extend_from_slice(self.label.as_bytes());
+        buf.extend_from_slice(&self.kdf.memory_cost.to_le_bytes());
+        buf.extend_from_slice(&self.kdf.time_cost.to_le_bytes());
+        buf.extend_from_slice(&self.kdf.parallelism.to_le_bytes());
+        let mut salt_bytes = [0u8; SALT_SIZE];
+        self.salt.decode_b64(&mut salt_bytes)?;
+        buf.extend_from_slice(&salt_bytes);
+        Ok(buf)
+    }
+
+    pub fn serialize(&self, buf: &mut Vec<u8>) -> Result<(), ClipassError> {
+        buf.extend_from_slice(&self.metadata()?);
+        buf.extend_from_slice(&self.nonce);
+        buf.extend_from_slice(&self.wrapped);
+        Ok(())
+    }
+
+    pub fn deserialize<R: Read>(reader: &mut R) -> Result<Self, ClipassError> {
+        fn read_exact<const N: usize, R: Read>(reader: &mut R) -> Result<[u8; N], ClipassError> {
+            let mut buf = [0u8; N];
+            reader.read_exact(&mut buf)?;
+            Ok(buf)
+        }
+
+        let [kind, flags, label_len] = read_exact::<3, R>(reader)?;
+        let kind = SlotKind::from_id(kind)?;
+        if flags & !SLOT_FLAG_KEYFILE != 0 {
+            return Err(ClipassError::HeaderError(format!("unsupported key slot flags {flags:#x}")));
+        }
+        let mut label = vec![0u8; label_len as usize];
+        reader.read_exact(&mut label)?;
+        let label = String::from_utf8(label)
+            .map_err(|_| ClipassError::HeaderError("key slot label is not utf-8".to_string()))?;
+        let kdf = KdfParams {
+            memory_cost: u32::from_le_bytes(read_exact::<4, R>(reader)?),
+            time_cost: u32::from_le_bytes(read_exact::<4, R>(reader)?),
+            parallelism: u32::from_le_bytes(read_exact::<4, R>(reader)?),
+        };
+        kdf.check().map_err(|e| ClipassError::HeaderError(format!("key slot \"{label}\": {e}")))?;
+        let salt = SaltString::encode_b64(&read_exact::<SALT_SIZE, R>(reader)?)?;
+        let nonce = read_exact::<NONCE_SIZE, R>(reader)?.to_vec();
+        let wrapped = read_exact::<WRAPPED_KEY_SIZE, R>(reader)?.to_vec();
+        Ok(Self { kind, keyfile: flags & SLOT_FLAG_KEYFILE != 0, label, kdf, salt, nonce, wrapped })
+    }
+}
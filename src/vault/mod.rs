@@ -1,5 +1,11 @@
+#[allow(clippy::module_inception)]
 pub mod vault;
+pub mod entry;
+pub mod backup;
+pub mod key_slot;
+pub mod recovery;
+pub mod shamir;
 mod vault_header;
 
-const SALT_SIZE: usize = 32;
+pub(crate) const SALT_SIZE: usize = 32;
 const NONCE_SIZE: usize = 12;
@@ -0,0 +1,66 @@
+use rand::thread_rng;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+use crate::error::ClipassError;
+use crate::otp::{base32_decode, base32_encode};
+use crate::secret::SecretString;
+
+/*
+  Printable codes (recovery codes, key shares): the bytes in base32 followed
+  by a 4 characters checksum (first 20 bits of their SHA-256, base32), in
+  groups of 4. A recovery code is 160 random bits:
+      XXXX-XXXX-XXXX-XXXX-XXXX-XXXX-XXXX-XXXX-CCCC
+  Its key slot derives its key from the 32 characters without dashes.
+*/
+
+const SECRET_SIZE: usize = 20;
+const CHECKSUM_CHARS: usize = 4;
+const GROUP_SIZE: usize = 4;
+
+pub fn generate() -> SecretString {
+    let mut secret = Zeroizing::new([0u8; SECRET_SIZE]);
+    thread_rng().fill_bytes(secret.as_mut_slice());
+    encode_checked(secret.as_slice())
+}
+
+// Check a typed recovery code, ignoring case, spaces and dashes.
+// Returns the form its key slot is derived from.
+pub fn normalize(code: &str) -> Result<SecretString, ClipassError> {
+    let secret = decode_checked(code, SECRET_SIZE, "recovery code")?;
+    Ok(SecretString::new(base32_encode(&secret)))
+}
+
+// Base32 of `bytes` and its checksum, in dash separated groups
+pub(crate) fn encode_checked(bytes: &[u8]) -> SecretString {
+    let encoded = Zeroizing::new(base32_encode(bytes) + &checksum(bytes));
+    let groups: Vec<&str> = encoded.as_bytes()
+        .chunks(GROUP_SIZE)
+        .map(|group| std::str::from_utf8(group).unwrap_or_default())
+        .collect();
+    SecretString::new(groups.join("-"))
+}
+
+// Inverse of encode_checked for `size` bytes, `what` naming the code in errors
+pub(crate) fn decode_checked(text: &str, size: usize, what: &str) -> Result<Zeroizing<Vec<u8>>, ClipassError> {
+    let error = |message: &str| ClipassError::Input(format!("{what}: {message}"));
+    let text = Zeroizing::new(text.chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .map(|c| c.to_ascii_uppercase())
+        .collect::<String>());
+    let data_chars = (size * 8).div_ceil(5);
+    if text.len() != data_chars + CHECKSUM_CHARS || !text.is_ascii() {
+        return Err(error("wrong length"));
+    }
+    let (data, check) = text.split_at(data_chars);
+    let bytes = Zeroizing::new(base32_decode(data).map_err(|_| error("invalid character"))?);
+    if bytes.len() != size || checksum(&bytes) != check {
+        return Err(error("checksum mismatch, check for typos"));
+    }
+    Ok(bytes)
+}
+
+fn checksum(bytes: &[u8]) -> String {
+    let digest = Sha256::digest(bytes);
+    base32_encode(&digest[..3])[..CHECKSUM_CHARS].to_string()
+}
@@ -0,0 +1,146 @@
+use rand::thread_rng;
+use rand::RngCore;
+use zeroize::Zeroizing;
+use crate::crypto::{Key, KEY_SIZE};
+use crate::error::ClipassError;
+use crate::hardening::LockedBytes;
+use crate::secret::SecretString;
+use crate::vault::recovery;
+
+/*
+              *** KEY SHARES ***
+*****************************************
+Shamir secret sharing of the data key over GF(256): every key byte is the
+constant term of a random polynomial of degree threshold - 1, share x holds
+the values at x. Any threshold shares rebuild the key, fewer tell nothing.
+
+  - Threshold {1}
+  - Index {1}           : x, 1 to 255
+  - Split id {4}        : random, the same for all shares of one split
+  - Value {32}
+
+Printed like recovery codes: base32 with a checksum, in groups of 4.
+****************************************
+*/
+
+const SPLIT_ID_SIZE: usize = 4;
+const SHARE_SIZE: usize = 2 + SPLIT_ID_SIZE + KEY_SIZE;
+
+pub struct Share {
+    threshold: u8,
+    index: u8,
+    split_id: [u8; SPLIT_ID_SIZE],
+    value: LockedBytes,
+}
+
+impl Share {
+    pub fn threshold(&self) -> u8 {
+        self.threshold
+    }
+
+    pub fn index(&self) -> u8 {
+        self.index
+    }
+
+    pub fn encode(&self) -> SecretString {
+        let mut bytes = Zeroizing::new(Vec::with_capacity(SHARE_SIZE));
+        bytes.push(self.threshold);
+        bytes.push(self.index);
+        bytes.extend_from_slice(&self.split_id);
+        bytes.extend_from_slice(self.value.as_slice());
+        recovery::encode_checked(&bytes)
+    }
+
+    // Check a typed share, ignoring case, spaces and dashes
+    pub fn parse(text: &str) -> Result<Self, ClipassError> {
+        let bytes = recovery::decode_checked(text, SHARE_SIZE, "key share")?;
+        let (threshold, index) = (bytes[0], bytes[1]);
+        if threshold < 2 || index == 0 {
+            return Err(ClipassError::Input("key share: invalid threshold or index".to_string()));
+        }
+        let mut split_id = [0u8; SPLIT_ID_SIZE];
+        split_id.copy_from_slice(&bytes[2..2 + SPLIT_ID_SIZE]);
+        Ok(Self { threshold, index, split_id, value: LockedBytes::from_slice(&bytes[2 + SPLIT_ID_SIZE..]) })
+    }
+}
+
+// `count` shares of `key`, any `threshold` of them rebuild it
+pub fn split(key: &Key, threshold: u8, count: u8) -> Result<Vec<Share>, ClipassError> {
+    if threshold < 2 || threshold > count {
+        return Err(ClipassError::Input("the threshold must be between 2 and the number of shares".to_string()));
+    }
+    let mut split_id = [0u8; SPLIT_ID_SIZE];
+    thread_rng().fill_bytes(&mut split_id);
+    let mut shares: Vec<Share> = (1..=count)
+        .map(|index| Share { threshold, index, split_id, value: LockedBytes::zeroed(KEY_SIZE) })
+        .collect();
+
+    let mut coefficients = Zeroizing::new(vec![0u8; threshold as usize]);
+    for (i, secret) in key.as_bytes().iter().enumerate() {
+        coefficients[0] = *secret;
+        thread_rng().fill_bytes(&mut coefficients[1..]);
+        for share in shares.iter_mut() {
+            // Horner's rule, from the highest degree
+            share.value.as_mut_slice()[i] = coefficients.iter().rev()
+                .fold(0, |acc, c| gf_mul(acc, share.index) ^ c);
+        }
+    }
+    Ok(shares)
+}
+
+// Rebuild the key from at least threshold shares of the same split
+pub fn combine(shares: &[Share]) -> Result<Key, ClipassError> {
+    let Some(first) = shares.first() else {
+        return Err(ClipassError::Input("no key share given".to_string()));
+    };
+    if shares.iter().any(|s| s.split_id != first.split_id || s.threshold != first.threshold) {
+        return Err(ClipassError::Input("key shares come from different splits".to_string()));
+    }
+    let mut indexes: Vec<u8> = shares.iter().map(Share::index).collect();
+    indexes.sort_unstable();
+    indexes.dedup();
+    if indexes.len() != shares.len() {
+        return Err(ClipassError::Input("the same key share was given twice".to_string()));
+    }
+    if shares.len() < first.threshold as usize {
+        return Err(ClipassError::Input(format!("{} key shares needed, {} given", first.threshold, shares.len())));
+    }
+
+    // Lagrange interpolation at x = 0, subtraction is xor in GF(256)
+    let shares = &shares[..first.threshold as usize];
+    let mut key = LockedBytes::zeroed(KEY_SIZE);
+    for share in shares {
+        let basis = shares.iter()
+            .filter(|other| other.index != share.index)
+            .fold(1, |acc, other| gf_mul(acc, gf_mul(other.index, gf_inv(other.index ^ share.index))));
+        for (byte, value) in key.as_mut_slice().iter_mut().zip(share.value.as_slice()) {
+            *byte ^= gf_mul(*value, basis);
+        }
+    }
+    Ok(Key::from_bytes(key))
+}
+
+// Multiplication modulo x^8 + x^4 + x^3 + x + 1, without secret dependent branches
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    for _ in 0..8 {
+        product ^= (b & 1).wrapping_neg() & a;
+        let carry = (a >> 7).wrapping_neg();
+        a = (a << 1) ^ (carry & 0x1b);
+        b >>= 1;
+    }
+    product
+}
+
+// a^254, the inverse of a non zero a
+fn gf_inv(a: u8) -> u8 {
+    let mut result = 1;
+    let mut power = a;
+    for bit in 0..8 {
+        if 254u8 >> bit & 1 == 1 {
+            result = gf_mul(result, power);
+        }
+        power = gf_mul(power, power);
+    }
+    result
+}
@@ -1,46 +1,104 @@
 use crate::crypto;
-use crate::crypto::{KdfParams, Key};
+use crate::crypto::{Cipher, KdfParams, Key, Keyfile};
 use crate::error::ClipassError;
-use crate::vault::vault_header::{VaultHeader, HEADER_SIZE};
+use crate::secret::SecretBuffer;
+use crate::vault::backup;
+use crate::vault::backup::DEFAULT_BACKUP_COUNT;
+use crate::vault::entry::{Entry, StoredEntry, FIELD_OTP};
+use crate::secret::SecretString;
+use crate::vault::key_slot::{Credential, KeySlot, SlotKind, MAX_SLOTS};
+use crate::vault::recovery;
+use crate::vault::shamir;
+use crate::vault::shamir::Share;
+use crate::vault::vault_header::{VaultHeader, VERSION};
 use crate::vault::{NONCE_SIZE, SALT_SIZE};
-use aes_gcm::aead::rand_core::RngCore;
-use argon2::password_hash::SaltString;
 use chrono::{DateTime, Local};
-use rand::thread_rng;
 use std::collections::HashMap;
 use std::fs;
-use std::fs::File;
-use std::io::Write;
+use std::path::Path;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+const DEFAULT_SLOT_LABEL: &str = "default";
+const RECOVERY_SLOT_LABEL: &str = "recovery";
+
+/// What a vault file tells without being unlocked
+pub struct VaultInfo {
+    pub version: u16,
+    pub file_size: usize,
+    pub header_size: usize,
+    pub created_at: DateTime<Local>,
+    pub modified_at: DateTime<Local>,
+    pub cipher: Cipher,
+    pub legacy_kdf: Option<KdfParams>, // vaults without key slots
+    pub slots: Vec<KeySlot>,
+}
+
+/// A vault dropped from memory with its data key, unsaved changes kept encrypted as in its file
+pub struct LockedVault {
+    sealed: Option<Vec<u8>>, // only with unsaved changes, the file is loaded again otherwise
+    backup_count: usize,
+}
+
+impl LockedVault {
+    pub fn unlock(&self, credential: &Credential, path: &str) -> Result<Vault, ClipassError> {
+        let mut vault = match &self.sealed {
+            Some(sealed) => {
+                let mut vault = Vault::open_sealed(credential, sealed.clone())?;
+                vault.updated = true;
+                vault
+            },
+            None => Vault::load_with_credential(credential, path)?,
+        };
+        vault.backup_count = self.backup_count;
+        Ok(vault)
+    }
+
+    // Whether unlock() reads the vault file
+    pub fn from_file(&self) -> bool {
+        self.sealed.is_none()
+    }
+}
 
 pub struct Vault {
-    entries: HashMap<String, String>,
-    kdf_params: KdfParams,
+    entries: HashMap<String, Entry>,
+    kdf_params: KdfParams, // for new key slots
     updated: bool,
     created_at: SystemTime,
     modified_at: SystemTime,
-    salt: SaltString,
-    key: Key,
+    key: Key, // random data key, wrapped in every slot
+    slots: Vec<KeySlot>,
+    cipher: Cipher,
+    backup_count: usize,
 }
 
 impl Vault {
     pub fn new_empty(master_password: &str) -> Result<Self, ClipassError> {
-        let mut salt_bytes = [0u8; 32];
-        thread_rng().fill_bytes(&mut salt_bytes);
-        let salt = SaltString::encode_b64(&salt_bytes)?;
-        let (key, kdf_params) = crypto::derive_key(master_password, &salt, None)?;
+        Self::new_empty_with_kdf(master_password, KdfParams::default())
+    }
+
+    pub fn new_empty_with_kdf(master_password: &str, kdf_params: KdfParams) -> Result<Self, ClipassError> {
+        Self::create(&Credential::password(master_password), kdf_params)
+    }
+
+    // New vault with a single key slot for `credential`
+    pub fn create(credential: &Credential, kdf_params: KdfParams) -> Result<Self, ClipassError> {
+        kdf_params.check().map_err(ClipassError::Input)?;
+        let key = Key::generate();
+        let slot = KeySlot::seal(credential, DEFAULT_SLOT_LABEL, kdf_params.clone(), &key)?;
         let created_at = SystemTime::now();
-        Ok(Self {entries: HashMap::new(), created_at, modified_at: created_at, salt, key, kdf_params, updated: false})
+        Ok(Self {
+            entries: HashMap::new(), created_at, modified_at: created_at, key, slots: vec![slot], kdf_params,
+            cipher: Cipher::default(), updated: false, backup_count: DEFAULT_BACKUP_COUNT,
+        })
     }
 
-    pub fn new_entry(&mut self, key: &str, value: &str)
+    pub fn new_entry(&mut self, key: &str, entry: Entry)
         -> Result<(), ClipassError>
     {
         if self.entries.contains_key(key) {
             return Err(ClipassError::IdExists(key.to_string()));
         }
-        self.entries.insert(key.to_string(), value.to_string());
+        self.entries.insert(key.to_string(), entry);
         self.updated = true;
         Ok(())
     }
@@ -58,34 +116,226 @@ impl Vault {
         self.entries.contains_key(key)
     }
 
-    pub fn get_value(&self, key: &str) -> Result<&String, ClipassError> {
+    pub fn get_entry(&self, key: &str) -> Result<&Entry, ClipassError> {
         match self.entries.get(key) {
             None => Err(ClipassError::NotFound(key.to_string())),
+            Some(e) => Ok(e),
+        }
+    }
+
+    pub fn get_value(&self, key: &str, field: &str) -> Result<&str, ClipassError> {
+        match self.get_entry(key)?.field(field) {
+            None => Err(ClipassError::FieldNotFound(key.to_string(), field.to_string())),
             Some(v) => Ok(v),
         }
     }
 
-    pub fn get_all(&self) -> &HashMap<String, String> {
+    pub fn get_all(&self) -> &HashMap<String, Entry> {
         &self.entries
     }
 
-    pub fn update(&mut self, key: &str, value: &str) -> Result<(), ClipassError> {
-        let value_ref = match self.entries.get_mut(key) {
+    pub fn update(&mut self, key: &str, field: &str, value: &str) -> Result<(), ClipassError> {
+        let entry = match self.entries.get_mut(key) {
             None => return Err(ClipassError::NotFound(key.to_string())),
-            Some(v) => v,
+            Some(e) => e,
         };
-        *value_ref = value.to_string();
+        entry.set_field(field, value)?;
         self.updated = true;
         Ok(())
     }
 
+    // Current TOTP code of an entry and the seconds it stays valid
+    pub fn totp(&self, key: &str) -> Result<(String, u64), ClipassError> {
+        let otp = self.get_entry(key)?.otp()
+            .ok_or(ClipassError::FieldNotFound(key.to_string(), FIELD_OTP.to_string()))?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        otp.totp(now)
+    }
+
+    // Next HOTP code of an entry, the moved counter is saved with the vault
+    pub fn hotp(&mut self, key: &str) -> Result<String, ClipassError> {
+        let otp = self.entries.get_mut(key)
+            .ok_or(ClipassError::NotFound(key.to_string()))?
+            .otp_mut()
+            .ok_or(ClipassError::FieldNotFound(key.to_string(), FIELD_OTP.to_string()))?;
+        let code = otp.next_hotp()?;
+        self.updated = true;
+        Ok(code)
+    }
+
+    // Replace the key slot opened by the old password
+    pub fn change_master_password(&mut self, old_password: &str, new_password: &str)
+        -> Result<(), ClipassError>
+    {
+        self.change_password(&Credential::password(old_password), new_password)
+    }
+
+    // Change the password of the slot `current` opens, it keeps its keyfile requirement.
+    // Written on the next crypt_to_file.
+    pub fn change_password(&mut self, current: &Credential, new_password: &str) -> Result<(), ClipassError> {
+        let index = self.find_password_slot(current)?;
+        let keyfile = current.keyfile().filter(|_| self.slots[index].requires_keyfile());
+        self.reseal_slot(index, &Credential::with_keyfile(new_password, keyfile))
+    }
+
+    // Require a keyfile for the slot `current` opens, or stop requiring one with None
+    pub fn set_keyfile(&mut self, current: &Credential, keyfile: Option<&Keyfile>) -> Result<(), ClipassError> {
+        let index = self.find_password_slot(current)?;
+        let Credential::Password(password, _) = current else {
+            unreachable!("find_password_slot only accepts passwords");
+        };
+        self.reseal_slot(index, &Credential::with_keyfile(password, keyfile))
+    }
+
+    // Derive the key of the slot `current` opens with `kdf`, used for new slots too.
+    // Other slots keep their parameters until their password changes.
+    pub fn set_kdf(&mut self, current: &Credential, kdf: KdfParams) -> Result<(), ClipassError> {
+        kdf.check().map_err(ClipassError::Input)?;
+        let index = self.find_password_slot(current)?;
+        let Credential::Password(password, keyfile) = current else {
+            unreachable!("find_password_slot only accepts passwords");
+        };
+        let keyfile = keyfile.filter(|_| self.slots[index].requires_keyfile());
+        self.kdf_params = kdf;
+        self.reseal_slot(index, &Credential::with_keyfile(password, keyfile))
+    }
+
+    pub fn kdf_params(&self) -> &KdfParams {
+        &self.kdf_params
+    }
+
+    // Set a new password on a password slot without knowing the old one, after a recovery.
+    // The slot no longer requires a keyfile.
+    pub fn reset_password(&mut self, index: usize, new_password: &str) -> Result<(), ClipassError> {
+        match self.slots.get(index).map(KeySlot::kind) {
+            Some(SlotKind::Password) => self.reseal_slot(index, &Credential::password(new_password)),
+            Some(_) => Err(ClipassError::Input(format!("key slot {index} is not a password slot"))),
+            None => Err(ClipassError::NotFound(format!("key slot {index}"))),
+        }
+    }
+
+    // New recovery code, replacing the previous one. It is not stored anywhere else.
+    pub fn new_recovery_code(&mut self) -> Result<SecretString, ClipassError> {
+        if self.slots.iter().filter(|s| s.kind() != SlotKind::Recovery).count() >= MAX_SLOTS {
+            return Err(ClipassError::Input(format!("a vault holds at most {MAX_SLOTS} key slots")));
+        }
+        let code = recovery::generate();
+        let normalized = recovery::normalize(code.expose())?;
+        let slot = KeySlot::seal(&Credential::Recovery(normalized.expose()), RECOVERY_SLOT_LABEL, self.kdf_params.clone(), &self.key)?;
+        self.slots.retain(|s| s.kind() != SlotKind::Recovery);
+        self.slots.push(slot);
+        self.updated = true;
+        Ok(code)
+    }
+
+    fn find_password_slot(&self, current: &Credential) -> Result<usize, ClipassError> {
+        if !matches!(current, Credential::Password(..)) {
+            return Err(ClipassError::Input("only password key slots can be changed".to_string()));
+        }
+        self.find_slot(current)?.ok_or(ClipassError::WrongPassword)
+    }
+
+    fn reseal_slot(&mut self, index: usize, credential: &Credential) -> Result<(), ClipassError> {
+        let label = self.slots[index].label().to_string();
+        self.slots[index] = KeySlot::seal(credential, &label, self.kdf_params.clone(), &self.key)?;
+        self.updated = true;
+        Ok(())
+    }
+
+    pub fn verify_password(&self, password: &str) -> Result<bool, ClipassError> {
+        self.verify(&Credential::password(password))
+    }
+
+    pub fn verify(&self, credential: &Credential) -> Result<bool, ClipassError> {
+        if let Credential::DataKey(key) = credential {
+            return Ok(key.matches(&self.key));
+        }
+        Ok(self.find_slot(credential)?.is_some())
+    }
+
+    // Index of the first slot `credential` opens
+    fn find_slot(&self, credential: &Credential) -> Result<Option<usize>, ClipassError> {
+        for (index, slot) in self.slots.iter().enumerate() {
+            if slot.open(credential)?.is_some_and(|key| key.matches(&self.key)) {
+                return Ok(Some(index));
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn slots(&self) -> &[KeySlot] {
+        &self.slots
+    }
+
+    pub fn add_slot(&mut self, credential: &Credential, label: &str) -> Result<(), ClipassError> {
+        if self.slots.len() >= MAX_SLOTS {
+            return Err(ClipassError::Input(format!("a vault holds at most {MAX_SLOTS} key slots")));
+        }
+        self.slots.push(KeySlot::seal(credential, label, self.kdf_params.clone(), &self.key)?);
+        self.updated = true;
+        Ok(())
+    }
+
+    pub fn revoke_slot(&mut self, index: usize) -> Result<(), ClipassError> {
+        if index >= self.slots.len() {
+            return Err(ClipassError::NotFound(format!("key slot {index}")));
+        }
+        if self.slots.len() == 1 {
+            return Err(ClipassError::Input("cannot revoke the last key slot".to_string()));
+        }
+        self.slots.remove(index);
+        self.updated = true;
+        Ok(())
+    }
+
+    // Shares of the data key, any `threshold` of them unlock the vault.
+    // They stay valid as long as the data key does.
+    pub fn split_key(&self, threshold: u8, count: u8) -> Result<Vec<Share>, ClipassError> {
+        shamir::split(&self.key, threshold, count)
+    }
+
+    pub fn cipher(&self) -> Cipher {
+        self.cipher
+    }
+
+    // Encrypt the content with `cipher` from the next crypt_to_file.
+    // Key slots keep wrapping the same data key.
+    pub fn set_cipher(&mut self, cipher: Cipher) {
+        if cipher != self.cipher {
+            self.cipher = cipher;
+            self.updated = true;
+        }
+    }
+
+    // Whether some key slot needs a keyfile
+    pub fn requires_keyfile(&self) -> bool {
+        self.slots.iter().any(KeySlot::requires_keyfile)
+    }
+
+    // Drop the content from memory, see LockedVault
+    pub fn lock(&self) -> Result<LockedVault, ClipassError> {
+        let sealed = self.updated.then(|| self.seal()).transpose()?;
+        Ok(LockedVault { sealed, backup_count: self.backup_count })
+    }
+
     pub fn crypt_to_file(&self, path: &str) -> Result<(), ClipassError> {
-        let entries_json = serde_json::to_vec(&self.entries)?;
+        let sealed = self.seal()?;
+        let path = Path::new(path);
+        // Kept once, the upgraded file cannot be read by older versions
+        if let Some(version) = VaultHeader::file_version(path) && version < VERSION {
+            backup::keep_copy(path, &format!("v{version}"))?;
+        }
+        backup::rotate_backups(path, self.backup_count)?;
+        backup::write_atomic(path, &[&sealed])
+    }
 
-        let mut salt_bytes = [0u8; SALT_SIZE];
-        self.salt.decode_b64(&mut salt_bytes)?;
+    // Header and encrypted entries, as written in the file
+    fn seal(&self) -> Result<Vec<u8>, ClipassError> {
+        let mut entries_json = SecretBuffer::with_capacity(4096);
+        serde_json::to_writer(&mut entries_json, &self.entries)?;
+        let entries_json = entries_json.into_inner();
 
-        let nonce = crypto::generate_nonce();
+        let nonce = crypto::generate_nonce(self.cipher);
 
         let now = SystemTime::now();
         let created_at = self.created_at.duration_since(UNIX_EPOCH)?.as_secs();
@@ -94,46 +344,158 @@ impl Vault {
             false => self.modified_at.duration_since(UNIX_EPOCH)?.as_secs(),
         };
 
-        let header = VaultHeader::new(self.salt.clone(), nonce, created_at, modified_at, self.kdf_params.clone());
+        let header = VaultHeader::with_slots(self.cipher, nonce.clone(), created_at, modified_at, self.slots.clone())?;
         let header_bytes = header.serialize()?;
 
         let ciphertext = crypto::encrypt_data(
+            self.cipher,
             &self.key,
             &nonce,
-            &entries_json,
+            entries_json.as_slice(),
             &header_bytes
         )?;
-
-        let mut file = File::create(path)?;
-        file.write_all(&header_bytes)?;
-        file.write_all(&ciphertext)?;
-
-        Ok(())
+        let mut sealed = header_bytes;
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
     }
 
     pub fn load_from_file(master_password: &str, path: &str)
         -> Result<Self, ClipassError>
     {
+        Self::load_with_credential(&Credential::password(master_password), path)
+    }
+
+    // Parse the header only, no password needed
+    pub fn inspect(path: &str) -> Result<VaultInfo, ClipassError> {
         let data = fs::read(path)?;
+        let header = VaultHeader::deserialize(&data)?;
+        let legacy = header.is_legacy();
+        Ok(VaultInfo {
+            version: header.version,
+            file_size: data.len(),
+            header_size: header.header_size,
+            created_at: DateTime::from(UNIX_EPOCH + Duration::from_secs(header.created_at)),
+            modified_at: DateTime::from(UNIX_EPOCH + Duration::from_secs(header.modified_at)),
+            cipher: header.cipher,
+            legacy_kdf: legacy.then_some(header.kdf),
+            slots: header.slots,
+        })
+    }
+
+    pub fn load_with_credential(credential: &Credential, path: &str)
+        -> Result<Self, ClipassError>
+    {
+        Self::open_sealed(credential, fs::read(path)?)
+    }
 
+    fn open_sealed(credential: &Credential, data: Vec<u8>) -> Result<Self, ClipassError> {
         if data.len() < (SALT_SIZE + NONCE_SIZE) {
-            return Err(ClipassError::Io("file too small or invalid".to_string()));
+            return Err(ClipassError::Corrupted("file too small or invalid".to_string()));
         }
 
         let header = VaultHeader::deserialize(&data)?;
-        let salt = header.salt;
-        let nonce = header.nonce;
-        let ciphertext = &data[HEADER_SIZE..];
-        let kdf_params = header.kdf;
+        let ciphertext = &data[header.header_size..];
         let created_at = UNIX_EPOCH + Duration::from_secs(header.created_at);
         let modified_at = UNIX_EPOCH + Duration::from_secs(header.modified_at);
 
-        let (key, _) = crypto::derive_key(master_password, &salt, Some(kdf_params.clone()))?;
+        // The content of legacy vaults is not encrypted with the data key
+        let legacy = header.is_legacy();
+        let (content_key, key, slots, kdf_params) = match (!legacy, credential) {
+            // Checked by decrypting the content
+            (true, Credential::DataKey(key)) => {
+                let kdf_params = header.slots[0].kdf().clone();
+                ((*key).clone(), (*key).clone(), header.slots, kdf_params)
+            },
+            (true, _) => {
+                let (key, kdf_params) = Self::open_slots(&header.slots, credential)?;
+                (key.clone(), key, header.slots, kdf_params)
+            },
+            (false, _) => Self::open_legacy(&header, credential)?,
+        };
 
-        let decrypted = crypto::decrypt_data(&key, &nonce, &ciphertext, &data[..HEADER_SIZE])?;
-        let entries: HashMap<String, String> = serde_json::from_slice(&decrypted)?;
+        // A key from a slot is already authenticated, the content is then damaged.
+        // Legacy and data keys are not, they may just be wrong.
+        let decrypted = crypto::decrypt_data(
+            header.cipher, &content_key, &header.nonce, ciphertext, &data[..header.header_size],
+        ).map_err(|_| match (legacy, credential) {
+            (true, _) => ClipassError::WrongPassword,
+            (false, Credential::DataKey(_)) => ClipassError::CryptoError(
+                "these key shares do not open this vault, or it is corrupted".to_string()),
+            (false, _) => ClipassError::Corrupted("the content does not match its header".to_string()),
+        })?;
+        let stored: HashMap<String, StoredEntry> = serde_json::from_slice(decrypted.as_slice())
+            .map_err(|e| ClipassError::Corrupted(format!("invalid content: {e}")))?;
+        let entries = stored.into_iter()
+            .map(|(id, entry)| (id, Entry::from(entry)))
+            .collect();
 
-        Ok(Self { salt, key, entries, kdf_params, created_at, modified_at, updated: false })
+        Ok(Self {
+            key, slots, entries, kdf_params, created_at, modified_at, cipher: header.cipher,
+            updated: false, backup_count: DEFAULT_BACKUP_COUNT,
+        })
+    }
+
+    // Data key from the first slot the credential opens, with that slot KDF
+    fn open_slots(slots: &[KeySlot], credential: &Credential) -> Result<(Key, KdfParams), ClipassError> {
+        let mut keyfile_missing = false;
+        for slot in slots {
+            if let Some(key) = slot.open(credential)? {
+                return Ok((key, slot.kdf().clone()));
+            }
+            keyfile_missing |= slot.requires_keyfile() && matches!(credential, Credential::Password(_, None));
+        }
+        match keyfile_missing {
+            true => Err(ClipassError::KeyfileRequired),
+            false => Err(ClipassError::WrongPassword),
+        }
+    }
+
+    // Vaults from before key slots: the content is encrypted with the password key itself.
+    // It becomes the key of a first slot around a new random data key, used from the next save.
+    fn open_legacy(header: &VaultHeader, credential: &Credential)
+        -> Result<(Key, Key, Vec<KeySlot>, KdfParams), ClipassError>
+    {
+        let password = match credential {
+            Credential::Password(password, _) => password,
+            Credential::Recovery(_) => return Err(ClipassError::Input("this vault has no recovery key slot".to_string())),
+            Credential::DataKey(_) => return Err(ClipassError::Input("this vault has no data key to share".to_string())),
+        };
+        // They never took a keyfile
+        let (legacy_key, kdf_params) = crypto::derive_key(password, &header.salt, Some(header.kdf.clone()), None)?;
+        let slot_credential = Credential::password(password);
+        let data_key = Key::generate();
+        let slot = KeySlot::seal_with_kek(
+            &slot_credential, DEFAULT_SLOT_LABEL, kdf_params.clone(), header.salt.clone(), &legacy_key, &data_key,
+        )?;
+        Ok((legacy_key, data_key, vec![slot], kdf_params))
+    }
+
+    // The file as saved by another session, opened with the data key of this one
+    pub fn reload(&self, path: &str) -> Result<Self, ClipassError> {
+        let mut vault = Self::load_with_credential(&Credential::DataKey(&self.key), path)?;
+        vault.backup_count = self.backup_count;
+        Ok(vault)
+    }
+
+    // After crypt_to_file, the file holds the current content
+    pub fn mark_saved(&mut self) {
+        if self.updated {
+            self.modified_at = SystemTime::now();
+            self.updated = false;
+        }
+    }
+
+    // Number of rotated backups kept by crypt_to_file, 0 disables them
+    pub fn set_backup_count(&mut self, count: usize) {
+        self.backup_count = count;
+    }
+
+    pub fn backup_count(&self) -> usize {
+        self.backup_count
+    }
+
+    pub fn is_updated(&self) -> bool {
+        self.updated
     }
 
     pub fn created_at(&self) -> DateTime<Local>{
@@ -143,4 +505,4 @@ impl Vault {
     pub fn modified_at(&self) -> DateTime<Local> {
         DateTime::from(self.modified_at)
     }
-}
\ No newline at end of file
+}
@@ -1,16 +1,43 @@
+use std::fs::File;
 use std::io::{Cursor, Read};
-use aes_gcm::aead::consts::U12;
-use aes_gcm::Nonce;
+use std::path::Path;
 use argon2::password_hash::SaltString;
-use crate::crypto::{KdfParams, KDF_SIZE};
+use crate::crypto::{Cipher, KdfParams, KDF_SIZE};
 use crate::error::ClipassError;
+use crate::vault::key_slot::{KeySlot, MAX_SLOTS};
 use crate::vault::{NONCE_SIZE, SALT_SIZE};
 
 /*
-        *** CLIPASS VAULT FILE v3 ***
+        *** CLIPASS VAULT FILE v4 ***
 *****************************************
                   HEADER
   - Magic number {4}    : "CLIP"
+  - Version {2}         : 0x0004
+  - Header Size {4}     : where the ciphertext starts
+  - Created at {8}
+  - Modified at {8}
+  - Records {..}        : up to Header Size, each one
+    - Tag {2}           : below 0x8000 critical, an unknown one is refused,
+                          from 0x8000 optional, an unknown one is skipped
+    - Length {4}
+    - Value {Length}
+
+  Records
+  - 0x0001 Cipher       : id {1} (1 AES-256-GCM, 2 XChaCha20-Poly1305),
+                          nonce {12|24}
+  - 0x0002 KDF          : algorithm {1} of the key slots, 1 Argon2id v1.3
+  - 0x0003 Key slot     : see key_slot.rs, one record per slot
+  - 0x0004 Flags {4}    : none defined yet, unknown bits are refused
+  Cipher, KDF and at least one key slot are required, once each but slots.
+------------------------------------------
+                  CIPHERTEXT
+  The whole header is its associated data.
+****************************************
+
+        *** CLIPASS VAULT FILE v3 ***
+*****************************************
+Still read, written as v4 from the next save.
+  - Magic number {4}    : "CLIP"
   - Version {2}         : 0x0003
   - Header Size {2}     : ?HEADER_SIZE
   - Created at {8}
@@ -21,91 +48,222 @@ use crate::vault::{NONCE_SIZE, SALT_SIZE};
     - parallelism {4}
   - Salt {32}           : Argon2 Salt
   - Nonce {12}          : AES-GCM nonce
-------------------------------------------
-                  CIPHERTEXT
+  The content is encrypted with the key derived from the password.
 ****************************************
 */
 
 const MAGIC_SIZE: usize = 4;
 const MAGIC: [u8; MAGIC_SIZE] = *b"CLIP";
-const VERSION: u16 = 3;
-const PRE_HEADER_SIZE: usize = 8; // MAGIC (4) + VERSION (2) + HEADER_SIZE (2)
+pub const VERSION: u16 = 4;
+const VERSION_V3: u16 = 3;
 const TIMESTAMP_SIZE: usize = 8;
-pub const HEADER_SIZE: usize =
-    PRE_HEADER_SIZE +
+const PREFIX_SIZE: usize = MAGIC_SIZE + 2 + 4 + TIMESTAMP_SIZE * 2;
+const RECORD_HEAD_SIZE: usize = 6; // tag (2) + length (4)
+
+const TAG_CIPHER: u16 = 0x0001;
+const TAG_KDF: u16 = 0x0002;
+const TAG_KEY_SLOT: u16 = 0x0003;
+const TAG_FLAGS: u16 = 0x0004;
+const TAG_OPTIONAL: u16 = 0x8000;
+const KDF_ARGON2ID: u8 = 1;
+const KNOWN_FLAGS_V4: u32 = 0;
+
+const V3_PRE_HEADER_SIZE: usize = 8; // MAGIC (4) + VERSION (2) + HEADER_SIZE (2)
+const V3_HEADER_SIZE: usize =
+    V3_PRE_HEADER_SIZE +
         TIMESTAMP_SIZE * 2 + // created_at + modified_at
         KDF_SIZE +
         SALT_SIZE +
         NONCE_SIZE;
 
 pub struct VaultHeader {
+    pub version: u16,
     pub created_at: u64,
     pub modified_at: u64,
-    pub kdf: KdfParams,
-    pub salt: SaltString,
-    pub nonce: Nonce<U12>
+    pub kdf: KdfParams,     // v3 vaults only
+    pub salt: SaltString,   // same
+    pub cipher: Cipher,
+    pub nonce: Vec<u8>,
+    pub flags: u32,
+    pub slots: Vec<KeySlot>,
+    pub header_size: usize, // where the ciphertext starts
+}
+
+fn read_exact<const N: usize, R: Read>(reader: &mut R) -> Result<[u8; N], ClipassError> {
+    let mut buf = [0u8; N];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn push_record(buf: &mut Vec<u8>, tag: u16, value: &[u8]) -> Result<(), ClipassError> {
+    let length = u32::try_from(value.len())
+        .map_err(|_| ClipassError::HeaderError(format!("header record {tag:#06x} too large")))?;
+    buf.extend_from_slice(&tag.to_le_bytes());
+    buf.extend_from_slice(&length.to_le_bytes());
+    buf.extend_from_slice(value);
+    Ok(())
 }
 
 impl VaultHeader {
-    pub fn new(salt: SaltString, nonce: Nonce<U12>, created_at: u64, modified_at: u64, kdf: KdfParams) -> Self {
-        Self { created_at, modified_at, kdf,  nonce, salt }
+    // Header of a vault whose content key is kept in `slots`
+    pub fn with_slots(cipher: Cipher, nonce: Vec<u8>, created_at: u64, modified_at: u64, slots: Vec<KeySlot>)
+        -> Result<Self, ClipassError>
+    {
+        let salt = SaltString::encode_b64(&[0u8; SALT_SIZE])?;
+        let kdf = KdfParams { memory_cost: 0, time_cost: 0, parallelism: 0 };
+        Ok(Self {
+            version: VERSION, created_at, modified_at, kdf, cipher, nonce, salt, flags: 0, slots, header_size: 0,
+        })
+    }
+
+    // Vaults from before key slots, their key comes from the header KDF
+    pub fn is_legacy(&self) -> bool {
+        self.slots.is_empty()
     }
+
+    // Format version of the vault file at `path`, None when it is missing or not a vault
+    pub fn file_version(path: &Path) -> Option<u16> {
+        let prefix = read_exact::<{ MAGIC_SIZE + 2 }, _>(&mut File::open(path).ok()?).ok()?;
+        (prefix[..MAGIC_SIZE] == MAGIC).then(|| u16::from_le_bytes([prefix[4], prefix[5]]))
+    }
+
+    // Always written in the current version
     pub fn serialize(&self) -> Result<Vec<u8>, ClipassError> {
-        let mut buf = Vec::with_capacity(HEADER_SIZE);
+        let mut buf = Vec::with_capacity(PREFIX_SIZE + 256);
 
         buf.extend_from_slice(&MAGIC);
         buf.extend_from_slice(&VERSION.to_le_bytes());
-        buf.extend_from_slice(&(HEADER_SIZE as u16).to_le_bytes());
-
-        // Timestamp
+        buf.extend_from_slice(&0u32.to_le_bytes()); // header size, set once known
         buf.extend_from_slice(&self.created_at.to_le_bytes());
         buf.extend_from_slice(&self.modified_at.to_le_bytes());
 
-        // KDF
-        buf.extend_from_slice(&self.kdf.memory_cost.to_le_bytes());
-        buf.extend_from_slice(&self.kdf.time_cost.to_le_bytes());
-        buf.extend_from_slice(&self.kdf.parallelism.to_le_bytes());
-
-        let mut salt_bytes = [0u8; SALT_SIZE];
-        self.salt.decode_b64(&mut salt_bytes)?;
-        buf.extend_from_slice(salt_bytes.as_slice());
+        let mut cipher = vec![self.cipher.id()];
+        cipher.extend_from_slice(&self.nonce);
+        push_record(&mut buf, TAG_CIPHER, &cipher)?;
+        push_record(&mut buf, TAG_KDF, &[KDF_ARGON2ID])?;
+        push_record(&mut buf, TAG_FLAGS, &self.flags.to_le_bytes())?;
+        for slot in &self.slots {
+            let mut value = Vec::new();
+            slot.serialize(&mut value)?;
+            push_record(&mut buf, TAG_KEY_SLOT, &value)?;
+        }
 
-        buf.extend_from_slice(self.nonce.as_slice());
+        let header_size = u32::try_from(buf.len())
+            .map_err(|_| ClipassError::HeaderError("header too large".to_string()))?;
+        buf[6..10].copy_from_slice(&header_size.to_le_bytes());
 
         Ok(buf)
     }
+
     pub fn deserialize(data: &Vec<u8>) -> Result<Self, ClipassError> {
         let mut cursor = Cursor::new(data);
 
-        // --- helpers locaux ---
-        fn read_exact<const N: usize>(cursor: &mut Cursor<&Vec<u8>>) -> Result<[u8; N], ClipassError> {
-            let mut buf = [0u8; N];
-            cursor.read_exact(&mut buf)?;
-            Ok(buf)
-        }
-
-        // --- magic ---
-        let magic = read_exact::<4>(&mut cursor)?;
+        let magic = read_exact::<4, _>(&mut cursor)?;
         if magic != MAGIC {
             return Err(ClipassError::HeaderError("bad magic".to_string()));
         }
 
-        // --- version ---
-        let version = u16::from_le_bytes(read_exact::<2>(&mut cursor)?);
-        if version != VERSION {
-            return Err(ClipassError::HeaderError("incompatible version".to_string()));
+        match u16::from_le_bytes(read_exact::<2, _>(&mut cursor)?) {
+            VERSION => Self::deserialize_v4(data, cursor),
+            VERSION_V3 => Self::deserialize_v3(data, cursor),
+            version => Err(ClipassError::HeaderError(format!("incompatible version {version}"))),
+        }
+    }
+
+    fn deserialize_v4(data: &[u8], mut cursor: Cursor<&Vec<u8>>) -> Result<Self, ClipassError> {
+        let header_size = u32::from_le_bytes(read_exact::<4, _>(&mut cursor)?) as usize;
+        if header_size < PREFIX_SIZE || header_size > data.len() {
+            return Err(ClipassError::HeaderError(format!("invalid header size {header_size}")));
         }
+        let created_at = u64::from_le_bytes(read_exact::<8, _>(&mut cursor)?);
+        let modified_at = u64::from_le_bytes(read_exact::<8, _>(&mut cursor)?);
 
-        let _header_size = u16::from_le_bytes(read_exact::<2>(&mut cursor)?);
+        let mut cipher = None;
+        let mut kdf_algorithm = None;
+        let mut flags = None;
+        let mut slots = Vec::new();
+        let mut records = Cursor::new(&data[PREFIX_SIZE..header_size]);
+        while (records.position() as usize) < header_size - PREFIX_SIZE {
+            let head = read_exact::<RECORD_HEAD_SIZE, _>(&mut records)
+                .map_err(|_| ClipassError::HeaderError("truncated header record".to_string()))?;
+            let tag = u16::from_le_bytes([head[0], head[1]]);
+            let length = u32::from_le_bytes([head[2], head[3], head[4], head[5]]) as usize;
+            let start = records.position() as usize;
+            let value = records.get_ref().get(start..start.saturating_add(length))
+                .ok_or(ClipassError::HeaderError(format!("header record {tag:#06x} beyond the header")))?;
+            records.set_position((start + length) as u64);
+
+            let duplicate = || ClipassError::HeaderError(format!("duplicate header record {tag:#06x}"));
+            match tag {
+                TAG_CIPHER => {
+                    let (&id, nonce) = value.split_first()
+                        .ok_or(ClipassError::HeaderError("empty cipher record".to_string()))?;
+                    let id = Cipher::from_id(id)?;
+                    if nonce.len() != id.nonce_size() {
+                        return Err(ClipassError::HeaderError(format!("{id} nonce has a wrong size")));
+                    }
+                    if cipher.replace((id, nonce.to_vec())).is_some() {
+                        return Err(duplicate());
+                    }
+                },
+                TAG_KDF => {
+                    if value != [KDF_ARGON2ID] {
+                        return Err(ClipassError::HeaderError("unknown key derivation algorithm".to_string()));
+                    }
+                    if kdf_algorithm.replace(KDF_ARGON2ID).is_some() {
+                        return Err(duplicate());
+                    }
+                },
+                TAG_FLAGS => {
+                    let bits = u32::from_le_bytes(value.try_into()
+                        .map_err(|_| ClipassError::HeaderError("flags record of a wrong size".to_string()))?);
+                    if bits & !KNOWN_FLAGS_V4 != 0 {
+                        return Err(ClipassError::HeaderError(format!("unsupported flags {bits:#x}")));
+                    }
+                    if flags.replace(bits).is_some() {
+                        return Err(duplicate());
+                    }
+                },
+                TAG_KEY_SLOT => {
+                    let mut reader = Cursor::new(value);
+                    slots.push(KeySlot::deserialize(&mut reader)?);
+                    if reader.position() as usize != value.len() {
+                        return Err(ClipassError::HeaderError("key slot record of a wrong size".to_string()));
+                    }
+                },
+                tag if tag & TAG_OPTIONAL != 0 => {},
+                tag => return Err(ClipassError::HeaderError(format!("unknown critical header record {tag:#06x}"))),
+            }
+        }
+
+        let (Some((cipher, nonce)), Some(_)) = (cipher, kdf_algorithm) else {
+            return Err(ClipassError::HeaderError("missing cipher or key derivation record".to_string()));
+        };
+        if slots.is_empty() || slots.len() > MAX_SLOTS {
+            return Err(ClipassError::HeaderError(format!("invalid key slot count {}", slots.len())));
+        }
+        let salt = SaltString::encode_b64(&[0u8; SALT_SIZE])?;
+        let kdf = KdfParams { memory_cost: 0, time_cost: 0, parallelism: 0 };
+        Ok(Self {
+            version: VERSION, created_at, modified_at, kdf, salt, cipher, nonce,
+            flags: flags.unwrap_or(0), slots, header_size,
+        })
+    }
+
+    fn deserialize_v3(data: &[u8], mut cursor: Cursor<&Vec<u8>>) -> Result<Self, ClipassError> {
+        let header_size = u16::from_le_bytes(read_exact::<2, _>(&mut cursor)?) as usize;
+        if header_size != V3_HEADER_SIZE || header_size > data.len() {
+            return Err(ClipassError::HeaderError(format!("invalid header size {header_size}")));
+        }
 
         // --- created / modified ---
-        let created_at = u64::from_le_bytes(read_exact::<8>(&mut cursor)?);
-        let modified_at = u64::from_le_bytes(read_exact::<8>(&mut cursor)?);
+        let created_at = u64::from_le_bytes(read_exact::<8, _>(&mut cursor)?);
+        let modified_at = u64::from_le_bytes(read_exact::<8, _>(&mut cursor)?);
 
         // --- KDF ---
-        let memory_cost = u32::from_le_bytes(read_exact::<4>(&mut cursor)?);
-        let time_cost = u32::from_le_bytes(read_exact::<4>(&mut cursor)?);
-        let parallelism = u32::from_le_bytes(read_exact::<4>(&mut cursor)?);
+        let memory_cost = u32::from_le_bytes(read_exact::<4, _>(&mut cursor)?);
+        let time_cost = u32::from_le_bytes(read_exact::<4, _>(&mut cursor)?);
+        let parallelism = u32::from_le_bytes(read_exact::<4, _>(&mut cursor)?);
 
         let kdf = KdfParams {
             memory_cost,
@@ -114,19 +272,26 @@ impl VaultHeader {
         };
 
         // --- salt ---
-        let salt_bytes = read_exact::<SALT_SIZE>(&mut cursor)?;
+        let salt_bytes = read_exact::<SALT_SIZE, _>(&mut cursor)?;
         let salt = SaltString::encode_b64(&salt_bytes)?;
 
         // --- nonce ---
-        let nonce_bytes = read_exact::<NONCE_SIZE>(&mut cursor)?;
-        let nonce = Nonce::from_slice(&nonce_bytes).clone();
+        let nonce = read_exact::<NONCE_SIZE, _>(&mut cursor)?.to_vec();
+
+        // The key is derived from these parameters
+        kdf.check().map_err(ClipassError::HeaderError)?;
 
         Ok(Self {
+            version: VERSION_V3,
             kdf,
             created_at,
             modified_at,
             salt,
+            cipher: Cipher::Aes256Gcm,
             nonce,
+            flags: 0,
+            slots: Vec::new(),
+            header_size,
         })
     }
 }
@@ -0,0 +1,166 @@
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+use tempfile::tempdir;
+use clipass::agent::{Agent, AgentClient, AgentRequest};
+use clipass::clipass::Clipass;
+use clipass::config::Config;
+use clipass::error::ClipassError;
+use clipass::secret::SecretString;
+use clipass::vault::entry::Entry;
+use clipass::vault::vault::Vault;
+
+fn wait_for_socket(path: &Path) {
+    for _ in 0..50 {
+        if path.exists() {
+            return;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    panic!("agent socket never appeared");
+}
+
+fn request(client: &AgentClient, vault: &str, args: &[&str], stdin: Option<&str>) -> clipass::agent::AgentResponse {
+    let request = AgentRequest {
+        vault: vault.to_string(),
+        args: args.iter().map(|a| a.to_string()).collect(),
+        stdin: stdin.map(SecretString::from),
+    };
+    client.request(&request).expect("agent request")
+}
+
+#[test]
+fn agent_serves_commands_until_locked() -> Result<(), ClipassError> {
+    let dir = tempdir()?;
+    let vault_path = dir.path().join("vault.clip");
+    let vault_path = vault_path.to_str().unwrap();
+    let socket_path = dir.path().join("run").join("agent.sock");
+
+    let mut vault = Vault::new_empty("test-pass")?;
+    vault.new_entry("github", Entry::with_password("s3cr3t"))?;
+    vault.crypt_to_file(vault_path)?;
+
+    let agent = Agent::new(Clipass::with_vault(vault, vault_path, &Config::default()), vault_path, &socket_path, Duration::from_secs(60))?;
+    let server = thread::spawn(move || agent.serve());
+    wait_for_socket(&socket_path);
+
+    let canonical = clipass::agent::canonical_path(vault_path)?;
+    let client = AgentClient::new(&socket_path);
+
+    let get = request(&client, &canonical, &["get", "github", "password"], None);
+    assert!(get.ok);
+    assert_eq!(get.output, "s3cr3t");
+
+    let set = request(&client, &canonical, &["set", "gitlab", "--stdin"], Some("other"));
+    assert!(set.ok, "{:?}", set.error);
+    let missing = request(&client, &canonical, &["get", "nope"], None);
+    assert_eq!(missing.code, 3);
+    let other_vault = request(&client, "/elsewhere/vault.clip", &["list"], None);
+    assert_eq!(other_vault.code, 5);
+    assert!(client.run("/does/not/exist", &["list".to_string()], None).is_none());
+    // Admin commands are left to a direct run
+    for args in [&["slots"][..], &["import", "pass", "/tmp/store"], &["update", "github", "password"]] {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        assert!(client.run(vault_path, &args, None).is_none(), "{args:?}");
+    }
+    assert_eq!(client.run(vault_path, &["frobnicate".to_string()], None).map(|r| r.code), Some(2));
+
+    let lock = request(&client, "", &["lock"], None);
+    assert!(lock.ok);
+    server.join().unwrap()?;
+    assert!(!socket_path.exists());
+
+    // updates were saved by the agent
+    let saved = Vault::load_from_file("test-pass", vault_path)?;
+    assert_eq!(saved.get_value("gitlab", "password")?, "other");
+    Ok(())
+}
+
+#[test]
+fn agent_locks_when_idle() -> Result<(), ClipassError> {
+    let dir = tempdir()?;
+    let vault_path = dir.path().join("vault.clip");
+    let vault_path = vault_path.to_str().unwrap();
+    let socket_path = dir.path().join("agent.sock");
+    let vault = Vault::new_empty("test-pass")?;
+    vault.crypt_to_file(vault_path)?;
+
+    let agent = Agent::new(Clipass::with_vault(vault, vault_path, &Config::default()), vault_path, &socket_path, Duration::from_millis(300))?;
+    let server = thread::spawn(move || agent.serve());
+    server.join().unwrap()?;
+    assert!(!socket_path.exists());
+    Ok(())
+}
+
+#[test]
+fn agent_refuses_a_shared_socket_directory() -> Result<(), ClipassError> {
+    use std::os::unix::fs::PermissionsExt;
+    let dir = tempdir()?;
+    let vault_path = dir.path().join("vault.clip");
+    let vault_path = vault_path.to_str().unwrap();
+    let vault = Vault::new_empty("test-pass")?;
+    vault.crypt_to_file(vault_path)?;
+    // Like /tmp: writable by everyone, left as it is
+    let shared = dir.path().join("shared");
+    std::fs::create_dir(&shared)?;
+    std::fs::set_permissions(&shared, std::fs::Permissions::from_mode(0o777))?;
+
+    let socket_path = shared.join("agent.sock");
+    let agent = Agent::new(Clipass::with_vault(vault, vault_path, &Config::default()), vault_path, &socket_path, Duration::from_secs(60))?;
+    assert!(matches!(agent.serve(), Err(ClipassError::Io(_))));
+    assert!(!socket_path.exists());
+    assert_eq!(std::fs::metadata(&shared)?.permissions().mode() & 0o777, 0o777);
+    Ok(())
+}
+
+#[test]
+fn agent_never_overwrites_a_vault_saved_elsewhere() -> Result<(), ClipassError> {
+    let dir = tempdir()?;
+    let vault_path = dir.path().join("vault.clip");
+    let vault_path = vault_path.to_str().unwrap();
+    let socket_path = dir.path().join("agent.sock");
+    let vault = Vault::new_empty("test-pass")?;
+    vault.crypt_to_file(vault_path)?;
+
+    let agent = Agent::new(Clipass::with_vault(vault, vault_path, &Config::default()), vault_path, &socket_path, Duration::from_secs(60))?;
+    let server = thread::spawn(move || agent.serve());
+    wait_for_socket(&socket_path);
+    let canonical = clipass::agent::canonical_path(vault_path)?;
+    let client = AgentClient::new(&socket_path);
+
+    // Saved by another session while the agent runs
+    let mut other = Vault::load_from_file("test-pass", vault_path)?;
+    other.new_entry("github", Entry::with_password("s3cr3t"))?;
+    other.crypt_to_file(vault_path)?;
+
+    let get = request(&client, &canonical, &["get", "github", "password"], None);
+    assert_eq!(get.output, "s3cr3t", "{:?}", get.error);
+    let set = request(&client, &canonical, &["set", "gitlab", "--stdin"], Some("other"));
+    assert!(set.ok, "{:?}", set.error);
+    request(&client, "", &["lock"], None);
+    server.join().unwrap()?;
+
+    let saved = Vault::load_from_file("test-pass", vault_path)?;
+    assert_eq!(saved.get_value("github", "password")?, "s3cr3t");
+    assert_eq!(saved.get_value("gitlab", "password")?, "other");
+    Ok(())
+}
+
+#[test]
+fn session_refuses_to_save_over_a_vault_saved_elsewhere() -> Result<(), ClipassError> {
+    let dir = tempdir()?;
+    let vault_path = dir.path().join("vault.clip");
+    let vault_path = vault_path.to_str().unwrap();
+    let vault = Vault::new_empty("test-pass")?;
+    vault.crypt_to_file(vault_path)?;
+    let mut session = Clipass::with_vault(Vault::load_from_file("test-pass", vault_path)?, vault_path, &Config::default());
+    session.set_value("gitlab", "password", "mine")?;
+
+    let mut other = Vault::load_from_file("test-pass", vault_path)?;
+    other.new_entry("github", Entry::with_password("s3cr3t"))?;
+    other.crypt_to_file(vault_path)?;
+
+    assert!(matches!(session.save(), Err(ClipassError::Io(_))));
+    assert!(Vault::load_from_file("test-pass", vault_path)?.contains_key("github"));
+    Ok(())
+}
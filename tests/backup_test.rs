@@ -0,0 +1,42 @@
+use tempfile::tempdir;
+use clipass::error::ClipassError;
+use clipass::vault::backup;
+use clipass::vault::entry::Entry;
+use clipass::vault::vault::Vault;
+
+#[test]
+fn save_rotates_backups() -> Result<(), ClipassError> {
+    let dir = tempdir()?;
+    let path = dir.path().join("vault.clip");
+    let path_str = path.to_str().unwrap();
+
+    let mut vault = Vault::new_empty("test-pass")?;
+    vault.set_backup_count(2);
+    for i in 0..4 {
+        vault.new_entry(&format!("entry{i}"), Entry::with_password("secret"))?;
+        vault.crypt_to_file(path_str)?;
+    }
+
+    let backups = backup::list_backups(&path)?;
+    let generations: Vec<usize> = backups.iter().map(|b| b.generation).collect();
+    assert_eq!(generations, [1, 2]);
+
+    // generation 1 is the save before the last one
+    let previous = Vault::load_from_file("test-pass", backups[0].path.to_str().unwrap())?;
+    assert!(previous.contains_key("entry2"));
+    assert!(!previous.contains_key("entry3"));
+
+    // no temporary file is left behind
+    assert_eq!(std::fs::read_dir(dir.path())?.count(), 3);
+    Ok(())
+}
+
+#[test]
+fn write_atomic_replaces_content() -> Result<(), ClipassError> {
+    let dir = tempdir()?;
+    let path = dir.path().join("file");
+    std::fs::write(&path, b"old content that is longer")?;
+    backup::write_atomic(&path, &[b"new ", b"content"])?;
+    assert_eq!(std::fs::read(&path)?, b"new content");
+    Ok(())
+}
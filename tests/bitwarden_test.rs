@@ -0,0 +1,117 @@
+use std::path::Path;
+use clipass::crypto::KdfParams;
+use clipass::error::ClipassError;
+use clipass::formats::{bitwarden, merge, Import, ImportStrategy};
+use clipass::vault::entry::Entry;
+use clipass::vault::vault::Vault;
+
+fn test_vault() -> Vault {
+    let kdf = KdfParams { memory_cost: 8192, time_cost: 1, parallelism: 1 };
+    Vault::new_empty_with_kdf("test-pass", kdf).expect("vault")
+}
+
+fn entry<'a>(import: &'a Import, id: &str) -> &'a Entry {
+    &import.entries.iter().find(|(i, _)| i == id).unwrap_or_else(|| panic!("no entry {id}")).1
+}
+
+#[test]
+fn json_export_maps_logins_notes_and_fields() {
+    let import = bitwarden::read(Path::new("tests/fixtures/bitwarden.json")).expect("read");
+    let ids: Vec<&str> = import.entries.iter().map(|(id, _)| id.as_str()).collect();
+    assert_eq!(ids, ["Work/Dev/Git-Hub", "wifi", "Work/Git-Hub", "visa"]);
+
+    let github = entry(&import, "Work/Dev/Git-Hub");
+    assert_eq!(github.username(), Some("octocat"));
+    assert_eq!(github.password(), Some("hunter2"));
+    assert_eq!(github.urls(), ["https://github.com", "https://gist.github.com"]);
+    assert_eq!(github.notes(), Some("main account"));
+    assert!(github.otp().is_some());
+    assert_eq!(github.field("recovery-pin"), Some("4321"));
+    // A custom field never replaces a standard one
+    assert_eq!(github.field("password-2"), Some("old-one"));
+    assert_eq!(github.custom().len(), 2);
+
+    assert_eq!(entry(&import, "wifi").notes(), Some("SSID home, key abc"));
+    assert_eq!(entry(&import, "visa").field("number"), Some("4111111111111111"));
+
+    let work = entry(&import, "Work/Git-Hub");
+    assert!(work.otp().is_none());
+    assert_eq!(work.field("totp"), Some("steam://ABCDEF"));
+    assert_eq!(import.warnings.len(), 1);
+}
+
+#[test]
+fn csv_export_maps_the_same_fields() {
+    let import = bitwarden::read(Path::new("tests/fixtures/bitwarden.csv")).expect("read");
+    assert_eq!(import.entries.len(), 2);
+
+    let github = entry(&import, "Work/Git-Hub");
+    assert_eq!(github.username(), Some("octocat"));
+    assert_eq!(github.password(), Some("hunter2"));
+    assert_eq!(github.urls(), ["https://github.com", "https://gist.github.com"]);
+    assert_eq!(github.notes(), Some("main, \"personal\" account"));
+    assert!(github.otp().is_some());
+    assert_eq!(github.field("recovery-pin"), Some("4321"));
+    assert_eq!(github.field("region"), Some("eu"));
+
+    assert_eq!(entry(&import, "wifi").notes(), Some("SSID home\nkey abc"));
+}
+
+#[test]
+fn encrypted_and_broken_exports_are_refused() {
+    let encrypted = r#"{"encrypted": true, "passwordProtected": true, "data": "2.abc"}"#;
+    assert!(matches!(bitwarden::parse_json(encrypted), Err(ClipassError::Input(_))));
+    assert!(matches!(bitwarden::parse_json("{\"items\": [{}]}"), Err(ClipassError::Input(_))));
+    assert!(matches!(bitwarden::parse_csv("name,type\n\"unterminated,login\n"), Err(ClipassError::Input(_))));
+    assert!(matches!(bitwarden::parse_csv("title,url\n"), Err(ClipassError::Input(_))));
+}
+
+#[test]
+fn merge_follows_the_conflict_strategy() -> Result<(), ClipassError> {
+    let import = || Import {
+        entries: vec![
+            ("github".to_string(), Entry::with_password("imported")),
+            ("github".to_string(), Entry::with_password("second")),
+            ("mail".to_string(), Entry::with_password("new")),
+        ],
+        warnings: Vec::new(),
+    };
+    let mut vault = test_vault();
+    vault.new_entry("github", Entry::with_password("mine"))?;
+
+    let report = merge(&mut vault, import(), ImportStrategy::Skip, false)?;
+    assert_eq!(report.skipped, ["github", "github"]);
+    assert_eq!(report.added, ["mail"]);
+    assert_eq!(vault.get_value("github", "password")?, "mine");
+
+    let mut vault = test_vault();
+    vault.new_entry("github", Entry::with_password("mine"))?;
+    let report = merge(&mut vault, import(), ImportStrategy::Rename, false)?;
+    assert_eq!(report.imported(), 3);
+    assert_eq!(vault.get_value("github", "password")?, "mine");
+    assert_eq!(vault.get_value("github-2", "password")?, "imported");
+    assert_eq!(vault.get_value("github-3", "password")?, "second");
+
+    let mut vault = test_vault();
+    vault.new_entry("github", Entry::with_password("mine"))?;
+    // The second item does not overwrite the first one
+    let report = merge(&mut vault, import(), ImportStrategy::Overwrite, false)?;
+    assert_eq!(report.overwritten, ["github"]);
+    assert_eq!(vault.get_value("github", "password")?, "imported");
+    assert_eq!(vault.get_value("github-2", "password")?, "second");
+    Ok(())
+}
+
+#[test]
+fn dry_run_leaves_the_vault_untouched() -> Result<(), ClipassError> {
+    let mut vault = test_vault();
+    vault.new_entry("Work/Git-Hub", Entry::with_password("mine"))?;
+    let import = bitwarden::read(Path::new("tests/fixtures/bitwarden.json"))?;
+    let report = merge(&mut vault, import, ImportStrategy::Overwrite, true)?;
+    assert_eq!(report.added.len(), 3);
+    assert_eq!(report.overwritten, ["Work/Git-Hub"]);
+    assert!(report.to_string().starts_with("dry run"));
+    assert_eq!(vault.get_all().len(), 1);
+    assert_eq!(vault.get_value("Work/Git-Hub", "password")?, "mine");
+    Ok(())
+}
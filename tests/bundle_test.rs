@@ -0,0 +1,57 @@
+use std::path::Path;
+use clipass::crypto::{Cipher, KdfParams};
+use clipass::error::ClipassError;
+use clipass::formats::{self, bundle, ImportStrategy};
+use clipass::vault::entry::Entry;
+use clipass::vault::vault::Vault;
+
+fn fast_kdf() -> KdfParams {
+    KdfParams { memory_cost: 8192, time_cost: 1, parallelism: 1 }
+}
+
+#[test]
+fn bundle_holds_the_selected_entries_under_its_passphrase() -> Result<(), ClipassError> {
+    let mut vault = Vault::new_empty_with_kdf("vault-pass", fast_kdf())?;
+    let mut github = Entry::with_password("hunter2");
+    github.set_field("username", "octocat")?;
+    github.set_field("otp", "JBSWY3DPEHPK3PXP")?;
+    vault.new_entry("team/github", github.clone())?;
+    vault.new_entry("team/ci", Entry::with_password("tok"))?;
+    vault.new_entry("personal/bank", Entry::with_password("p4ss"))?;
+
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("team.clip");
+    let team = vault.get_all().iter().filter(|(id, _)| id.starts_with("team/"));
+    assert_eq!(bundle::export(team, &path, "bundle-pass", fast_kdf(), Cipher::XChaCha20Poly1305)?, 2);
+    assert_eq!(Vault::inspect(path.to_str().unwrap())?.cipher, Cipher::XChaCha20Poly1305);
+
+    let import = bundle::read(&path, "bundle-pass")?;
+    assert_eq!(import.entries, [("team/ci".to_string(), Entry::with_password("tok")), ("team/github".to_string(), github)]);
+    assert!(matches!(bundle::read(&path, "vault-pass"), Err(ClipassError::WrongPassword)));
+
+    // An existing file is never replaced
+    let again = bundle::export(vault.get_all(), &path, "other-pass", fast_kdf(), Cipher::default());
+    assert!(matches!(again, Err(ClipassError::Input(_))));
+    assert_eq!(bundle::read(&path, "bundle-pass")?.entries.len(), 2);
+    Ok(())
+}
+
+#[test]
+fn bundle_merges_with_conflict_handling() -> Result<(), ClipassError> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("bundle.clip");
+    let mut sender = Vault::new_empty_with_kdf("sender-pass", fast_kdf())?;
+    sender.new_entry("github", Entry::with_password("theirs"))?;
+    sender.new_entry("ci", Entry::with_password("tok"))?;
+    bundle::export(sender.get_all(), &path, "bundle-pass", fast_kdf(), Cipher::default())?;
+
+    let mut receiver = Vault::new_empty_with_kdf("receiver-pass", fast_kdf())?;
+    receiver.new_entry("github", Entry::with_password("ours"))?;
+    let import = bundle::read(Path::new(&path), "bundle-pass")?;
+    let report = formats::merge(&mut receiver, import, ImportStrategy::Rename, false)?;
+    assert_eq!(report.added, ["ci"]);
+    assert_eq!(report.renamed, [("github".to_string(), "github-2".to_string())]);
+    assert_eq!(receiver.get_value("github", "password")?, "ours");
+    assert_eq!(receiver.get_value("github-2", "password")?, "theirs");
+    Ok(())
+}
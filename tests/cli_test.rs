@@ -0,0 +1,161 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+use tempfile::tempdir;
+
+fn clipass(vault: &str, args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_clipass"))
+        .arg(vault)
+        .args(args)
+        .env("CLIPASS_PASSWORD", "test-pass")
+        .env("CLIPASS_AGENT_SOCK", format!("{vault}.sock"))
+        .env("XDG_CONFIG_HOME", format!("{vault}.config"))
+        .env_remove("CLIPASS_CONFIG")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("spawn clipass");
+    child.stdin.take().unwrap().write_all(stdin.as_bytes()).unwrap();
+    child.wait_with_output().expect("wait clipass")
+}
+
+// One-shot runs only open existing vaults, the interactive mode creates them
+fn create_vault(vault: &str) {
+    let create = clipass(vault, &[], "quit\n");
+    assert!(create.status.success(), "{}", String::from_utf8_lossy(&create.stderr));
+}
+
+#[test]
+fn one_shot_set_get_and_exit_codes() {
+    let dir = tempdir().unwrap();
+    let vault = dir.path().join("vault.clip");
+    let vault = vault.to_str().unwrap();
+    create_vault(vault);
+
+    let set = clipass(vault, &["set", "github", "--stdin"], "s3cr3t\n");
+    assert!(set.status.success(), "{}", String::from_utf8_lossy(&set.stderr));
+
+    let get = clipass(vault, &["get", "github", "password"], "");
+    assert!(get.status.success());
+    assert_eq!(String::from_utf8_lossy(&get.stdout), "s3cr3t\n");
+
+    let missing = clipass(vault, &["get", "gitlab"], "");
+    assert_eq!(missing.status.code(), Some(3));
+    assert!(missing.stdout.is_empty());
+
+    let invalid = clipass(vault, &["frobnicate"], "");
+    assert_eq!(invalid.status.code(), Some(2));
+
+    // A mistyped vault is not created
+    let typo = dir.path().join("typo.clip");
+    let missing_vault = clipass(typo.to_str().unwrap(), &["list"], "");
+    assert_eq!(missing_vault.status.code(), Some(3));
+    assert!(!typo.exists());
+    let agent = Command::new(env!("CARGO_BIN_EXE_clipass"))
+        .args(["agent", typo.to_str().unwrap()])
+        .env("CLIPASS_PASSWORD", "test-pass")
+        .env("CLIPASS_AGENT_SOCK", format!("{vault}.sock"))
+        .env("XDG_CONFIG_HOME", format!("{vault}.config"))
+        .env_remove("CLIPASS_CONFIG")
+        .stdin(Stdio::null())
+        .output()
+        .expect("run clipass agent");
+    assert_eq!(agent.status.code(), Some(3));
+    assert!(!typo.exists());
+}
+
+#[test]
+fn configured_vault_is_used_without_vault_argument() {
+    let dir = tempdir().unwrap();
+    let vault = dir.path().join("vault.clip");
+    let config = dir.path().join("config.toml");
+    std::fs::write(&config, format!(
+        "vault = {:?}\n[kdf]\nmemory_cost = 8192\ntime_cost = 1\nparallelism = 1\n",
+        vault.to_str().unwrap()
+    )).unwrap();
+    let run = |args: &[&str], stdin: &str| {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_clipass"))
+            .args(args)
+            .env("CLIPASS_PASSWORD", "test-pass")
+            .env("CLIPASS_AGENT_SOCK", dir.path().join("agent.sock"))
+            .env("CLIPASS_CONFIG", &config)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("spawn clipass");
+        child.stdin.take().unwrap().write_all(stdin.as_bytes()).unwrap();
+        child.wait_with_output().expect("wait clipass")
+    };
+
+    let create = run(&[], "quit\n");
+    assert!(create.status.success(), "{}", String::from_utf8_lossy(&create.stderr));
+    let set = run(&["set", "github", "--stdin"], "s3cr3t");
+    assert!(set.status.success(), "{}", String::from_utf8_lossy(&set.stderr));
+    assert!(vault.exists());
+
+    let get = run(&["get", "github", "password"], "");
+    assert_eq!(String::from_utf8_lossy(&get.stdout), "s3cr3t\n");
+}
+
+#[test]
+fn inspect_and_verify_tell_wrong_password_from_corruption() {
+    let dir = tempdir().unwrap();
+    let vault = dir.path().join("vault.clip");
+    let vault = vault.to_str().unwrap();
+    create_vault(vault);
+    let set = clipass(vault, &["set", "github", "--stdin"], "s3cr3t\n");
+    assert!(set.status.success(), "{}", String::from_utf8_lossy(&set.stderr));
+
+    let run = |command: &str, password: Option<&str>| {
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_clipass"));
+        cmd.args([command, vault])
+            .env_remove("CLIPASS_PASSWORD")
+            .env("XDG_CONFIG_HOME", format!("{vault}.config"))
+            .env_remove("CLIPASS_CONFIG")
+            .stdin(Stdio::null());
+        if let Some(password) = password {
+            cmd.env("CLIPASS_PASSWORD", password);
+        }
+        cmd.output().expect("run clipass")
+    };
+
+    let inspect = run("inspect", None);
+    assert!(inspect.status.success(), "{}", String::from_utf8_lossy(&inspect.stderr));
+    let report = String::from_utf8_lossy(&inspect.stdout);
+    assert!(report.contains("format:       v4"));
+    assert!(report.contains("password \"default\""));
+
+    let verify = run("verify", Some("test-pass"));
+    assert_eq!(String::from_utf8_lossy(&verify.stdout), "vault ok: 1 entries, 2 key slots\n");
+    assert_eq!(run("verify", Some("wrong-pass")).status.code(), Some(4));
+
+    let mut data = std::fs::read(vault).unwrap();
+    let last = data.len() - 1;
+    data[last] ^= 0x01;
+    std::fs::write(vault, &data).unwrap();
+    let corrupted = run("verify", Some("test-pass"));
+    assert_eq!(corrupted.status.code(), Some(6));
+    assert!(String::from_utf8_lossy(&corrupted.stderr).contains("corrupted vault"));
+}
+
+#[test]
+fn csv_export_asks_before_writing_in_clear() {
+    let dir = tempdir().unwrap();
+    let vault = dir.path().join("vault.clip");
+    let vault = vault.to_str().unwrap();
+    let export = dir.path().join("export.csv");
+    create_vault(vault);
+    let set = clipass(vault, &["set", "github", "--stdin"], "s3cr3t\n");
+    assert!(set.status.success(), "{}", String::from_utf8_lossy(&set.stderr));
+
+    let args = ["export", "csv", export.to_str().unwrap()];
+    let refused = clipass(vault, &args, "no\n");
+    assert!(String::from_utf8_lossy(&refused.stderr).contains("WARNING"));
+    assert!(String::from_utf8_lossy(&refused.stdout).contains("export cancelled"));
+    assert!(!export.exists());
+
+    let confirmed = clipass(vault, &args, "yes\n");
+    assert!(confirmed.status.success(), "{}", String::from_utf8_lossy(&confirmed.stderr));
+    assert_eq!(std::fs::read_to_string(&export).unwrap(), "name,url,username,password,notes,otp\r\ngithub,,,s3cr3t,,\r\n");
+}
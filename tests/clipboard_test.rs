@@ -0,0 +1,34 @@
+use std::time::Duration;
+use clipass::clipboard::{Clipboard, ClipboardBackend, MemoryBackend};
+
+#[test]
+fn copied_value_is_cleared_after_timeout() {
+    let backend = MemoryBackend::new();
+    let mut clipboard = Clipboard::new(Box::new(backend.clone()), Duration::from_millis(50));
+    clipboard.copy("s3cr3t").unwrap();
+    assert_eq!(backend.content().as_deref(), Some("s3cr3t"));
+    clipboard.wait();
+    assert_eq!(backend.content(), None);
+}
+
+#[test]
+fn changed_clipboard_is_left_alone() {
+    let mut backend = MemoryBackend::new();
+    let mut clipboard = Clipboard::new(Box::new(backend.clone()), Duration::from_millis(50));
+    clipboard.copy("s3cr3t").unwrap();
+    backend.set("something the user copied").unwrap();
+    clipboard.wait();
+    assert_eq!(backend.content().as_deref(), Some("something the user copied"));
+}
+
+#[test]
+fn clear_now_and_recopy() {
+    let backend = MemoryBackend::new();
+    let mut clipboard = Clipboard::new(Box::new(backend.clone()), Duration::from_secs(60));
+    clipboard.copy("first").unwrap();
+    clipboard.copy("second").unwrap();
+    assert_eq!(backend.content().as_deref(), Some("second"));
+    clipboard.clear_now();
+    assert!(!clipboard.is_pending());
+    assert_eq!(backend.content(), None);
+}
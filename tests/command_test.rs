@@ -1,11 +1,16 @@
 use std::str::FromStr;
 use clipass::command::Command;
+use clipass::crypto::Cipher;
+use clipass::formats::{ExportFormat, ImportFormat, ImportStrategy};
 
 #[test]
 fn parse_get_command_with_arg() {
     let cmd = Command::from_str("get myid").expect("parse ok");
     match cmd {
-        Command::Get(arg) => assert_eq!(arg, "myid"),
+        Command::Get(arg, field) => {
+            assert_eq!(arg, "myid");
+            assert!(field.is_none());
+        },
         _ => panic!("expected Get variant"),
     }
 }
@@ -13,6 +18,152 @@ fn parse_get_command_with_arg() {
 #[test]
 fn parse_help_and_list() {
     assert!(matches!(Command::from_str("help").unwrap(), Command::Help));
-    assert!(matches!(Command::from_str("list").unwrap(), Command::List));
-    assert!(matches!(Command::from_str("new").unwrap(), Command::New));
-}
\ No newline at end of file
+    assert!(matches!(Command::from_str("list").unwrap(), Command::List(None)));
+    assert!(matches!(Command::from_str("new").unwrap(), Command::New(None)));
+}
+
+#[test]
+fn parse_get_and_update_with_field() {
+    match Command::from_str("get myid username").expect("parse ok") {
+        Command::Get(id, field) => {
+            assert_eq!(id, "myid");
+            assert_eq!(field.as_deref(), Some("username"));
+        },
+        _ => panic!("expected Get variant"),
+    }
+    match Command::from_str("update myid url").expect("parse ok") {
+        Command::Update(id, field) => {
+            assert_eq!(id, "myid");
+            assert_eq!(field.as_deref(), Some("url"));
+        },
+        _ => panic!("expected Update variant"),
+    }
+}
+
+#[test]
+fn parse_set_from_args() {
+    match Command::from_args(["set", "myid", "--stdin"]).expect("parse ok") {
+        Command::Set(id, field, stdin) => {
+            assert_eq!(id, "myid");
+            assert_eq!(field, "password");
+            assert!(stdin);
+        },
+        _ => panic!("expected Set variant"),
+    }
+    assert!(Command::from_args(["set", "myid", "user", "extra"]).is_err());
+}
+
+#[test]
+fn parse_keyfile() {
+    assert!(matches!(Command::from_str("keyfile ~/clipass.key").unwrap(), Command::Keyfile(Some(p)) if p == "~/clipass.key"));
+    assert!(matches!(Command::from_str("keyfile --remove").unwrap(), Command::Keyfile(None)));
+    assert!(Command::from_str("keyfile").is_err());
+}
+
+#[test]
+fn parse_key_slot_commands() {
+    assert!(matches!(Command::from_str("slots").unwrap(), Command::Slots));
+    match Command::from_str("add-slot bob --keyfile bob.key").unwrap() {
+        Command::AddSlot(label, keyfile) => {
+            assert_eq!(label.as_deref(), Some("bob"));
+            assert_eq!(keyfile.as_deref(), Some("bob.key"));
+        },
+        _ => panic!("expected AddSlot variant"),
+    }
+    assert!(matches!(Command::from_str("add-slot").unwrap(), Command::AddSlot(None, None)));
+    assert!(matches!(Command::from_str("revoke-slot 1").unwrap(), Command::RevokeSlot(1)));
+    assert!(matches!(Command::from_str("recovery-code").unwrap(), Command::RecoveryCode));
+    assert!(matches!(Command::from_str("split-key 3 5").unwrap(), Command::SplitKey(3, 5)));
+    assert!(Command::from_str("split-key 3").is_err());
+    assert!(matches!(Command::from_str("reencrypt --cipher xchacha20-poly1305").unwrap(),
+                     Command::Reencrypt(Cipher::XChaCha20Poly1305)));
+    assert!(Command::from_str("reencrypt --cipher rot13").is_err());
+    assert!(Command::from_str("reencrypt").is_err());
+    assert!(matches!(Command::from_str("kdf-bench").unwrap(), Command::KdfBench(None)));
+    assert!(matches!(Command::from_str("kdf-bench 500").unwrap(), Command::KdfBench(Some(500))));
+    assert!(Command::from_str("kdf-bench 0").is_err());
+    assert!(Command::from_str("kdf-bench 100000000000").is_err());
+    assert!(matches!(Command::from_str("kdf set --memory 256 --time 4").unwrap(),
+                     Command::KdfSet(Some(256), Some(4), None)));
+    assert!(Command::from_str("kdf set --memory").is_err());
+    assert!(Command::from_str("kdf --time 3").is_err());
+    assert!(Command::from_str("revoke-slot one").is_err());
+}
+
+#[test]
+fn parse_import_command() {
+    match Command::from_str("import bitwarden export.json --dry-run --on-conflict rename").expect("parse ok") {
+        Command::Import(args) => {
+            assert_eq!(args.format, ImportFormat::Bitwarden);
+            assert_eq!(args.path, "export.json");
+            assert_eq!(args.strategy, ImportStrategy::Rename);
+            assert!(args.dry_run);
+        },
+        _ => panic!("expected Import variant"),
+    }
+    match Command::from_str("import bitwarden export.csv").expect("parse ok") {
+        Command::Import(args) => assert_eq!((args.strategy, args.dry_run), (ImportStrategy::Skip, false)),
+        _ => panic!("expected Import variant"),
+    }
+    match Command::from_str("import keepass db.kdbx --keyfile db.keyx").expect("parse ok") {
+        Command::Import(args) => {
+            assert_eq!(args.format, ImportFormat::KeePass);
+            assert_eq!(args.keyfile.as_deref(), Some("db.keyx"));
+        },
+        _ => panic!("expected Import variant"),
+    }
+    match Command::from_str("import csv logins.csv --map name=Account,password=Secret").expect("parse ok") {
+        Command::Import(args) => {
+            assert_eq!(args.format, ImportFormat::Csv);
+            assert_eq!(args.columns, "name=Account,password=Secret".parse().unwrap());
+        },
+        _ => panic!("expected Import variant"),
+    }
+    match Command::from_str("import team.clip --on-conflict overwrite").expect("parse ok") {
+        Command::Import(args) => {
+            assert_eq!((args.format, args.path.as_str()), (ImportFormat::Bundle, "team.clip"));
+            assert_eq!(args.strategy, ImportStrategy::Overwrite);
+        },
+        _ => panic!("expected Import variant"),
+    }
+    assert!(Command::from_str("import csv logins.csv --map colour=Blue").is_err());
+    assert!(Command::from_str("import bitwarden export.csv --map name=Title").is_err());
+    assert!(Command::from_str("import bitwarden export.csv --keyfile db.keyx").is_err());
+    assert!(Command::from_str("import bitwarden").is_err());
+    assert!(Command::from_str("import lastpass export.csv").is_err());
+    assert!(Command::from_str("import bitwarden export.csv --on-conflict merge").is_err());
+}
+
+#[test]
+fn parse_export_command() {
+    match Command::from_str("export pass /tmp/store web/*").expect("parse ok") {
+        Command::Export(args) => {
+            assert_eq!(args.format, ExportFormat::Pass);
+            assert_eq!(args.path, "/tmp/store");
+            assert_eq!(args.filters, ["web/*"]);
+        },
+        _ => panic!("expected Export variant"),
+    }
+    match Command::from_str("export csv out.csv --yes web/* --map url=Website").expect("parse ok") {
+        Command::Export(args) => {
+            assert_eq!(args.format, ExportFormat::Csv);
+            assert_eq!(args.filters, ["web/*"]);
+            assert!(args.yes);
+        },
+        _ => panic!("expected Export variant"),
+    }
+    match Command::from_str("export --encrypted --to team.clip github team/*").expect("parse ok") {
+        Command::Export(args) => {
+            assert_eq!((args.format, args.path.as_str()), (ExportFormat::Bundle, "team.clip"));
+            assert_eq!(args.filters, ["github", "team/*"]);
+        },
+        _ => panic!("expected Export variant"),
+    }
+    assert!(Command::from_str("export --encrypted --to team.clip").is_err());
+    assert!(Command::from_str("export --encrypted github").is_err());
+    assert!(Command::from_str("export pass /tmp/store --yes").is_err());
+    assert!(matches!(Command::from_str("import pass ~/.password-store").unwrap(), Command::Import(_)));
+    assert!(Command::from_str("export pass").is_err());
+    assert!(Command::from_str("export keepass out.kdbx").is_err());
+    assert!(Command::from_str("export pass /tmp/store web extra").is_err());
+}
@@ -0,0 +1,46 @@
+use std::time::Duration;
+use clipass::config::Config;
+use clipass::crypto::Cipher;
+
+#[test]
+fn parse_full_config() {
+    let config = Config::parse(r#"
+        vault = "/srv/vault.clip"
+        clipboard_timeout = 45
+        auto_lock = 0
+        backup_count = 5
+        cipher = "xchacha20-poly1305"
+
+        [kdf]
+        memory_cost = 131072
+
+        [generator]
+        length = 32
+        symbols = false
+
+        [passphrase]
+        words = 8
+    "#).unwrap();
+    assert_eq!(config.vault.as_deref(), Some(std::path::Path::new("/srv/vault.clip")));
+    assert_eq!(config.clipboard_timeout(), Duration::from_secs(45));
+    assert_eq!(config.auto_lock(), None);
+    assert_eq!(config.backup_count, 5);
+    assert_eq!(config.cipher, Cipher::XChaCha20Poly1305);
+    assert_eq!(config.kdf.memory_cost, 131072);
+    assert_eq!(config.kdf.time_cost, 3);
+    assert_eq!(config.generator.length, 32);
+    assert!(!config.generator.symbols);
+    assert!(config.generator.digits);
+    assert_eq!(config.passphrase.words, 8);
+}
+
+#[test]
+fn empty_and_invalid_configs() {
+    let config = Config::parse("").unwrap();
+    assert_eq!(config.vault, None);
+    assert_eq!(config.auto_lock(), Some(Duration::from_secs(900)));
+    assert!(Config::parse("unknown_key = 1").is_err());
+    assert!(Config::parse("backup_count = \"three\"").is_err());
+    assert!(Config::parse("cipher = \"des\"").is_err());
+    assert!(Config::parse("[kdf]\nmemory_cost = 0").is_err());
+}
@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use clipass::error::ClipassError;
+use clipass::formats::csv::{self, CsvMapping};
+use clipass::vault::entry::Entry;
+
+const GENERIC: &str = "tests/fixtures/generic.csv";
+
+#[test]
+fn import_finds_the_usual_columns() {
+    let import = csv::read(Path::new(GENERIC), &CsvMapping::default()).expect("read");
+    let ids: Vec<&str> = import.entries.iter().map(|(id, _)| id.as_str()).collect();
+    // A row without a name is named after its url host
+    assert_eq!(ids, ["GitHub", "accounts.example.org", "Bank-Account"]);
+
+    let github = &import.entries[0].1;
+    assert_eq!(github.username(), Some("octocat"));
+    assert_eq!(github.password(), Some("pa,ss\"word"));
+    assert_eq!(github.urls(), ["https://github.com/login"]);
+    assert!(github.otp().is_some());
+    // Other columns become custom fields
+    assert_eq!(github.field("Recovery-Email"), Some("octo@example.com"));
+    assert_eq!(github.custom().len(), 1);
+
+    assert_eq!(import.entries[1].1.notes(), Some("line one\nline two"));
+    assert!(import.entries[2].1.custom().is_empty());
+}
+
+#[test]
+fn import_with_a_column_mapping() {
+    let text = "Account,Site,Login Name,Secret,Folder\nmail,https://mail.example,jane,p4ss,personal\n";
+    let mapping: CsvMapping = "name=Account,url=Site,username=login-name,password=Secret".parse().unwrap();
+    let import = csv::parse(text, &mapping).expect("parse");
+    let (id, mail) = &import.entries[0];
+    assert_eq!(id, "mail");
+    assert_eq!((mail.username(), mail.password()), (Some("jane"), Some("p4ss")));
+    assert_eq!(mail.urls(), ["https://mail.example"]);
+    assert_eq!(mail.field("Folder"), Some("personal"));
+
+    // Without the mapping there is no password column
+    assert!(matches!(csv::parse(text, &CsvMapping::default()), Err(ClipassError::Input(_))));
+    let missing: CsvMapping = "password=Passphrase".parse().unwrap();
+    assert!(matches!(csv::parse(text, &missing), Err(ClipassError::Input(_))));
+    assert!("colour=Site".parse::<CsvMapping>().is_err());
+    assert!("name".parse::<CsvMapping>().is_err());
+}
+
+#[test]
+fn export_quotes_fields_and_reads_back() -> Result<(), ClipassError> {
+    let mut github = Entry::with_password("pa,ss\"word");
+    github.set_field("username", "octocat")?;
+    github.set_field("url", "https://github.com https://gist.github.com")?;
+    github.set_field("notes", "work account\nrotate yearly")?;
+    github.set_field("name", "The Octocat")?;
+    let mut bank = Entry::with_password("p4ss");
+    bank.set_field("pin", "1234")?;
+    let entries = HashMap::from([("web/github".to_string(), github.clone()), ("bank".to_string(), bank.clone())]);
+
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("export.csv");
+    assert_eq!(csv::export(&entries, &path, &CsvMapping::default())?, 2);
+    let content = fs::read_to_string(&path)?;
+    let lines: Vec<&str> = content.split("\r\n").collect();
+    // The custom "name" field cannot take the name column
+    assert_eq!(lines[0], "name,url,username,password,notes,otp,name-2,pin");
+    assert_eq!(lines[1], "bank,,,p4ss,,,,1234");
+    assert!(lines[2].starts_with("web/github,https://github.com https://gist.github.com,octocat,\"pa,ss\"\"word\",\"work account\nrotate"));
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(fs::metadata(&path)?.permissions().mode() & 0o777, 0o600);
+    }
+
+    let import = csv::read(&path, &CsvMapping::default())?;
+    assert_eq!(import.entries[0], ("bank".to_string(), bank));
+    assert_eq!(import.entries[1].1.field("name-2"), Some("The Octocat"));
+
+    // An existing file is never replaced
+    assert!(matches!(csv::export(&entries, &path, &CsvMapping::default()), Err(ClipassError::Input(_))));
+    let mapping: CsvMapping = "name=Title".parse().unwrap();
+    let other = dir.path().join("mapped.csv");
+    csv::export(&entries, &other, &mapping)?;
+    assert!(fs::read_to_string(&other)?.starts_with("Title,url,"));
+    Ok(())
+}
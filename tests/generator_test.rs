@@ -0,0 +1,60 @@
+use clipass::error::ClipassError;
+use clipass::generator::{generate_passphrase, generate_password, wordlist, GenerateArgs, PassphrasePolicy, PasswordPolicy};
+
+#[test]
+fn password_respects_policy() {
+    let policy = PasswordPolicy { length: 4, symbols: false, exclude_ambiguous: true, ..PasswordPolicy::default() };
+    for _ in 0..200 {
+        let password = generate_password(&policy).unwrap();
+        assert_eq!(password.chars().count(), 4);
+        assert!(password.chars().any(|c| c.is_ascii_lowercase()));
+        assert!(password.chars().any(|c| c.is_ascii_uppercase()));
+        assert!(password.chars().any(|c| c.is_ascii_digit()));
+        assert!(password.chars().all(|c| c.is_ascii_alphanumeric()));
+        assert!(!password.contains(['I', 'l', '1', 'O', '0', 'o']));
+    }
+}
+
+#[test]
+fn password_policy_errors() {
+    let too_short = PasswordPolicy { length: 3, ..PasswordPolicy::default() };
+    assert!(generate_password(&too_short).is_err());
+    let no_class = PasswordPolicy { lowercase: false, uppercase: false, digits: false, symbols: false, ..PasswordPolicy::default() };
+    assert!(generate_password(&no_class).is_err());
+    for length in [0, 1025, 10_000_000_000] {
+        let policy = PasswordPolicy { length, require_each_class: false, ..PasswordPolicy::default() };
+        assert!(matches!(generate_password(&policy), Err(ClipassError::Input(_))), "{length}");
+    }
+    for words in [0, 257] {
+        let policy = PassphrasePolicy { words, ..PassphrasePolicy::default() };
+        assert!(matches!(generate_passphrase(&policy), Err(ClipassError::Input(_))), "{words}");
+    }
+    let longest = PasswordPolicy { length: 1024, ..PasswordPolicy::default() };
+    assert_eq!(generate_password(&longest).unwrap().len(), 1024);
+}
+
+#[test]
+fn passphrase_uses_wordlist() {
+    let words = wordlist();
+    assert_eq!(words.len(), 1296);
+    let policy = PassphrasePolicy { words: 5, separator: " ".to_string(), capitalize: false };
+    let passphrase = generate_passphrase(&policy).unwrap();
+    let parts: Vec<&str> = passphrase.split(' ').collect();
+    assert_eq!(parts.len(), 5);
+    assert!(parts.iter().all(|w| words.contains(w)));
+}
+
+#[test]
+fn generate_args_override_defaults() {
+    let args = GenerateArgs::from_args(["-l", "32", "--no-symbols"]).unwrap();
+    let password = args.generate(&PasswordPolicy::default(), &PassphrasePolicy::default()).unwrap();
+    assert_eq!(password.len(), 32);
+    assert!(password.chars().all(|c| c.is_ascii_alphanumeric()));
+
+    let args = GenerateArgs::from_args(["--passphrase", "-w", "3", "--separator", "."]).unwrap();
+    let passphrase = args.generate(&PasswordPolicy::default(), &PassphrasePolicy::default()).unwrap();
+    assert_eq!(passphrase.split('.').count(), 3);
+
+    assert!(GenerateArgs::from_args(["--length"]).is_err());
+    assert!(GenerateArgs::from_args(["--bogus"]).is_err());
+}
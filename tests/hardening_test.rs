@@ -0,0 +1,31 @@
+use clipass::hardening::{harden_process, LockedBytes};
+
+#[test]
+fn locked_bytes_grow_and_truncate() {
+    let mut bytes = LockedBytes::new();
+    assert!(bytes.is_empty());
+    for i in 0..10_000u32 {
+        bytes.extend_from_slice(&i.to_le_bytes());
+    }
+    assert_eq!(bytes.len(), 40_000);
+    assert_eq!(&bytes.as_slice()[4..8], &1u32.to_le_bytes());
+    bytes.truncate(6);
+    assert_eq!(bytes.as_slice(), &[0, 0, 0, 0, 1, 0]);
+}
+
+#[test]
+fn locked_slots_are_reused_wiped() {
+    let small: Vec<LockedBytes> = (0..5000).map(|i| LockedBytes::from_slice(format!("secret-{i}").as_bytes())).collect();
+    assert_eq!(small[4321].as_slice(), b"secret-4321");
+    drop(small);
+    let zeroed = LockedBytes::zeroed(32);
+    assert_eq!(zeroed.as_slice(), &[0u8; 32]);
+}
+
+#[test]
+fn core_dumps_are_disabled() {
+    harden_process();
+    let mut limit = libc::rlimit { rlim_cur: 1, rlim_max: 1 };
+    assert_eq!(unsafe { libc::getrlimit(libc::RLIMIT_CORE, &mut limit) }, 0);
+    assert_eq!(limit.rlim_cur, 0);
+}
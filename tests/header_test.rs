@@ -0,0 +1,116 @@
+use std::fs;
+use tempfile::TempDir;
+use clipass::crypto::KdfParams;
+use clipass::error::ClipassError;
+use clipass::vault::entry::Entry;
+use clipass::vault::key_slot::Credential;
+use clipass::vault::vault::Vault;
+
+const PREFIX_SIZE: usize = 26;
+
+fn saved_vault(dir: &TempDir) -> Result<String, ClipassError> {
+    let kdf = KdfParams { memory_cost: 8192, time_cost: 1, parallelism: 1 };
+    let mut vault = Vault::new_empty_with_kdf("test-pass", kdf)?;
+    vault.new_entry("key", Entry::with_password("secret"))?;
+    let path = dir.path().join("vault.clip").to_string_lossy().to_string();
+    vault.crypt_to_file(&path)?;
+    Ok(path)
+}
+
+fn header_size(data: &[u8]) -> usize {
+    u32::from_le_bytes(data[6..10].try_into().unwrap()) as usize
+}
+
+// Append a record at the end of the header
+fn insert_record(data: &mut Vec<u8>, tag: u16, value: &[u8]) {
+    let end = header_size(data);
+    let mut record = tag.to_le_bytes().to_vec();
+    record.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    record.extend_from_slice(value);
+    data.splice(end..end, record.iter().copied());
+    let size = (end + record.len()) as u32;
+    data[6..10].copy_from_slice(&size.to_le_bytes());
+}
+
+#[test]
+fn v4_header_roundtrip() -> Result<(), ClipassError> {
+    let dir = TempDir::new()?;
+    let path = saved_vault(&dir)?;
+    let data = fs::read(&path)?;
+    assert_eq!(&data[..6], b"CLIP\x04\x00");
+    assert!(header_size(&data) > PREFIX_SIZE);
+
+    let mut vault = Vault::load_from_file("test-pass", &path)?;
+    vault.add_slot(&Credential::password("other-pass"), "other")?;
+    vault.crypt_to_file(&path)?;
+    let vault = Vault::load_from_file("other-pass", &path)?;
+    assert_eq!(vault.slots().len(), 2);
+    assert_eq!(vault.get_value("key", "password")?, "secret");
+    Ok(())
+}
+
+#[test]
+fn v3_vaults_are_upgraded_once_with_a_copy() -> Result<(), ClipassError> {
+    let dir = TempDir::new()?;
+    let path = dir.path().join("vault.clip");
+    let copy = dir.path().join("vault.clip.v3");
+    fs::copy("tests/fixtures/legacy_v3.clip", &path)?;
+    let path = path.to_string_lossy().to_string();
+
+    let mut vault = Vault::load_from_file("test-pass", &path)?;
+    assert_eq!(vault.get_value("email", "password")?, "poney@gmail.com");
+    vault.set_backup_count(0);
+    vault.crypt_to_file(&path)?;
+    assert_eq!(&fs::read(&path)?[4..6], &[4, 0]);
+    assert_eq!(fs::read(&copy)?, fs::read("tests/fixtures/legacy_v3.clip")?);
+
+    vault.update("email", "password", "changed")?;
+    vault.crypt_to_file(&path)?;
+    assert_eq!(fs::read(&copy)?, fs::read("tests/fixtures/legacy_v3.clip")?);
+    assert_eq!(Vault::load_from_file("test-pass", &path)?.get_value("email", "password")?, "changed");
+
+    // Only the 80 bytes v3 header exists, a longer one is refused
+    let mut longer = fs::read("tests/fixtures/legacy_v3.clip")?;
+    longer[6..8].copy_from_slice(&84u16.to_le_bytes());
+    fs::write(&path, &longer)?;
+    assert!(matches!(Vault::load_from_file("test-pass", &path), Err(ClipassError::HeaderError(_))));
+    Ok(())
+}
+
+#[test]
+fn tampered_headers_are_detected() -> Result<(), ClipassError> {
+    let dir = TempDir::new()?;
+    let path = saved_vault(&dir)?;
+    let original = fs::read(&path)?;
+
+    // Parsed fine but authenticated as associated data
+    let mut data = original.clone();
+    data[18] ^= 0x01; // modified at
+    fs::write(&path, &data)?;
+    assert!(matches!(Vault::load_from_file("test-pass", &path), Err(ClipassError::Corrupted(_))));
+
+    let mut data = original.clone();
+    insert_record(&mut data, 0x8001, b"optional");
+    fs::write(&path, &data)?;
+    assert!(matches!(Vault::load_from_file("test-pass", &path), Err(ClipassError::Corrupted(_))));
+
+    // Refused before any decryption
+    let mut data = original.clone();
+    insert_record(&mut data, 0x0042, b"critical");
+    fs::write(&path, &data)?;
+    assert!(matches!(Vault::load_from_file("test-pass", &path), Err(ClipassError::HeaderError(_))));
+
+    let mut data = original.clone();
+    data[6..10].copy_from_slice(&u32::MAX.to_le_bytes());
+    fs::write(&path, &data)?;
+    assert!(matches!(Vault::load_from_file("test-pass", &path), Err(ClipassError::HeaderError(_))));
+
+    let mut data = original.clone();
+    data[PREFIX_SIZE + 2..PREFIX_SIZE + 6].copy_from_slice(&1000u32.to_le_bytes()); // cipher record length
+    fs::write(&path, &data)?;
+    assert!(matches!(Vault::load_from_file("test-pass", &path), Err(ClipassError::HeaderError(_))));
+
+    fs::write(&path, &original)?;
+    assert!(Vault::load_from_file("test-pass", &path).is_ok());
+    Ok(())
+}
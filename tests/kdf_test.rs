@@ -0,0 +1,69 @@
+use std::time::Duration;
+use tempfile::NamedTempFile;
+use clipass::crypto;
+use clipass::crypto::{KdfParams, MIN_MEMORY_COST};
+use clipass::error::ClipassError;
+use clipass::vault::entry::Entry;
+use clipass::vault::key_slot::Credential;
+use clipass::vault::vault::Vault;
+
+#[test]
+fn kdf_params_bounds() {
+    assert!(KdfParams::default().check().is_ok());
+    assert!(KdfParams { memory_cost: 0, ..KdfParams::default() }.check().is_err());
+    assert!(KdfParams { memory_cost: 64 * 1024 * 1024, ..KdfParams::default() }.check().is_err());
+    assert!(KdfParams { time_cost: 0, ..KdfParams::default() }.check().is_err());
+    assert!(KdfParams { parallelism: 1000, ..KdfParams::default() }.check().is_err());
+}
+
+#[test]
+fn calibration_proposes_valid_params() -> Result<(), ClipassError> {
+    // Too short a target falls back to the minimum
+    let (params, _) = crypto::calibrate_kdf(Duration::from_millis(1))?;
+    assert!(params.check().is_ok());
+    assert_eq!(params.memory_cost, MIN_MEMORY_COST);
+    assert_eq!(params.time_cost, 1);
+    Ok(())
+}
+
+#[test]
+fn set_kdf_rekeys_the_current_slot() -> Result<(), ClipassError> {
+    let kdf = KdfParams { memory_cost: 8192, time_cost: 1, parallelism: 1 };
+    let mut vault = Vault::new_empty_with_kdf("test-pass", kdf.clone())?;
+    vault.new_entry("key", Entry::with_password("secret"))?;
+    let stronger = KdfParams { memory_cost: 16384, time_cost: 2, parallelism: 1 };
+    assert!(vault.set_kdf(&Credential::password("wrong-pass"), stronger.clone()).is_err());
+    let absurd = KdfParams { memory_cost: 0, ..kdf };
+    assert!(matches!(vault.set_kdf(&Credential::password("test-pass"), absurd), Err(ClipassError::Input(_))));
+    vault.set_kdf(&Credential::password("test-pass"), stronger)?;
+    let tmp = NamedTempFile::new()?;
+    let path = tmp.path().to_str().unwrap();
+    vault.crypt_to_file(path)?;
+
+    let loaded = Vault::load_from_file("test-pass", path)?;
+    assert_eq!(loaded.slots()[0].kdf().memory_cost, 16384);
+    assert_eq!(loaded.slots()[0].kdf().time_cost, 2);
+    assert_eq!(loaded.get_value("key", "password")?, "secret");
+    Ok(())
+}
+
+#[test]
+fn absurd_header_params_are_rejected() -> Result<(), ClipassError> {
+    let tmp = NamedTempFile::new()?;
+    let path = tmp.path().to_str().unwrap();
+
+    // Memory cost of a legacy vault
+    let mut legacy = std::fs::read("tests/fixtures/legacy_v3.clip")?;
+    legacy[24..28].copy_from_slice(&0u32.to_le_bytes());
+    std::fs::write(path, &legacy)?;
+    assert!(matches!(Vault::load_from_file("test-pass", path), Err(ClipassError::HeaderError(_))));
+
+    // Memory cost of the first key slot, after its "default" label
+    let kdf = KdfParams { memory_cost: 8192, time_cost: 1, parallelism: 1 };
+    Vault::new_empty_with_kdf("test-pass", kdf)?.crypt_to_file(path)?;
+    let mut data = std::fs::read(path)?;
+    data[78..82].copy_from_slice(&u32::MAX.to_le_bytes());
+    std::fs::write(path, &data)?;
+    assert!(matches!(Vault::load_from_file("test-pass", path), Err(ClipassError::HeaderError(_))));
+    Ok(())
+}
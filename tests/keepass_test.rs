@@ -0,0 +1,75 @@
+use std::path::Path;
+use clipass::error::ClipassError;
+use clipass::formats::{keepass, Import};
+use clipass::vault::entry::Entry;
+
+const DATABASE: &str = "tests/fixtures/keepass.kdbx";
+const KEYFILE_DATABASE: &str = "tests/fixtures/keepass_keyfile.kdbx";
+const KEYFILE: &str = "tests/fixtures/keepass.keyx";
+
+fn entry<'a>(import: &'a Import, id: &str) -> &'a Entry {
+    &import.entries.iter().find(|(i, _)| i == id).unwrap_or_else(|| panic!("no entry {id}")).1
+}
+
+#[test]
+fn reads_groups_entries_history_and_attachments() {
+    let import = keepass::read(Path::new(DATABASE), "test-pass", None).expect("read");
+    // The root group and the recycle bin are left out of the ids
+    let ids: Vec<&str> = import.entries.iter().map(|(id, _)| id.as_str()).collect();
+    assert_eq!(ids, ["Bank", "Work/Git-Hub", "Work/Dev/ci"]);
+
+    let bank = entry(&import, "Bank");
+    assert_eq!(bank.username(), Some("jane"));
+    assert_eq!(bank.password(), Some("current-pw"));
+    assert_eq!(bank.urls(), ["https://bank.example"]);
+    assert_eq!(bank.notes(), Some("account & savings"));
+    assert_eq!(bank.field("PIN"), Some("0000"));
+    assert!(bank.otp().is_some());
+    // History first-pw, second-pw, first-pw: newest first, each one once
+    assert_eq!(bank.field("old-password"), Some("first-pw"));
+    assert_eq!(bank.field("old-password-2"), Some("second-pw"));
+    assert_eq!(bank.field("recovery.txt"), Some("codes: 1234 5678"));
+    assert_eq!(bank.custom().len(), 4);
+    assert_eq!(import.warnings, ["Bank: attachment photo.png is not text, not imported"]);
+
+    let github = entry(&import, "Work/Git-Hub");
+    assert_eq!(github.password(), Some("hunter2"));
+    let otp = github.otp().expect("otp from the TimeOtp settings");
+    assert!(otp.uri().contains("period=60"), "{}", otp.uri());
+    assert!(github.custom().is_empty());
+
+    assert_eq!(entry(&import, "Work/Dev/ci").password(), Some("tok"));
+}
+
+#[test]
+fn reads_a_keyfile_only_database() {
+    let import = keepass::read(Path::new(KEYFILE_DATABASE), "", Some(Path::new(KEYFILE))).expect("read");
+    let mail = entry(&import, "mail");
+    assert_eq!(mail.username(), Some("jane@example.com"));
+    assert_eq!(mail.password(), Some("p4ss"));
+
+    let no_keyfile = keepass::read(Path::new(KEYFILE_DATABASE), "", None);
+    assert!(matches!(no_keyfile, Err(ClipassError::WrongPassword)));
+}
+
+#[test]
+fn wrong_password_and_damaged_files_are_refused() {
+    let wrong = keepass::read(Path::new(DATABASE), "wrong-pass", None);
+    assert!(matches!(wrong, Err(ClipassError::WrongPassword)));
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("damaged.kdbx");
+    let data = std::fs::read(DATABASE).unwrap();
+    // Last byte of the first data block
+    let mut damaged = data.clone();
+    let last = damaged.len() - 37;
+    damaged[last] ^= 0x01;
+    std::fs::write(&path, &damaged).unwrap();
+    let error = keepass::read(&path, "test-pass", None).unwrap_err();
+    assert!(error.to_string().contains("corrupted block"), "{error}");
+
+    std::fs::write(&path, &data[..data.len() / 2]).unwrap();
+    assert!(matches!(keepass::read(&path, "test-pass", None), Err(ClipassError::Input(_))));
+    std::fs::write(&path, b"not a keepass file").unwrap();
+    assert!(matches!(keepass::read(&path, "test-pass", None), Err(ClipassError::Input(_))));
+}
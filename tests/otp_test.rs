@@ -0,0 +1,89 @@
+use tempfile::NamedTempFile;
+use clipass::clipass::Clipass;
+use clipass::command::Command;
+use clipass::config::Config;
+use clipass::error::ClipassError;
+use clipass::otp::{base32_encode, Otp, OtpKind};
+use clipass::vault::entry::Entry;
+use clipass::vault::vault::Vault;
+
+// RFC 6238 appendix B, time 59
+#[test]
+fn totp_rfc6238_vectors() -> Result<(), ClipassError> {
+    let cases = [
+        ("SHA1", "12345678901234567890", "94287082"),
+        ("SHA256", "12345678901234567890123456789012", "46119246"),
+        ("SHA512", "1234567890123456789012345678901234567890123456789012345678901234", "90693936"),
+    ];
+    for (algorithm, seed, expected) in cases {
+        let uri = format!(
+            "otpauth://totp/ACME:alice?secret={}&algorithm={algorithm}&digits=8&period=30",
+            base32_encode(seed.as_bytes())
+        );
+        let otp = Otp::parse(&uri)?;
+        assert_eq!(otp.totp(59)?, (expected.to_string(), 1));
+    }
+    Ok(())
+}
+
+// RFC 4226 appendix D
+#[test]
+fn hotp_rfc4226_vectors() -> Result<(), ClipassError> {
+    let mut otp = Otp::parse("otpauth://hotp/test?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&counter=0")?;
+    for expected in ["755224", "287082", "359152", "969429"] {
+        assert_eq!(otp.next_hotp()?, expected);
+    }
+    assert_eq!(otp.kind(), OtpKind::Hotp { counter: 4 });
+    assert!(otp.totp(59).is_err());
+
+    // The last counter gives no code rather than wrapping to old ones
+    let mut last = Otp::parse("otpauth://hotp/test?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&counter=18446744073709551615")?;
+    assert!(matches!(last.next_hotp(), Err(ClipassError::Input(_))));
+    assert_eq!(last.kind(), OtpKind::Hotp { counter: u64::MAX });
+    Ok(())
+}
+
+#[test]
+fn bare_base32_secret_is_a_default_totp() -> Result<(), ClipassError> {
+    let otp = Otp::parse("gezd gnbv gy3t qojq gezd gnbv gy3t qojq")?;
+    assert_eq!(otp.kind(), OtpKind::Totp { period: 30 });
+    assert_eq!(otp.totp(59)?, ("287082".to_string(), 1));
+    assert!(Otp::parse("not base32!").is_err());
+    Ok(())
+}
+
+#[test]
+fn hotp_counter_is_persisted() -> Result<(), ClipassError> {
+    let mut entry = Entry::new();
+    entry.set_field("otp", "otpauth://hotp/test?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&counter=0")?;
+    let mut vault = Vault::new_empty("test-pass")?;
+    vault.new_entry("bank", entry)?;
+    assert_eq!(vault.hotp("bank")?, "755224");
+
+    let tmp = NamedTempFile::new()?;
+    let path = tmp.path().to_str().unwrap();
+    vault.crypt_to_file(path)?;
+
+    let mut loaded = Vault::load_from_file("test-pass", path)?;
+    assert_eq!(loaded.hotp("bank")?, "287082");
+    Ok(())
+}
+
+#[test]
+fn hotp_session_saves_the_counter_at_once() -> Result<(), ClipassError> {
+    let mut entry = Entry::new();
+    entry.set_field("otp", "otpauth://hotp/test?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&counter=0")?;
+    let mut vault = Vault::new_empty("test-pass")?;
+    vault.new_entry("bank", entry)?;
+    let tmp = NamedTempFile::new()?;
+    let path = tmp.path().to_str().unwrap();
+    vault.crypt_to_file(path)?;
+
+    // As in the interactive mode, without save or quit
+    let mut session = Clipass::with_vault(Vault::load_from_file("test-pass", path)?, path, &Config::default());
+    assert_eq!(session.run(Command::Hotp("bank".to_string()))?, "755224");
+    drop(session);
+    let mut loaded = Vault::load_from_file("test-pass", path)?;
+    assert_eq!(loaded.hotp("bank")?, "287082");
+    Ok(())
+}
@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use clipass::error::ClipassError;
+use clipass::formats::pass::{self, Plaintext};
+use clipass::vault::entry::Entry;
+
+const STORE: &str = "tests/fixtures/password-store";
+
+#[test]
+fn import_reads_password_fields_and_notes() {
+    let import = pass::read(Path::new(STORE), &Plaintext).expect("read");
+    let ids: Vec<&str> = import.entries.iter().map(|(id, _)| id.as_str()).collect();
+    assert_eq!(ids, ["email/jane", "web/dev/ci", "web/git-hub"]);
+
+    let jane = &import.entries[0].1;
+    assert_eq!(jane.password(), Some("s3cret"));
+    assert_eq!(jane.username(), Some("jane@example.com"));
+    assert_eq!(jane.urls(), ["https://mail.example", "https://webmail.example"]);
+    assert!(jane.otp().is_some());
+    assert_eq!(jane.field("security-question"), Some("first pet"));
+    assert_eq!(jane.notes(), Some("second factor on the phone\nspare codes in the drawer"));
+
+    assert_eq!(import.entries[1].1.password(), Some("tok3n"));
+    assert_eq!(import.entries[2].1.username(), Some("octocat"));
+}
+
+#[test]
+fn export_writes_the_store_layout_back() -> Result<(), ClipassError> {
+    let mut entries = HashMap::new();
+    let mut github = Entry::with_password("hunter2");
+    github.set_field("username", "octocat")?;
+    github.set_field("url", "https://github.com https://gist.github.com")?;
+    github.set_field("otp", "JBSWY3DPEHPK3PXP")?;
+    github.set_field("pin", "1234")?;
+    github.set_field("notes", "work account\nrotate yearly")?;
+    entries.insert("web/github.com".to_string(), github.clone());
+    entries.insert("mail".to_string(), Entry::with_password("p4ss"));
+
+    let store = tempfile::tempdir()?;
+    let report = pass::export(&entries, store.path(), &Plaintext)?;
+    assert_eq!(report.written, ["mail", "web/github.com"]);
+    let content = fs::read_to_string(store.path().join("web/github.com.txt"))?;
+    assert!(content.starts_with("hunter2\nlogin: octocat\nurl: https://github.com\nurl: https://gist.github.com\notpauth://"));
+    assert!(content.ends_with("pin: 1234\nwork account\nrotate yearly\n"));
+
+    // Read back unchanged, existing files are left alone
+    let import = pass::read(store.path(), &Plaintext)?;
+    assert_eq!(import.entries[1], ("web/github.com".to_string(), github));
+    entries.insert("mail".to_string(), Entry::with_password("changed"));
+    let report = pass::export(&entries, store.path(), &Plaintext)?;
+    assert_eq!(report.existing, ["mail", "web/github.com"]);
+    assert_eq!(fs::read_to_string(store.path().join("mail.txt"))?, "p4ss\n");
+    Ok(())
+}
+
+#[test]
+fn export_refuses_ids_leaving_the_store() {
+    let store = tempfile::tempdir().unwrap();
+    for id in ["../escape", "/etc/passwd", "web/../../escape", "web/", "web/.hidden"] {
+        let entries = HashMap::from([(id.to_string(), Entry::with_password("x"))]);
+        assert!(matches!(pass::export(&entries, store.path(), &Plaintext), Err(ClipassError::Input(_))), "{id}");
+    }
+    assert!(fs::read_dir(store.path()).unwrap().next().is_none());
+}
+
+#[test]
+fn export_leaves_out_multi_line_fields() -> Result<(), ClipassError> {
+    let mut server = Entry::with_password("hunter2");
+    server.set_field("username", "root")?;
+    server.set_field("ssh-key", "-----BEGIN KEY-----\nAAAA\n-----END KEY-----")?;
+    server.set_field("port", "22")?;
+    server.set_field("notes", "rack 4")?;
+    let entries = HashMap::from([
+        ("server".to_string(), server.clone()),
+        ("broken".to_string(), Entry::with_password("first\nsecond")),
+    ]);
+
+    let store = tempfile::tempdir()?;
+    let report = pass::export(&entries, store.path(), &Plaintext)?;
+    assert_eq!(report.written, ["server"]);
+    assert_eq!(report.warnings, [
+        "broken: password spans several lines, entry not exported",
+        "server: field ssh-key spans several lines, not exported",
+    ]);
+    assert!(!store.path().join("broken.txt").exists());
+
+    // Everything else reads back as it was, the key does not leak into the notes
+    let import = pass::read(store.path(), &Plaintext)?;
+    let read = &import.entries[0].1;
+    assert_eq!(read.field("ssh-key"), None);
+    assert_eq!(read.notes(), Some("rack 4"));
+    server.set_field("ssh-key", "")?;
+    assert_eq!(read, &server);
+    Ok(())
+}
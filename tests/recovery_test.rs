@@ -0,0 +1,50 @@
+use tempfile::NamedTempFile;
+use clipass::crypto::KdfParams;
+use clipass::error::ClipassError;
+use clipass::vault::entry::Entry;
+use clipass::vault::key_slot::{Credential, SlotKind};
+use clipass::vault::recovery;
+use clipass::vault::vault::Vault;
+
+#[test]
+fn recovery_code_format_and_checksum() {
+    let code = recovery::generate();
+    let groups: Vec<&str> = code.expose().split('-').collect();
+    assert_eq!(groups.len(), 9);
+    assert!(groups.iter().all(|g| g.len() == 4));
+
+    let typed = code.expose().to_lowercase().replace('-', " ");
+    assert_eq!(recovery::normalize(&typed).unwrap().expose(), &code.expose().replace('-', "")[..32]);
+
+    // One wrong character is caught by the checksum
+    let mut typo: Vec<char> = code.expose().chars().collect();
+    typo[0] = if typo[0] == 'A' { 'B' } else { 'A' };
+    assert!(recovery::normalize(&typo.into_iter().collect::<String>()).is_err());
+    assert!(recovery::normalize("ABCD-EFGH").is_err());
+}
+
+#[test]
+fn recovery_code_unlocks_and_resets_password() -> Result<(), ClipassError> {
+    let kdf = KdfParams { memory_cost: 8192, time_cost: 1, parallelism: 1 };
+    let mut vault = Vault::new_empty_with_kdf("forgotten", kdf)?;
+    vault.new_entry("key", Entry::with_password("secret"))?;
+    let first = vault.new_recovery_code()?;
+    let code = vault.new_recovery_code()?;
+    assert_eq!(vault.slots().iter().filter(|s| s.kind() == SlotKind::Recovery).count(), 1);
+    let tmp = NamedTempFile::new()?;
+    let path = tmp.path().to_str().unwrap();
+    vault.crypt_to_file(path)?;
+
+    let first = recovery::normalize(first.expose())?;
+    assert!(Vault::load_with_credential(&Credential::Recovery(first.expose()), path).is_err());
+    let code = recovery::normalize(code.expose())?;
+    let mut recovered = Vault::load_with_credential(&Credential::Recovery(code.expose()), path)?;
+    assert_eq!(recovered.get_value("key", "password")?, "secret");
+
+    recovered.reset_password(0, "remembered")?;
+    assert!(recovered.reset_password(1, "remembered").is_err());
+    recovered.crypt_to_file(path)?;
+    assert!(Vault::load_from_file("forgotten", path).is_err());
+    assert!(Vault::load_from_file("remembered", path).is_ok());
+    Ok(())
+}
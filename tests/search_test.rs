@@ -0,0 +1,48 @@
+use std::collections::HashMap;
+use clipass::search::{find, fuzzy_score, glob_match, Filter};
+use clipass::vault::entry::Entry;
+
+#[test]
+fn glob_matching() {
+    assert!(glob_match("git*", "github"));
+    assert!(glob_match("*hub", "github"));
+    assert!(glob_match("g?t*b", "github"));
+    assert!(glob_match("mail/[a-c]*", "mail/bob"));
+    assert!(!glob_match("mail/[!a-c]*", "mail/bob"));
+    assert!(!glob_match("git", "github"));
+    assert!(glob_match("*a*a*", "banana"));
+    // An unclosed class is a literal `[`
+    assert!(glob_match("key[1", "key[1"));
+    assert!(glob_match("key[*", "key[12"));
+    assert!(!glob_match("key[1", "key1"));
+}
+
+#[test]
+fn filter_kinds() {
+    assert!(Filter::parse("HUB").unwrap().matches("github"));
+    assert!(Filter::parse("git*").unwrap().matches("GitHub"));
+    assert!(Filter::parse("/^mail/(work|home)$/").unwrap().matches("mail/work"));
+    assert!(!Filter::parse("/^mail/(work|home)$/").unwrap().matches("mail/other"));
+    assert!(Filter::parse("/(/").is_err());
+}
+
+#[test]
+fn fuzzy_ranking() {
+    assert!(fuzzy_score("gh", "github").is_some());
+    assert!(fuzzy_score("xyz", "github").is_none());
+    assert!(fuzzy_score("git", "github") > fuzzy_score("git", "my-great-item"));
+
+    let mut entries = HashMap::new();
+    entries.insert("github".to_string(), Entry::new());
+    entries.insert("gitlab".to_string(), Entry::new());
+    let mut bank = Entry::new();
+    bank.set_field("url", "https://github.example").unwrap();
+    entries.insert("bank".to_string(), bank);
+
+    let found = find(&entries, "gthb");
+    let ids: Vec<&str> = found.iter().map(|m| m.id).collect();
+    assert_eq!(ids[0], "github");
+    assert!(ids.contains(&"bank"));
+    assert!(!ids.contains(&"gitlab"));
+    assert_eq!(found.iter().find(|m| m.id == "bank").unwrap().field, "url");
+}
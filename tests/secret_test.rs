@@ -0,0 +1,32 @@
+use std::io::Write;
+use clipass::secret::{SecretBuffer, SecretString};
+use clipass::vault::entry::Entry;
+
+#[test]
+fn secret_string_is_redacted() {
+    let secret = SecretString::from("hunter2");
+    assert_eq!(secret.expose(), "hunter2");
+    assert!(!format!("{secret:?}").contains("hunter2"));
+
+    let mut entry = Entry::with_password("hunter2");
+    entry.set_field("otp", "JBSWY3DPEHPK3PXP").unwrap();
+    let debug = format!("{entry:?}");
+    assert!(!debug.contains("hunter2"));
+    assert!(!debug.contains("JBSWY3DPEHPK3PXP"));
+}
+
+#[test]
+fn secret_string_serializes_as_plain_string() {
+    let json = serde_json::to_string(&SecretString::from("hunter2")).unwrap();
+    assert_eq!(json, "\"hunter2\"");
+    let secret: SecretString = serde_json::from_str(&json).unwrap();
+    assert_eq!(secret.expose(), "hunter2");
+}
+
+#[test]
+fn secret_buffer_grows_past_capacity() {
+    let mut buffer = SecretBuffer::with_capacity(4);
+    buffer.write_all(b"abc").unwrap();
+    buffer.write_all(b"defghij").unwrap();
+    assert_eq!(buffer.into_inner().as_slice(), b"abcdefghij");
+}
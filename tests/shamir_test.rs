@@ -0,0 +1,88 @@
+use tempfile::NamedTempFile;
+use clipass::crypto::KdfParams;
+use clipass::error::ClipassError;
+use clipass::vault::entry::Entry;
+use clipass::vault::key_slot::Credential;
+use clipass::vault::shamir;
+use clipass::vault::shamir::Share;
+use clipass::vault::vault::Vault;
+
+fn shared_vault(path: &str) -> Result<Vec<Share>, ClipassError> {
+    let kdf = KdfParams { memory_cost: 8192, time_cost: 1, parallelism: 1 };
+    let mut vault = Vault::new_empty_with_kdf("break-glass", kdf)?;
+    vault.new_entry("root", Entry::with_password("secret"))?;
+    vault.crypt_to_file(path)?;
+    vault.split_key(3, 5)
+}
+
+#[test]
+fn any_threshold_shares_unlock_the_vault() -> Result<(), ClipassError> {
+    let tmp = NamedTempFile::new()?;
+    let path = tmp.path().to_str().unwrap();
+    let shares = shared_vault(path)?;
+    assert_eq!(shares.len(), 5);
+
+    // Through their printed form, in any order
+    let typed: Vec<Share> = [4, 1, 2].iter()
+        .map(|&i| Share::parse(&shares[i].encode().expose().to_lowercase()))
+        .collect::<Result<_, _>>()?;
+    let key = shamir::combine(&typed)?;
+    let vault = Vault::load_with_credential(&Credential::DataKey(&key), path)?;
+    assert_eq!(vault.get_value("root", "password")?, "secret");
+    assert!(vault.verify(&Credential::DataKey(&key))?);
+
+    let other = shamir::combine(&shares[2..])?;
+    assert!(other.matches(&key));
+    Ok(())
+}
+
+#[test]
+fn too_few_or_mixed_shares_are_refused() -> Result<(), ClipassError> {
+    let tmp = NamedTempFile::new()?;
+    let path = tmp.path().to_str().unwrap();
+    let shares = shared_vault(path)?;
+
+    assert!(shamir::combine(&shares[..2]).is_err());
+    let twice = [Share::parse(shares[0].encode().expose())?, Share::parse(shares[0].encode().expose())?,
+                 Share::parse(shares[1].encode().expose())?];
+    assert!(shamir::combine(&twice).is_err());
+
+    let other_split = shared_vault(path)?;
+    let mixed = [Share::parse(shares[0].encode().expose())?, Share::parse(shares[1].encode().expose())?,
+                 Share::parse(other_split[2].encode().expose())?];
+    assert!(shamir::combine(&mixed).is_err());
+
+    let mut typo: Vec<char> = shares[0].encode().expose().chars().collect();
+    typo[0] = if typo[0] == 'A' { 'B' } else { 'A' };
+    assert!(Share::parse(&typo.into_iter().collect::<String>()).is_err());
+    Ok(())
+}
+
+#[test]
+fn split_parameters_are_checked() -> Result<(), ClipassError> {
+    let kdf = KdfParams { memory_cost: 8192, time_cost: 1, parallelism: 1 };
+    let vault = Vault::new_empty_with_kdf("break-glass", kdf)?;
+    assert!(vault.split_key(1, 5).is_err());
+    assert!(vault.split_key(4, 3).is_err());
+    assert_eq!(vault.split_key(255, 255)?.len(), 255);
+    Ok(())
+}
+
+#[test]
+fn shares_let_a_password_slot_be_added() -> Result<(), ClipassError> {
+    let tmp = NamedTempFile::new()?;
+    let path = tmp.path().to_str().unwrap();
+    let shares = shared_vault(path)?;
+
+    let key = shamir::combine(&shares[..3])?;
+    let mut vault = Vault::load_with_credential(&Credential::DataKey(&key), path)?;
+    vault.add_slot(&Credential::password("new-pass"), "after shares")?;
+    vault.reset_password(0, "replaced")?;
+    vault.crypt_to_file(path)?;
+
+    let reopened = Vault::load_from_file("new-pass", path)?;
+    assert_eq!(reopened.get_value("root", "password")?, "secret");
+    assert!(Vault::load_from_file("replaced", path).is_ok());
+    assert!(matches!(Vault::load_from_file("break-glass", path), Err(ClipassError::WrongPassword)));
+    Ok(())
+}
@@ -1,48 +1,59 @@
 use tempfile::NamedTempFile;
+use clipass::crypto::{Cipher, KdfParams, Keyfile};
 use clipass::error::ClipassError;
+use clipass::vault::entry::Entry;
+use clipass::vault::key_slot::Credential;
 use clipass::vault::vault::Vault;
 
 #[test]
 fn vault_encrypt_decrypt_roundtrip() -> Result<(), ClipassError> {
     let mut vault = Vault::new_empty("test-pass")?;
-    vault.new_entry("email", "poney@gmail.com")?;
+    vault.new_entry("email", Entry::with_password("poney@gmail.com"))?;
     let tmp = NamedTempFile::new()?;
     let path = tmp.path().to_str().unwrap();
     vault.crypt_to_file(path)?;
 
     let loaded = Vault::load_from_file("test-pass", path)?;
-    let value = loaded.get_value("email")?;
+    let value = loaded.get_value("email", "password")?;
     assert_eq!(value, "poney@gmail.com");
     Ok(())
 }
 #[test]
 fn vault_wrong_password_fails() -> Result<(), ClipassError> {
     let mut vault = Vault::new_empty("correct-password")?;
-    vault.new_entry("key", "secret")?;
+    vault.new_entry("key", Entry::with_password("secret"))?;
 
     let tmp = NamedTempFile::new()?;
     let path = tmp.path().to_str().unwrap();
     vault.crypt_to_file(path)?;
 
-    // Attempt to load with wrong password — expect an Err (CryptoError)
+    // Attempt to load with wrong password — expect an Err (WrongPassword)
     let res = Vault::load_from_file("wrong-password", path);
     assert!(res.is_err());
     // optionally inspect variant
     match res {
         Err(e) => match e {
-            ClipassError::CryptoError(_) => {}
-            _ => panic!("expected CryptoError on wrong password, got {:?}", e),
+            ClipassError::WrongPassword => {}
+            _ => panic!("expected WrongPassword on wrong password, got {:?}", e),
         },
         Ok(_) => panic!("expected error for wrong password"),
     }
+
+    // A damaged file is told apart from a wrong password
+    let mut data = std::fs::read(path)?;
+    let last = data.len() - 1;
+    data[last] ^= 0x01;
+    std::fs::write(path, &data)?;
+    assert!(matches!(Vault::load_from_file("correct-password", path), Err(ClipassError::Corrupted(_))));
+    assert!(matches!(Vault::load_from_file("wrong-password", path), Err(ClipassError::WrongPassword)));
     Ok(())
 }
 
 #[test]
 fn new_entry_duplicate_returns_error() -> Result<(), ClipassError> {
     let mut vault = Vault::new_empty("test-pass")?;
-    vault.new_entry("dup", "one").expect("first insert ok");
-    let second = vault.new_entry("dup", "two");
+    vault.new_entry("dup", Entry::with_password("one")).expect("first insert ok");
+    let second = vault.new_entry("dup", Entry::with_password("two"));
     assert!(second.is_err());
     match second {
         Err(clipass::error::ClipassError::IdExists(id)) => {
@@ -52,4 +63,204 @@ fn new_entry_duplicate_returns_error() -> Result<(), ClipassError> {
         Err(e) => panic!("expected IdExists, got {:?}", e),
         Ok(_) => panic!("expected error"),
     }
-}
\ No newline at end of file
+}
+#[test]
+fn entry_fields_roundtrip() -> Result<(), ClipassError> {
+    let mut entry = Entry::new();
+    entry.set_field("username", "poney")?;
+    entry.set_field("password", "hunter2")?;
+    entry.set_field("url", "https://a.example https://b.example")?;
+    entry.set_field("pin", "1234")?;
+
+    let mut vault = Vault::new_empty("test-pass")?;
+    vault.new_entry("bank", entry)?;
+    vault.update("bank", "notes", "call before noon")?;
+    vault.update("bank", "pin", "")?;
+
+    let tmp = NamedTempFile::new()?;
+    let path = tmp.path().to_str().unwrap();
+    vault.crypt_to_file(path)?;
+
+    let loaded = Vault::load_from_file("test-pass", path)?;
+    let entry = loaded.get_entry("bank")?;
+    assert_eq!(entry.username(), Some("poney"));
+    assert_eq!(entry.urls(), ["https://a.example", "https://b.example"]);
+    assert_eq!(loaded.get_value("bank", "notes")?, "call before noon");
+    assert!(matches!(loaded.get_value("bank", "pin"), Err(ClipassError::FieldNotFound(_, _))));
+    Ok(())
+}
+
+#[test]
+fn legacy_string_values_load_as_password_entries() -> Result<(), ClipassError> {
+    // written by clipass 0.3.0-alpha, when entries were bare strings
+    let loaded = Vault::load_from_file("test-pass", "tests/fixtures/legacy_v3.clip")?;
+    let entry = loaded.get_entry("email")?;
+    assert_eq!(entry, &Entry::with_password("poney@gmail.com"));
+    Ok(())
+}
+
+#[test]
+fn change_master_password_rekeys_vault() -> Result<(), ClipassError> {
+    let mut vault = Vault::new_empty("old-pass")?;
+    vault.new_entry("key", Entry::with_password("secret"))?;
+    assert!(matches!(vault.change_master_password("not-it", "new-pass"), Err(ClipassError::WrongPassword)));
+    vault.change_master_password("old-pass", "new-pass")?;
+
+    let tmp = NamedTempFile::new()?;
+    let path = tmp.path().to_str().unwrap();
+    vault.crypt_to_file(path)?;
+
+    assert!(Vault::load_from_file("old-pass", path).is_err());
+    let loaded = Vault::load_from_file("new-pass", path)?;
+    assert_eq!(loaded.get_value("key", "password")?, "secret");
+    Ok(())
+}
+
+#[test]
+fn keyfile_is_required_once_set() -> Result<(), ClipassError> {
+    let kdf = KdfParams { memory_cost: 8192, time_cost: 1, parallelism: 1 };
+    let keyfile = Keyfile::from_bytes(b"keyfile content");
+    let mut vault = Vault::create(&Credential::with_keyfile("test-pass", Some(&keyfile)), kdf)?;
+    vault.new_entry("key", Entry::with_password("secret"))?;
+    let tmp = NamedTempFile::new()?;
+    let path = tmp.path().to_str().unwrap();
+    vault.crypt_to_file(path)?;
+
+    assert!(matches!(Vault::load_from_file("test-pass", path), Err(ClipassError::KeyfileRequired)));
+    let other = Keyfile::from_bytes(b"other");
+    assert!(Vault::load_with_credential(&Credential::with_keyfile("test-pass", Some(&other)), path).is_err());
+    let credential = Credential::with_keyfile("test-pass", Some(&keyfile));
+    let mut loaded = Vault::load_with_credential(&credential, path)?;
+    assert!(loaded.requires_keyfile());
+    assert!(loaded.verify(&credential)?);
+
+    loaded.set_keyfile(&credential, None)?;
+    loaded.crypt_to_file(path)?;
+    let loaded = Vault::load_from_file("test-pass", path)?;
+    assert!(!loaded.requires_keyfile());
+    assert_eq!(loaded.get_value("key", "password")?, "secret");
+    Ok(())
+}
+
+#[test]
+fn key_slots_open_the_same_vault() -> Result<(), ClipassError> {
+    let kdf = KdfParams { memory_cost: 8192, time_cost: 1, parallelism: 1 };
+    let mut vault = Vault::new_empty_with_kdf("alice-pass", kdf)?;
+    vault.new_entry("db", Entry::with_password("secret"))?;
+    vault.add_slot(&Credential::password("bob-pass"), "bob")?;
+    let tmp = NamedTempFile::new()?;
+    let path = tmp.path().to_str().unwrap();
+    vault.crypt_to_file(path)?;
+
+    let mut loaded = Vault::load_from_file("bob-pass", path)?;
+    assert_eq!(loaded.get_value("db", "password")?, "secret");
+    assert_eq!(loaded.slots().len(), 2);
+    assert_eq!(loaded.slots()[1].label(), "bob");
+
+    loaded.revoke_slot(0)?;
+    assert!(loaded.revoke_slot(0).is_err());
+    loaded.crypt_to_file(path)?;
+    assert!(Vault::load_from_file("alice-pass", path).is_err());
+    assert!(Vault::load_from_file("bob-pass", path).is_ok());
+    Ok(())
+}
+
+#[test]
+fn legacy_vault_gets_a_password_slot() -> Result<(), ClipassError> {
+    let tmp = NamedTempFile::new()?;
+    let path = tmp.path().to_str().unwrap();
+    std::fs::copy("tests/fixtures/legacy_v3.clip", path)?;
+
+    let vault = Vault::load_from_file("test-pass", path)?;
+    assert_eq!(vault.slots().len(), 1);
+    assert_eq!(vault.cipher(), Cipher::Aes256Gcm);
+    vault.crypt_to_file(path)?;
+    let loaded = Vault::load_from_file("test-pass", path)?;
+    assert_eq!(loaded.get_value("email", "password")?, "poney@gmail.com");
+    assert!(Vault::load_from_file("wrong-pass", path).is_err());
+    Ok(())
+}
+
+#[test]
+fn vault_switches_cipher() -> Result<(), ClipassError> {
+    let kdf = KdfParams { memory_cost: 8192, time_cost: 1, parallelism: 1 };
+    let mut vault = Vault::new_empty_with_kdf("test-pass", kdf)?;
+    vault.new_entry("key", Entry::with_password("secret"))?;
+    vault.set_cipher(Cipher::XChaCha20Poly1305);
+    let tmp = NamedTempFile::new()?;
+    let path = tmp.path().to_str().unwrap();
+    vault.crypt_to_file(path)?;
+
+    let mut loaded = Vault::load_from_file("test-pass", path)?;
+    assert_eq!(loaded.cipher(), Cipher::XChaCha20Poly1305);
+    assert_eq!(loaded.get_value("key", "password")?, "secret");
+
+    // A tampered cipher id is refused
+    let mut data = std::fs::read(path)?;
+    assert_eq!(data[32], Cipher::XChaCha20Poly1305.id());
+    data[32] = Cipher::Aes256Gcm.id();
+    std::fs::write(path, &data)?;
+    assert!(Vault::load_from_file("test-pass", path).is_err());
+
+    loaded.set_cipher(Cipher::Aes256Gcm);
+    loaded.crypt_to_file(path)?;
+    let loaded = Vault::load_from_file("test-pass", path)?;
+    assert_eq!(loaded.cipher(), Cipher::Aes256Gcm);
+    assert_eq!(loaded.get_value("key", "password")?, "secret");
+    Ok(())
+}
+
+#[test]
+fn inspect_reads_the_header_without_password() -> Result<(), ClipassError> {
+    let kdf = KdfParams { memory_cost: 8192, time_cost: 1, parallelism: 1 };
+    let mut vault = Vault::new_empty_with_kdf("test-pass", kdf)?;
+    vault.add_slot(&Credential::password("other-pass"), "other")?;
+    let tmp = NamedTempFile::new()?;
+    let path = tmp.path().to_str().unwrap();
+    vault.crypt_to_file(path)?;
+
+    let info = Vault::inspect(path)?;
+    assert_eq!(info.version, 4);
+    assert_eq!(info.cipher, Cipher::Aes256Gcm);
+    assert_eq!(info.file_size, std::fs::metadata(path)?.len() as usize);
+    assert_eq!(info.slots.len(), 2);
+    assert_eq!(info.slots[1].label(), "other");
+    assert!(info.legacy_kdf.is_none());
+
+    let legacy = Vault::inspect("tests/fixtures/legacy_v3.clip")?;
+    assert_eq!(legacy.version, 3);
+    assert_eq!(legacy.legacy_kdf.map(|kdf| kdf.memory_cost), Some(65536));
+    assert!(legacy.slots.is_empty());
+    Ok(())
+}
+
+#[test]
+fn locked_vault_unlocks_from_its_file_or_its_unsaved_changes() -> Result<(), ClipassError> {
+    let kdf = KdfParams { memory_cost: 8192, time_cost: 1, parallelism: 1 };
+    let mut vault = Vault::new_empty_with_kdf("test-pass", kdf)?;
+    vault.new_entry("github", Entry::with_password("saved"))?;
+    let tmp = NamedTempFile::new()?;
+    let path = tmp.path().to_str().unwrap();
+    vault.crypt_to_file(path)?;
+    vault.mark_saved();
+
+    // Nothing unsaved: read again from the file, which another session changed
+    let locked = vault.lock()?;
+    assert!(locked.from_file());
+    let mut other = Vault::load_from_file("test-pass", path)?;
+    other.new_entry("gitlab", Entry::with_password("other"))?;
+    other.crypt_to_file(path)?;
+    let mut vault = locked.unlock(&Credential::password("test-pass"), path)?;
+    assert!(vault.contains_key("gitlab"));
+
+    // Unsaved changes are kept, still to be saved
+    vault.delete_entry("github")?;
+    let locked = vault.lock()?;
+    drop(vault);
+    assert!(!locked.from_file());
+    assert!(matches!(locked.unlock(&Credential::password("wrong-pass"), path), Err(ClipassError::WrongPassword)));
+    let vault = locked.unlock(&Credential::password("test-pass"), path)?;
+    assert!(!vault.contains_key("github"));
+    assert!(vault.is_updated());
+    Ok(())
+}